@@ -2,14 +2,20 @@ use bitvec::prelude::*;
 use ordered_float::OrderedFloat;
 use plotters::prelude::*;
 use rayon::prelude::*;
-use rusqlite::{params, Connection, OpenFlags};
-use std::collections::{HashMap, HashSet};
+use rusqlite::params;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{self};
 use std::io::BufRead;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::remover::{FunctionKind, FunctionRange, Remover};
+use crate::remover::{FileFunctionRanges, FunctionKind, Remover};
+
+/// Smallest benchmark per function name, and the token count of each of these benchmarks
+type MinBenches = (HashMap<String, PathBuf>, HashMap<String, usize>);
+/// Conditions per file that never varied, as (line, decision, condition, outcome, benchmarks)
+type ConstantConditions = BTreeMap<String, Vec<(usize, usize, usize, bool, usize)>>;
 use crate::remover_config::Config;
+use crate::schema;
 
 const DEBUG: bool = false;
 
@@ -31,10 +37,7 @@ impl Analyzer {
         }
     }
 
-    pub fn get_functions(
-        &mut self,
-    ) -> Result<Vec<(PathBuf, Vec<(FunctionRange, FunctionRange)>)>, Box<dyn std::error::Error>>
-    {
+    pub fn get_functions(&mut self) -> Result<FileFunctionRanges, Box<dyn std::error::Error>> {
         let file_map = self.remover.get_rarely_used_functions(FunctionKind::All)?;
         let function_ranges = self.remover.find_function_ranges(file_map)?;
 
@@ -58,7 +61,7 @@ impl Analyzer {
             working_benchmarks.len()
         );
         for bench_path in working_benchmarks.iter() {
-            println!("{}", bench_path.display().to_string());
+            println!("{}", bench_path.display());
         }
 
         Ok(())
@@ -85,11 +88,7 @@ impl Analyzer {
             fuid_bench_map.len()
         );
         for (fuid, bench) in fuid_bench_map.iter() {
-            println!(
-                "\tFunction '{}' needs '{}'",
-                fuid,
-                bench.display().to_string()
-            );
+            println!("\tFunction '{}' needs '{}'", fuid, bench.display());
         }
 
         let bench_set: HashSet<&PathBuf> = HashSet::from_iter(fuid_bench_map.values());
@@ -99,7 +98,7 @@ impl Analyzer {
             bench_set.len()
         );
         for bench in bench_set {
-            println!("\t{}", bench.display().to_string());
+            println!("\t{}", bench.display());
         }
 
         if let Some(top_x) = top_x {
@@ -109,7 +108,7 @@ impl Analyzer {
             );
             // Sort the hashmap by occurence
             let mut count_vec: Vec<(String, usize)> = token_counts.into_iter().collect();
-            count_vec.sort_by(|a, b| b.1.cmp(&a.1));
+            count_vec.sort_by_key(|b| std::cmp::Reverse(b.1));
 
             for (token, count) in count_vec.iter().take(top_x) {
                 println!("\t{}\t{}", count, token);
@@ -119,6 +118,100 @@ impl Analyzer {
         Ok(())
     }
 
+    pub fn analyze_block_coverage(
+        &mut self,
+        max_coverage: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !(0.0..=1.0).contains(&max_coverage) {
+            return Err(Box::from("Expected a coverage value in range [0,1]"));
+        }
+
+        let functions = self.remover.get_block_coverage(max_coverage)?;
+        println!(
+            "Used functions executing at most {:.1}% of their blocks (Total: {}):",
+            max_coverage * 100.0,
+            functions.len()
+        );
+        for function in functions {
+            let union = function
+                .union_executed
+                .map_or("-".to_string(), |u| u.to_string());
+            println!(
+                "\t{:.1}%\t{}/{} (max {}, union {})\t{}:{} {}",
+                function.coverage() * 100.0,
+                function.union_executed.unwrap_or(function.max_executed),
+                function.blocks,
+                function.max_executed,
+                union,
+                function.path.display(),
+                function.start_line,
+                function.name
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Lists per file the conditions of decisions that were always true or always false in
+    /// all successful runs which evaluated them
+    pub fn analyze_constant_conditions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let conn = schema::open(Path::new(&self.db_path))?;
+        if !schema::has_table(&conn, "conditions")? {
+            return Err(Box::from(
+                "DB contains no condition coverage, it has to be collected with '-k conditions'",
+            ));
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT s.path, c.line_no, c.decision, c.condition,
+                c.true_benchmark_count, c.false_benchmark_count
+            FROM \"conditions\" AS c
+            JOIN \"sources\" AS s ON s.id = c.source_id
+            WHERE (c.true_benchmark_count = 0) != (c.false_benchmark_count = 0)
+            ORDER BY s.path, c.line_no, c.decision, c.condition",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            let mut path: String = row.get(0)?;
+            if let Some((old, new)) = self.path_rewrite.clone() {
+                path = path.replace(old.as_str(), new.as_str());
+            }
+            let line_no: usize = row.get(1)?;
+            let decision: usize = row.get(2)?;
+            let condition: usize = row.get(3)?;
+            let true_count: usize = row.get(4)?;
+            let false_count: usize = row.get(5)?;
+            Ok((path, line_no, decision, condition, true_count, false_count))
+        })?;
+
+        let mut file_map: ConstantConditions = BTreeMap::new();
+        for (path, line_no, decision, condition, true_count, false_count) in rows.flatten() {
+            file_map.entry(path).or_default().push((
+                line_no,
+                decision,
+                condition,
+                true_count > 0,
+                true_count.max(false_count),
+            ));
+        }
+
+        println!(
+            "Conditions whose outcome never varied (Files: {}, Total: {}):",
+            file_map.len(),
+            file_map.values().map(|c| c.len()).sum::<usize>()
+        );
+        for (path, conditions) in file_map {
+            println!("{} ({} conditions)", path, conditions.len());
+            for (line_no, decision, condition, outcome, benchmarks) in conditions {
+                println!(
+                    "\tline {} decision {} condition {}: always {} ({} benchmarks)",
+                    line_no, decision, condition, outcome, benchmarks
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn analyze_line_deviations(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         let function_ranges = self.get_functions()?;
 
@@ -311,11 +404,11 @@ impl Analyzer {
         &self,
         table_name: &String,
     ) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
-        let conn = Connection::open_with_flags(&self.db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let conn = schema::open(Path::new(&self.db_path))?;
 
         println!("Retrieving all benchmarks...");
         let stmt = "SELECT id, path FROM \"benchmarks\"";
-        let mut stmt = conn.prepare(&stmt)?;
+        let mut stmt = conn.prepare(stmt)?;
         let rows = stmt.query_map(params![], |row| {
             let id: usize = row.get(0)?;
             let mut path: String = row.get(1)?;
@@ -332,11 +425,9 @@ impl Analyzer {
         })?;
         let mut benchmark_paths = HashMap::new();
         let mut working_benchmarks = HashSet::new();
-        for row in rows {
-            if let Ok((id, path)) = row {
-                benchmark_paths.insert(id, path);
-                working_benchmarks.insert(id);
-            }
+        for (id, path) in rows.flatten() {
+            benchmark_paths.insert(id, path);
+            working_benchmarks.insert(id);
         }
 
         println!("Finding working benchmarks for each unused function...");
@@ -370,13 +461,12 @@ impl Analyzer {
     fn check_min_benches(
         &self,
         table_name: &String,
-    ) -> Result<(HashMap<String, PathBuf>, HashMap<String, usize>), Box<dyn std::error::Error>>
-    {
-        let conn = Connection::open_with_flags(&self.db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    ) -> Result<MinBenches, Box<dyn std::error::Error>> {
+        let conn = schema::open(Path::new(&self.db_path))?;
 
         println!("Retrieving token count for each benchmark...");
         let stmt = "SELECT id, path FROM \"benchmarks\" ORDER BY id";
-        let mut stmt = conn.prepare(&stmt)?;
+        let mut stmt = conn.prepare(stmt)?;
         let rows = stmt.query_map(params![], |row| {
             let id: usize = row.get(0)?;
             let mut path: String = row.get(1)?;
@@ -392,20 +482,18 @@ impl Analyzer {
             Ok((id, path))
         })?;
         let mut ires = vec![];
-        for row in rows {
-            if let Ok((id, path)) = row {
-                ires.push((id, path));
-            }
+        for (id, path) in rows.flatten() {
+            ires.push((id, path));
         }
         let benchmark_pairs: Vec<(usize, (PathBuf, usize))> = ires
             .par_iter()
             .map(|(id, path)| {
                 // println!("Analyzing Path: {}", path.display().to_string());
-                let token_count = Self::count_benchmark_tokens(&path);
+                let token_count = Self::count_benchmark_tokens(path);
                 if token_count.is_err() {
                     println!(
                         "Error during file {} encountered. Skipping...",
-                        path.display().to_string()
+                        path.display()
                     );
                 }
                 let token_count = token_count.unwrap_or(usize::MAX);
@@ -456,12 +544,10 @@ impl Analyzer {
         })?;
         let mut min_benches: HashMap<String, PathBuf> = HashMap::new();
         let mut token_counts: HashMap<String, usize> = HashMap::new();
-        for row in rows {
-            if let Ok((fuid, smallest_bench)) = row {
-                min_benches.insert(fuid, smallest_bench.clone());
-                for (k, v) in Self::get_benchmark_token_countset(&smallest_bench)? {
-                    *token_counts.entry(k).or_insert(0) += v;
-                }
+        for (fuid, smallest_bench) in rows.flatten() {
+            min_benches.insert(fuid, smallest_bench.clone());
+            for (k, v) in Self::get_benchmark_token_countset(&smallest_bench)? {
+                *token_counts.entry(k).or_insert(0) += v;
             }
         }
         Ok((min_benches, token_counts))
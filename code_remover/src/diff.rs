@@ -0,0 +1,666 @@
+//! Differences between two report DBs, e.g. before and after a cvc5 upgrade or a change of
+//! the benchmark set. Sources and benchmarks are matched by path, functions within a source
+//! by name and then by position.
+
+use bitvec::prelude::*;
+use clap::ValueEnum;
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::report::{Cell, Report};
+use crate::schema;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DiffKind {
+    /// Number of sources, functions and benchmarks in either DB, matched and changed
+    Summary,
+    /// Functions that became used or unused, were added or removed
+    Functions,
+    /// Benchmarks whose used functions changed, were added or removed
+    Benchmarks,
+    /// Source files whose functions or benchmark usage changed
+    Files,
+}
+
+struct Function {
+    id: u64,
+    path: String,
+    name: String,
+    start_line: u64,
+    usage: u64,
+}
+
+/// Functions and benchmarks of one report DB
+struct Side {
+    db: PathBuf,
+    conn: Connection,
+    /// Ordered by path and start line
+    functions: Vec<Function>,
+    /// Benchmark ids by path
+    benchmarks: BTreeMap<String, u64>,
+}
+
+impl Side {
+    fn load(
+        conn: Connection,
+        db: &Path,
+        rewrite: impl Fn(String) -> String,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let functions = {
+            let mut stmt = conn.prepare(
+                "SELECT f.id, s.path, f.name, f.start_line, f.benchmark_usage_count
+                FROM \"functions\" AS f
+                JOIN \"sources\" AS s ON s.id = f.source_id",
+            )?;
+            let rows = stmt.query_map(params![], |row| {
+                Ok(Function {
+                    id: row.get(0)?,
+                    path: row.get(1)?,
+                    name: row.get(2)?,
+                    start_line: row.get(3)?,
+                    usage: row.get(4)?,
+                })
+            })?;
+            let mut functions = rows
+                .map(|f| {
+                    f.map(|f| Function {
+                        path: rewrite(f.path),
+                        ..f
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            functions.sort_by(|a, b| (&a.path, a.start_line).cmp(&(&b.path, b.start_line)));
+            functions
+        };
+        let benchmarks = {
+            let mut stmt = conn.prepare("SELECT id, path FROM \"benchmarks\" ORDER BY id")?;
+            let rows = stmt.query_map(params![], |row| Ok((rewrite(row.get(1)?), row.get(0)?)))?;
+            let mut benchmarks = BTreeMap::new();
+            for row in rows {
+                let (path, id): (String, u64) = row?;
+                // Benchmarks are matched by path, one of them would silently be left out
+                if let Some(other) = benchmarks.insert(path.clone(), id) {
+                    return Err(Box::from(format!(
+                        "Benchmarks {} and {} of {} can't be told apart by their path {}",
+                        other,
+                        id,
+                        db.display(),
+                        path
+                    )));
+                }
+            }
+            benchmarks
+        };
+        Ok(Side {
+            db: db.to_path_buf(),
+            conn,
+            functions,
+            benchmarks,
+        })
+    }
+
+    /// Distinct source paths, ordered
+    fn sources(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self.functions.iter().map(|f| f.path.as_str()).collect();
+        paths.dedup();
+        paths
+    }
+
+    /// Benchmarks (bench_id - 1) using each function under the configuration
+    fn bitvecs(
+        &self,
+        config_id: u64,
+    ) -> Result<HashMap<u64, BitVec<u8, Msb0>>, Box<dyn std::error::Error>> {
+        schema::check_config(&self.conn, &self.db, config_id)?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT function_id, data FROM \"function_bitvecs\" WHERE config_id = ?1")?;
+        let rows = stmt.query_map(params![config_id], |row| {
+            let data: Vec<u8> = row.get(1)?;
+            Ok((row.get(0)?, BitVec::from_vec(data)))
+        })?;
+        Ok(rows.collect::<Result<HashMap<_, _>, _>>()?)
+    }
+}
+
+/// Used functions of a benchmark in both DBs, `gained` and `lost` only count matched
+/// functions
+#[derive(Default)]
+struct BenchmarkChange {
+    old_functions: u64,
+    new_functions: u64,
+    gained: u64,
+    lost: u64,
+}
+
+pub struct ReportDiff {
+    old: Side,
+    new: Side,
+    /// Indices of the matched old and new functions
+    matched: Vec<(usize, usize)>,
+}
+
+impl ReportDiff {
+    /// The path rewrite is applied to the source and benchmark paths of the old DB
+    pub fn new(
+        old_db: &Path,
+        new_db: &Path,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path_rewrite = path_rewrite.map(|v| (v[0].to_owned(), v[1].to_owned()));
+        let old = Side::load(schema::open(old_db)?, old_db, |path| match &path_rewrite {
+            Some((from, to)) => path.replace(from.as_str(), to.as_str()),
+            None => path,
+        })?;
+        let new = Side::load(schema::open(new_db)?, new_db, |path| path)?;
+        Ok(Self::matching(old, new))
+    }
+
+    fn matching(old: Side, new: Side) -> Self {
+        let matched = match_functions(&old.functions, &new.functions);
+        ReportDiff { old, new, matched }
+    }
+
+    pub fn report(
+        &self,
+        kind: DiffKind,
+        config_id: u64,
+    ) -> Result<Report, Box<dyn std::error::Error>> {
+        match kind {
+            DiffKind::Summary => self.summary(config_id),
+            DiffKind::Functions => Ok(self.functions()),
+            DiffKind::Benchmarks => self.benchmarks(config_id),
+            DiffKind::Files => Ok(self.files()),
+        }
+    }
+
+    fn summary(&self, config_id: u64) -> Result<Report, Box<dyn std::error::Error>> {
+        let (old_sources, new_sources) = (self.old.sources(), self.new.sources());
+        let matched_sources = old_sources
+            .iter()
+            .filter(|p| new_sources.binary_search(p).is_ok())
+            .count();
+        let (became_used, became_unused) =
+            self.matched.iter().fold((0, 0), |(used, unused), &(o, n)| {
+                match (
+                    self.old.functions[o].usage > 0,
+                    self.new.functions[n].usage > 0,
+                ) {
+                    (false, true) => (used + 1, unused),
+                    (true, false) => (used, unused + 1),
+                    _ => (used, unused),
+                }
+            });
+        let matched_benchmarks = self
+            .old
+            .benchmarks
+            .keys()
+            .filter(|p| self.new.benchmarks.contains_key(*p))
+            .count();
+        let changed_benchmarks = self
+            .benchmark_changes(config_id)?
+            .values()
+            .filter(|c| c.gained + c.lost > 0)
+            .count();
+
+        let mut report = Report::new(vec!["metric", "value"]);
+        for (metric, value) in [
+            ("old_sources", old_sources.len()),
+            ("new_sources", new_sources.len()),
+            ("matched_sources", matched_sources),
+            ("old_functions", self.old.functions.len()),
+            ("new_functions", self.new.functions.len()),
+            ("matched_functions", self.matched.len()),
+            ("functions_became_used", became_used),
+            ("functions_became_unused", became_unused),
+            ("old_benchmarks", self.old.benchmarks.len()),
+            ("new_benchmarks", self.new.benchmarks.len()),
+            ("matched_benchmarks", matched_benchmarks),
+            ("changed_benchmarks", changed_benchmarks),
+        ] {
+            report
+                .rows
+                .push(vec![Cell::Text(metric.to_string()), (value as u64).into()]);
+        }
+        Ok(report)
+    }
+
+    fn functions(&self) -> Report {
+        let mut changes = vec![];
+        let mut old_matched = vec![false; self.old.functions.len()];
+        let mut new_matched = vec![false; self.new.functions.len()];
+        for &(o, n) in &self.matched {
+            old_matched[o] = true;
+            new_matched[n] = true;
+            let (old, new) = (&self.old.functions[o], &self.new.functions[n]);
+            let change = match (old.usage > 0, new.usage > 0) {
+                (false, true) => "became_used",
+                (true, false) => "became_unused",
+                _ => continue,
+            };
+            changes.push((change, Some(old), Some(new)));
+        }
+        changes.extend(
+            self.old
+                .functions
+                .iter()
+                .zip(old_matched)
+                .filter(|(_, matched)| !matched)
+                .map(|(f, _)| ("removed", Some(f), None)),
+        );
+        changes.extend(
+            self.new
+                .functions
+                .iter()
+                .zip(new_matched)
+                .filter(|(_, matched)| !matched)
+                .map(|(f, _)| ("added", None, Some(f))),
+        );
+        changes.sort_by_key(|(_, old, new)| {
+            let f = new.or(*old).unwrap();
+            (f.path.as_str(), f.start_line)
+        });
+
+        let mut report = Report::new(vec![
+            "change",
+            "path",
+            "name",
+            "old_function_id",
+            "new_function_id",
+            "old_start_line",
+            "new_start_line",
+            "old_benchmark_usage_count",
+            "new_benchmark_usage_count",
+        ]);
+        for (change, old, new) in changes {
+            let f = new.or(old).unwrap();
+            report.rows.push(vec![
+                Cell::Text(change.to_string()),
+                Cell::Text(f.path.clone()),
+                Cell::Text(f.name.clone()),
+                old.map(|f| f.id).into(),
+                new.map(|f| f.id).into(),
+                old.map(|f| f.start_line).into(),
+                new.map(|f| f.start_line).into(),
+                old.map(|f| f.usage).into(),
+                new.map(|f| f.usage).into(),
+            ]);
+        }
+        report
+    }
+
+    /// Changes of the benchmarks in both DBs, by path
+    fn benchmark_changes(
+        &self,
+        config_id: u64,
+    ) -> Result<BTreeMap<&str, BenchmarkChange>, Box<dyn std::error::Error>> {
+        // Both sides are compared in the bits of the new DB, restricted to the shared
+        // benchmarks, so the bitvectors of a matched function line up
+        let bit_count = |side: &Side| side.benchmarks.values().max().copied().unwrap_or(0) as usize;
+        let bits = bit_count(&self.new);
+        // New bit of every shared benchmark by its old bit
+        let mut remap = vec![None; bit_count(&self.old)];
+        let mut shared: BitVec<u8, Msb0> = bitvec![u8, Msb0; 0; bits];
+        for (path, old_id) in &self.old.benchmarks {
+            if let Some(new_id) = self.new.benchmarks.get(path) {
+                remap[*old_id as usize - 1] = Some(*new_id as usize - 1);
+                shared.set(*new_id as usize - 1, true);
+            }
+        }
+        let old_bitvecs: HashMap<u64, BitVec<u8, Msb0>> = self
+            .old
+            .bitvecs(config_id)?
+            .into_iter()
+            .map(|(id, old)| {
+                let mut remapped = bitvec![u8, Msb0; 0; bits];
+                for bit in old
+                    .iter_ones()
+                    .filter_map(|bit| remap.get(bit).copied().flatten())
+                {
+                    remapped.set(bit, true);
+                }
+                (id, remapped)
+            })
+            .collect();
+        let new_bitvecs: HashMap<u64, BitVec<u8, Msb0>> = self
+            .new
+            .bitvecs(config_id)?
+            .into_iter()
+            .map(|(id, mut new)| {
+                new.resize(bits, false);
+                new &= shared.as_bitslice();
+                (id, new)
+            })
+            .collect();
+
+        let mut counts: Vec<BenchmarkChange> = Vec::with_capacity(bits);
+        counts.resize_with(bits, Default::default);
+        for bitvec in old_bitvecs.values() {
+            for bit in bitvec.iter_ones() {
+                counts[bit].old_functions += 1;
+            }
+        }
+        for bitvec in new_bitvecs.values() {
+            for bit in bitvec.iter_ones() {
+                counts[bit].new_functions += 1;
+            }
+        }
+        let unused = bitvec![u8, Msb0; 0; bits];
+        let mut changed = unused.clone();
+        for &(o, n) in &self.matched {
+            let old = old_bitvecs
+                .get(&self.old.functions[o].id)
+                .unwrap_or(&unused);
+            let new = new_bitvecs
+                .get(&self.new.functions[n].id)
+                .unwrap_or(&unused);
+            changed.copy_from_bitslice(old);
+            changed ^= new.as_bitslice();
+            for bit in changed.iter_ones() {
+                if old[bit] {
+                    counts[bit].lost += 1;
+                } else {
+                    counts[bit].gained += 1;
+                }
+            }
+        }
+
+        Ok(self
+            .new
+            .benchmarks
+            .iter()
+            .filter(|(_, id)| shared[**id as usize - 1])
+            .map(|(path, id)| (path.as_str(), std::mem::take(&mut counts[*id as usize - 1])))
+            .collect())
+    }
+
+    fn benchmarks(&self, config_id: u64) -> Result<Report, Box<dyn std::error::Error>> {
+        let mut report = Report::new(vec![
+            "change",
+            "path",
+            "old_bench_id",
+            "new_bench_id",
+            "old_used_functions",
+            "new_used_functions",
+            "gained_functions",
+            "lost_functions",
+        ]);
+        let changes = self.benchmark_changes(config_id)?;
+        let mut changed: Vec<_> = changes
+            .iter()
+            .filter(|(_, c)| c.gained + c.lost > 0)
+            .collect();
+        // Most changed first
+        changed.sort_by_key(|(path, c)| (std::cmp::Reverse(c.gained + c.lost), *path));
+        for (path, c) in changed {
+            report.rows.push(vec![
+                Cell::Text("changed".to_string()),
+                Cell::Text(path.to_string()),
+                self.old.benchmarks[*path].into(),
+                self.new.benchmarks[*path].into(),
+                c.old_functions.into(),
+                c.new_functions.into(),
+                c.gained.into(),
+                c.lost.into(),
+            ]);
+        }
+
+        let only = |side: &Side, other: &Side| -> Vec<(String, u64)> {
+            side.benchmarks
+                .iter()
+                .filter(|(path, _)| !other.benchmarks.contains_key(*path))
+                .map(|(path, id)| (path.clone(), *id))
+                .collect()
+        };
+        for (path, id) in only(&self.old, &self.new) {
+            report.rows.push(vec![
+                Cell::Text("removed".to_string()),
+                Cell::Text(path),
+                id.into(),
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+            ]);
+        }
+        for (path, id) in only(&self.new, &self.old) {
+            report.rows.push(vec![
+                Cell::Text("added".to_string()),
+                Cell::Text(path),
+                Cell::Null,
+                id.into(),
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+                Cell::Null,
+            ]);
+        }
+        Ok(report)
+    }
+
+    fn files(&self) -> Report {
+        /// Functions, used functions and summed benchmark_usage_count of a file
+        type Counts = (u64, u64, u64);
+        let mut files: BTreeMap<&str, (Option<Counts>, Option<Counts>)> = BTreeMap::new();
+        let count = |counts: &mut Option<Counts>, f: &Function| {
+            let (functions, used, usage) = counts.get_or_insert((0, 0, 0));
+            *functions += 1;
+            *used += (f.usage > 0) as u64;
+            *usage += f.usage;
+        };
+        for f in &self.old.functions {
+            count(&mut files.entry(&f.path).or_default().0, f);
+        }
+        for f in &self.new.functions {
+            count(&mut files.entry(&f.path).or_default().1, f);
+        }
+
+        let mut rows: Vec<(i64, &str, Option<Counts>, Option<Counts>)> = files
+            .into_iter()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(path, (old, new))| {
+                let usage = |c: Option<Counts>| c.map_or(0, |c| c.2 as i64);
+                (usage(new) - usage(old), path, old, new)
+            })
+            .collect();
+        // Largest shift first
+        rows.sort_by_key(|(shift, path, _, _)| (std::cmp::Reverse(shift.abs()), *path));
+
+        let mut report = Report::new(vec![
+            "path",
+            "old_functions",
+            "new_functions",
+            "old_used_functions",
+            "new_used_functions",
+            "old_benchmark_usage_count",
+            "new_benchmark_usage_count",
+            "usage_shift",
+        ]);
+        for (shift, path, old, new) in rows {
+            report.rows.push(vec![
+                Cell::Text(path.to_string()),
+                old.map(|c| c.0).into(),
+                new.map(|c| c.0).into(),
+                old.map(|c| c.1).into(),
+                new.map(|c| c.1).into(),
+                old.map(|c| c.2).into(),
+                new.map(|c| c.2).into(),
+                Cell::Int(shift),
+            ]);
+        }
+        report
+    }
+}
+
+/// Indices of the old and new functions by source path and name
+type NameGroups<'a> = BTreeMap<(&'a str, &'a str), (Vec<usize>, Vec<usize>)>;
+
+/// Pairs the functions of the same source and name, those at the same start line first and
+/// the remaining ones (moved overloads) in the order of their position
+fn match_functions(old: &[Function], new: &[Function]) -> Vec<(usize, usize)> {
+    let mut groups: NameGroups = BTreeMap::new();
+    for (i, f) in old.iter().enumerate() {
+        groups.entry((&f.path, &f.name)).or_default().0.push(i);
+    }
+    for (i, f) in new.iter().enumerate() {
+        groups.entry((&f.path, &f.name)).or_default().1.push(i);
+    }
+
+    let mut matched = vec![];
+    for (olds, mut news) in groups.into_values() {
+        let mut moved = vec![];
+        for o in olds {
+            match news
+                .iter()
+                .position(|&n| new[n].start_line == old[o].start_line)
+            {
+                Some(i) => matched.push((o, news.remove(i))),
+                None => moved.push(o),
+            }
+        }
+        matched.extend(moved.into_iter().zip(news));
+    }
+    matched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::OutputFormat;
+    use crate::schema::tests::report_db;
+
+    /// a.smt2 and b.smt2 are in both DBs under different ids, gone.smt2 and added.smt2 in
+    /// one of them. solve moved, check became unused, dropped was removed and fresh added.
+    const OLD: &str = "
+        INSERT INTO \"benchmarks\" (id, path) VALUES
+            (1, '/old/a.smt2'), (2, '/old/b.smt2'), (3, '/old/gone.smt2');
+        INSERT INTO \"configurations\" VALUES (1, 'default', '');
+        INSERT INTO \"sources\" VALUES (1, '/old/solver.cpp');
+        INSERT INTO \"functions\" (id, source_id, name, start_line, end_line, benchmark_usage_count) VALUES
+            (1, 1, 'solve', 10, 15, 2), (2, 1, 'check', 20, 25, 2), (3, 1, 'dropped', 30, 35, 0);
+        INSERT INTO \"function_bitvecs\" VALUES (1, 1, 1, X'C0'), (1, 2, 1, X'60');";
+    const NEW: &str = "
+        INSERT INTO \"benchmarks\" (id, path) VALUES
+            (1, '/new/added.smt2'), (2, '/new/b.smt2'), (3, '/new/a.smt2');
+        INSERT INTO \"configurations\" VALUES (1, 'default', ''), (2, 'proofs', '--produce-proofs');
+        INSERT INTO \"sources\" VALUES (1, '/new/solver.cpp');
+        INSERT INTO \"functions\" (id, source_id, name, start_line, end_line, benchmark_usage_count) VALUES
+            (1, 1, 'solve', 12, 17, 2), (2, 1, 'check', 20, 25, 0), (3, 1, 'fresh', 40, 45, 1);
+        INSERT INTO \"function_bitvecs\" VALUES (1, 1, 1, X'A0'), (1, 3, 1, X'40');";
+
+    fn diff(old: &str) -> Result<ReportDiff, Box<dyn std::error::Error>> {
+        let old = Side::load(report_db(old), Path::new("old.sqlite"), |path| {
+            path.replace("/old/", "/new/")
+        })?;
+        let new = Side::load(report_db(NEW), Path::new("new.sqlite"), |path| path)?;
+        Ok(ReportDiff::matching(old, new))
+    }
+
+    fn csv(report: Report) -> String {
+        let mut out = vec![];
+        report.write(OutputFormat::Csv, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn summary() {
+        let report = diff(OLD).unwrap().report(DiffKind::Summary, 1).unwrap();
+        assert_eq!(
+            csv(report),
+            "\
+metric,value
+old_sources,1
+new_sources,1
+matched_sources,1
+old_functions,3
+new_functions,3
+matched_functions,2
+functions_became_used,0
+functions_became_unused,1
+old_benchmarks,3
+new_benchmarks,3
+matched_benchmarks,2
+changed_benchmarks,1
+"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let report = diff(OLD).unwrap().report(DiffKind::Functions, 1).unwrap();
+        assert_eq!(
+            csv(report),
+            "\
+change,path,name,old_function_id,new_function_id,old_start_line,new_start_line,old_benchmark_usage_count,new_benchmark_usage_count
+became_unused,/new/solver.cpp,check,2,2,20,20,2,0
+removed,/new/solver.cpp,dropped,3,,30,,0,
+added,/new/solver.cpp,fresh,,3,,40,,1
+"
+        );
+    }
+
+    #[test]
+    fn benchmarks_compared_in_remapped_bits() {
+        // a.smt2 used solve in both DBs, as bit 0 of the old and bit 2 of the new one. The
+        // usage by gone.smt2 and added.smt2 is left out of the shared benchmarks.
+        let report = diff(OLD).unwrap().report(DiffKind::Benchmarks, 1).unwrap();
+        assert_eq!(
+            csv(report),
+            "\
+change,path,old_bench_id,new_bench_id,old_used_functions,new_used_functions,gained_functions,lost_functions
+changed,/new/b.smt2,2,2,2,1,0,2
+removed,/new/gone.smt2,3,,,,,
+added,/new/added.smt2,,1,,,,
+"
+        );
+    }
+
+    #[test]
+    fn files() {
+        let report = diff(OLD).unwrap().report(DiffKind::Files, 1).unwrap();
+        assert_eq!(
+            csv(report),
+            "\
+path,old_functions,new_functions,old_used_functions,new_used_functions,old_benchmark_usage_count,new_benchmark_usage_count,usage_shift
+/new/solver.cpp,3,3,2,2,4,3,-1
+"
+        );
+    }
+
+    #[test]
+    fn unknown_configuration() {
+        let error = diff(OLD).unwrap().report(DiffKind::Benchmarks, 2).err();
+        assert_eq!(
+            error.unwrap().to_string(),
+            "Report DB old.sqlite has no configuration 2"
+        );
+    }
+
+    #[test]
+    fn benchmark_path_collision() {
+        let old = format!(
+            "{}\nINSERT INTO \"benchmarks\" (id, path) VALUES (4, '/new/b.smt2');",
+            OLD
+        );
+        assert_eq!(
+            diff(&old).err().unwrap().to_string(),
+            "Benchmarks 2 and 4 of old.sqlite can't be told apart by their path /new/b.smt2"
+        );
+    }
+
+    #[test]
+    fn moved_overloads_are_matched_in_order() {
+        let function = |name: &str, start_line| Function {
+            id: start_line,
+            path: "solver.cpp".to_string(),
+            name: name.to_string(),
+            start_line,
+            usage: 0,
+        };
+        let old = [function("f", 1), function("f", 5), function("g", 9)];
+        let new = [function("f", 3), function("f", 5), function("f", 7)];
+        assert_eq!(match_functions(&old, &new), vec![(1, 1), (0, 0)]);
+    }
+}
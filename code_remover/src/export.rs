@@ -0,0 +1,618 @@
+//! Exports the usage counts of a report DB for standard coverage tooling: an lcov tracefile
+//! and a static HTML report. The hit count of a function or line is the number of benchmark
+//! runs that used it.
+
+use bitvec::prelude::*;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::schema;
+
+#[derive(Debug, Clone)]
+struct FunctionHits {
+    name: String,
+    start_line: u64,
+    end_line: u64,
+    hits: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FileHits {
+    functions: Vec<FunctionHits>,
+    /// Hits by line number, empty if lines were not tracked
+    lines: BTreeMap<u64, u64>,
+}
+
+impl FileHits {
+    fn used_functions(&self) -> usize {
+        self.functions.iter().filter(|f| f.hits > 0).count()
+    }
+
+    fn used_lines(&self) -> usize {
+        self.lines.values().filter(|h| **h > 0).count()
+    }
+}
+
+/// Usage counts of all source files, by their (rewritten) path
+pub struct CoverageExport {
+    files: BTreeMap<String, FileHits>,
+    /// Benchmarks the counts are restricted to, `None` for all of them
+    subset: Option<usize>,
+}
+
+impl CoverageExport {
+    /// Reads the counts of all functions and lines. With a `benchmark_filter`, only the
+    /// benchmarks whose path matches it are counted, using the usage bitvectors. Lines are
+    /// left out then if the report DB has no line bitvectors.
+    pub fn load(
+        db: &Path,
+        benchmark_filter: Option<&str>,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = schema::open(db)?;
+        Self::read(&conn, db, benchmark_filter, path_rewrite)
+    }
+
+    fn read(
+        conn: &Connection,
+        db: &Path,
+        benchmark_filter: Option<&str>,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let path_rewrite = path_rewrite.map(|v| (v[0].to_owned(), v[1].to_owned()));
+        let subset = match benchmark_filter {
+            Some(filter) => Some(Subset {
+                benchmarks: matching_benchmarks(conn, &Regex::new(filter)?)?,
+                failed_runs: failed_runs(conn)?,
+            }),
+            None => None,
+        };
+
+        let mut sources: HashMap<u64, String> = HashMap::new();
+        {
+            let mut stmt = conn.prepare("SELECT id, path FROM \"sources\"")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (id, path): (u64, String) = row?;
+                let path = match &path_rewrite {
+                    Some((old, new)) => path.replace(old.as_str(), new.as_str()),
+                    None => path,
+                };
+                sources.insert(id, path);
+            }
+        }
+        let mut files: BTreeMap<String, FileHits> = BTreeMap::new();
+
+        let function_subset = match &subset {
+            Some(subset) => Some(subset_hits(
+                conn,
+                "function_bitvecs",
+                "function_id",
+                subset,
+            )?),
+            None => None,
+        };
+        {
+            let mut stmt = conn.prepare(
+                "SELECT id, source_id, name, start_line, end_line, benchmark_usage_count
+                FROM \"functions\"
+                ORDER BY source_id, start_line",
+            )?;
+            let rows = stmt.query_map(params![], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, source_id, name, start_line, end_line, hits): (
+                    u64,
+                    u64,
+                    String,
+                    u64,
+                    u64,
+                    u64,
+                ) = row?;
+                let Some(path) = sources.get(&source_id) else {
+                    continue;
+                };
+                let hits = match &function_subset {
+                    Some(subset) => subset.get(&id).copied().unwrap_or(0),
+                    None => hits,
+                };
+                files
+                    .entry(path.clone())
+                    .or_default()
+                    .functions
+                    .push(FunctionHits {
+                        name,
+                        start_line,
+                        end_line,
+                        hits,
+                    });
+            }
+        }
+
+        let mut has_lines = schema::has_table(conn, "lines")?;
+        if has_lines && subset.is_some() && !schema::has_table(conn, "line_bitvecs")? {
+            // Migrated reports of older gen_coverage versions only have the line counts
+            eprintln!(
+                "Warning: {} has no line bitvectors, exporting the function counts of the selected benchmarks only",
+                db.display()
+            );
+            has_lines = false;
+        }
+        if has_lines {
+            let line_subset = match &subset {
+                Some(subset) => Some(subset_hits(conn, "line_bitvecs", "line_id", subset)?),
+                None => None,
+            };
+            let mut stmt = conn
+                .prepare("SELECT id, source_id, line_no, benchmark_usage_count FROM \"lines\"")?;
+            let rows = stmt.query_map(params![], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?;
+            for row in rows {
+                let (id, source_id, line_no, hits): (u64, u64, u64, u64) = row?;
+                let Some(path) = sources.get(&source_id) else {
+                    continue;
+                };
+                let hits = match &line_subset {
+                    Some(subset) => subset.get(&id).copied().unwrap_or(0),
+                    None => hits,
+                };
+                let lines = &mut files.entry(path.clone()).or_default().lines;
+                *lines.entry(line_no).or_insert(0) += hits;
+            }
+        }
+
+        Ok(CoverageExport {
+            files,
+            subset: subset.map(|s| s.benchmarks.len()),
+        })
+    }
+
+    /// Writes an lcov tracefile, one record per source file
+    pub fn write_lcov(&self, dst: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(dst)?);
+        self.lcov(&mut out)?;
+        out.flush()?;
+        println!(
+            "Wrote lcov tracefile of {} files to {}",
+            self.files.len(),
+            dst.display()
+        );
+        Ok(())
+    }
+
+    fn lcov(&self, out: &mut impl Write) -> std::io::Result<()> {
+        for (path, file) in &self.files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", path)?;
+            // lcov identifies the functions of a file by their name
+            let mut names: HashMap<&str, usize> = HashMap::new();
+            let functions: Vec<(String, &FunctionHits)> = file
+                .functions
+                .iter()
+                .map(|f| {
+                    let seen = names.entry(f.name.as_str()).or_insert(0);
+                    *seen += 1;
+                    if *seen == 1 {
+                        (f.name.clone(), f)
+                    } else {
+                        (format!("{}:{}", f.name, f.start_line), f)
+                    }
+                })
+                .collect();
+            for (name, function) in &functions {
+                writeln!(out, "FN:{},{}", function.start_line, name)?;
+            }
+            for (name, function) in &functions {
+                writeln!(out, "FNDA:{},{}", function.hits, name)?;
+            }
+            writeln!(out, "FNF:{}", file.functions.len())?;
+            writeln!(out, "FNH:{}", file.used_functions())?;
+            for (line_no, hits) in &file.lines {
+                writeln!(out, "DA:{},{}", line_no, hits)?;
+            }
+            if !file.lines.is_empty() {
+                writeln!(out, "LF:{}", file.lines.len())?;
+                writeln!(out, "LH:{}", file.used_lines())?;
+            }
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes an index page and one annotated page per source file into `dst`, without any
+    /// external assets. Sources that can't be read are listed with their functions only.
+    pub fn write_html(&self, dst: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(dst.join("files"))?;
+        let scope = match self.subset {
+            Some(benchmarks) => format!("{} selected benchmarks", benchmarks),
+            None => "all benchmarks".to_string(),
+        };
+
+        let mut index = String::new();
+        index.push_str(&page_head("Coverage report"));
+        index.push_str(&format!(
+            "<h1>Coverage report</h1>\n<p>Hits are the number of benchmark runs using a function or line, counted over {}.</p>\n",
+            scope
+        ));
+        index.push_str(
+            "<table>\n<tr><th>File</th><th>Functions</th><th>Used</th><th>Coverage</th><th>Lines</th><th>Used</th><th>Coverage</th></tr>\n",
+        );
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let page = format!("files/{}.html", i);
+            fs::write(dst.join(&page), file_page(path, file, &scope))?;
+            let lines = if file.lines.is_empty() {
+                "<td></td><td></td><td></td>".to_string()
+            } else {
+                format!(
+                    "<td>{}</td><td>{}</td><td>{}</td>",
+                    file.lines.len(),
+                    file.used_lines(),
+                    percent(file.used_lines(), file.lines.len())
+                )
+            };
+            index.push_str(&format!(
+                "<tr><td class=\"path\"><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td>{}</tr>\n",
+                page,
+                escape(path),
+                file.functions.len(),
+                file.used_functions(),
+                percent(file.used_functions(), file.functions.len()),
+                lines
+            ));
+        }
+        index.push_str("</table>\n</body>\n</html>\n");
+        fs::write(dst.join("index.html"), index)?;
+        println!(
+            "Wrote HTML report of {} files to {}",
+            self.files.len(),
+            dst.join("index.html").display()
+        );
+        Ok(())
+    }
+}
+
+/// Benchmarks selected by the filter
+struct Subset {
+    benchmarks: HashSet<u64>,
+    /// Runs by benchmark and configuration id whose coverage was collected although they
+    /// failed. Only the failed usage counts include them, but report DBs written before they
+    /// were left out of the bitvectors still have their bits set.
+    failed_runs: HashSet<(u64, u64)>,
+}
+
+/// Ids of the benchmarks whose path matches the filter
+fn matching_benchmarks(
+    conn: &Connection,
+    filter: &Regex,
+) -> Result<HashSet<u64>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare("SELECT id, path FROM \"benchmarks\"")?;
+    let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut ids = HashSet::new();
+    for row in rows {
+        let (id, path): (u64, String) = row?;
+        if filter.is_match(&path) {
+            ids.insert(id);
+        }
+    }
+    if ids.is_empty() {
+        return Err(Box::from(format!("No benchmark matches '{}'", filter)));
+    }
+    Ok(ids)
+}
+
+/// Runs of the coverage run that failed but still contributed coverage, by benchmark and
+/// configuration id
+pub fn failed_runs(conn: &Connection) -> Result<HashSet<(u64, u64)>, Box<dyn std::error::Error>> {
+    if !schema::has_table(conn, "result_benchmarks")? {
+        return Ok(HashSet::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT bench_id, config_id FROM \"result_benchmarks\" WHERE failed_coverage = 1",
+    )?;
+    let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Number of successful runs of the subset using each element, summed over all
+/// configurations like the unfiltered usage counts. Bit `bench_id - 1` of a bitvector is set
+/// if the benchmark used the element.
+fn subset_hits(
+    conn: &Connection,
+    table: &str,
+    id_column: &str,
+    subset: &Subset,
+) -> Result<HashMap<u64, u64>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, config_id, data FROM \"{}\"",
+        id_column, table
+    ))?;
+    let mut rows = stmt.query(params![])?;
+    let mut hits: HashMap<u64, u64> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let id: u64 = row.get(0)?;
+        let config_id: u64 = row.get(1)?;
+        let bits = BitSlice::<u8, Msb0>::from_slice(row.get_ref(2)?.as_blob()?);
+        let count = bits
+            .iter_ones()
+            .map(|bit| bit as u64 + 1)
+            .filter(|bench_id| {
+                subset.benchmarks.contains(bench_id)
+                    && !subset.failed_runs.contains(&(*bench_id, config_id))
+            })
+            .count() as u64;
+        *hits.entry(id).or_insert(0) += count;
+    }
+    Ok(hits)
+}
+
+fn file_page(path: &str, file: &FileHits, scope: &str) -> String {
+    let mut page = page_head(path);
+    page.push_str(&format!(
+        "<p><a href=\"../index.html\">Index</a></p>\n<h1>{}</h1>\n<p>{} of {} functions used by {}.</p>\n",
+        escape(path),
+        file.used_functions(),
+        file.functions.len(),
+        scope
+    ));
+
+    let source = match fs::read(path) {
+        Ok(source) => String::from_utf8_lossy(&source).into_owned(),
+        Err(e) => {
+            page.push_str(&format!(
+                "<p>Could not read the source ({}), use --path-rewrite if it moved.</p>\n<table>\n<tr><th>Line</th><th>Hits</th><th>Function</th></tr>\n",
+                escape(&e.to_string())
+            ));
+            for function in &file.functions {
+                page.push_str(&format!(
+                    "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    if function.hits > 0 { "hit" } else { "miss" },
+                    function.start_line,
+                    function.hits,
+                    escape(&function.name)
+                ));
+            }
+            page.push_str("</table>\n</body>\n</html>\n");
+            return page;
+        }
+    };
+
+    let mut starts: HashMap<u64, Vec<&FunctionHits>> = HashMap::new();
+    for function in &file.functions {
+        starts
+            .entry(function.start_line)
+            .or_default()
+            .push(function);
+    }
+    // Highest hits of the functions enclosing each line, by line number
+    let line_count = source.lines().count();
+    let mut enclosing: Vec<Option<u64>> = vec![None; line_count + 1];
+    for function in &file.functions {
+        // The source may have changed since the coverage run
+        let lines = enclosing
+            .iter_mut()
+            .take(function.end_line as usize + 1)
+            .skip(function.start_line as usize);
+        for max in lines {
+            *max = Some(max.map_or(function.hits, |hits| hits.max(function.hits)));
+        }
+    }
+    page.push_str("<table class=\"source\">\n<tr><th>Line</th><th>Hits</th><th>Source</th></tr>\n");
+    for (i, text) in source.lines().enumerate() {
+        let line_no = i as u64 + 1;
+        for function in starts.get(&line_no).into_iter().flatten() {
+            page.push_str(&format!(
+                "<tr class=\"function {}\"><td></td><td>{}</td><td>{} &mdash; used by {} benchmark runs</td></tr>\n",
+                if function.hits > 0 { "hit" } else { "miss" },
+                function.hits,
+                escape(&function.name),
+                function.hits
+            ));
+        }
+        // Lines outside of any function are only colored if line hits are known
+        let hits = file.lines.get(&line_no).copied();
+        let class = match (hits, enclosing[line_no as usize]) {
+            (Some(0), _) => "miss",
+            (Some(_), _) => "hit",
+            (None, Some(0)) if file.lines.is_empty() => "miss",
+            (None, Some(_)) if file.lines.is_empty() => "hit",
+            _ => "",
+        };
+        page.push_str(&format!(
+            "<tr class=\"{}\"><td class=\"no\">{}</td><td>{}</td><td><pre>{}</pre></td></tr>\n",
+            class,
+            line_no,
+            hits.map_or(String::new(), |h| h.to_string()),
+            escape(text)
+        ));
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+fn page_head(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 0 0.6em; text-align: right; }}
+td.path, .source td:last-child {{ text-align: left; }}
+pre {{ margin: 0; }}
+.no {{ color: #888; }}
+.hit {{ background: #dfd; }}
+.miss {{ background: #fdd; }}
+.function td {{ font-weight: bold; border-top: 1px solid #aaa; }}
+</style>
+</head>
+<body>
+",
+        escape(title)
+    )
+}
+
+fn percent(part: usize, total: usize) -> String {
+    if total == 0 {
+        String::new()
+    } else {
+        format!("{:.1}%", part as f64 * 100.0 / total as f64)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::report_db;
+
+    /// Benchmark 2 failed under configuration 1, but its coverage was collected and, as in
+    /// reports of older gen_coverage versions, its bits are set
+    const DATA: &str = "
+        INSERT INTO \"benchmarks\" (id, path) VALUES
+            (1, '/bench/qf_lia/a.smt2'), (2, '/bench/qf_lia/b.smt2'), (3, '/bench/qf_bv/c.smt2');
+        INSERT INTO \"configurations\" VALUES (1, 'default', ''), (2, 'proofs', '--produce-proofs');
+        INSERT INTO \"result_benchmarks\" (bench_id, config_id, failed_coverage) VALUES
+            (1, 1, 0), (2, 1, 1), (3, 1, 0), (1, 2, 0), (2, 2, 0), (3, 2, 0);
+        INSERT INTO \"sources\" VALUES (1, '/repo/src/solver.cpp');
+        INSERT INTO \"functions\" (id, source_id, name, start_line, end_line, benchmark_usage_count) VALUES
+            (1, 1, 'solve', 2, 4, 3), (2, 1, 'check', 6, 7, 1), (3, 1, 'solve', 9, 10, 0);
+        INSERT INTO \"function_bitvecs\" VALUES
+            (1, 1, 1, X'E0'), (1, 1, 2, X'80'), (1, 2, 2, X'40');
+        INSERT INTO \"lines\" (id, source_id, line_no, benchmark_usage_count) VALUES
+            (1, 1, 2, 3), (2, 1, 3, 0), (3, 1, 6, 1);
+        INSERT INTO \"line_bitvecs\" VALUES (1, 1, 1, X'E0'), (1, 1, 2, X'80'), (1, 3, 2, X'40');";
+
+    fn lcov(conn: &Connection, benchmark_filter: Option<&str>) -> String {
+        let export = CoverageExport::read(
+            conn,
+            Path::new("report.sqlite"),
+            benchmark_filter,
+            Some(vec!["/repo/".to_string(), "/src/cvc5/".to_string()]),
+        )
+        .unwrap();
+        let mut out = vec![];
+        export.lcov(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lcov_of_all_benchmarks() {
+        assert_eq!(
+            lcov(&report_db(DATA), None),
+            "\
+TN:
+SF:/src/cvc5/src/solver.cpp
+FN:2,solve
+FN:6,check
+FN:9,solve:9
+FNDA:3,solve
+FNDA:1,check
+FNDA:0,solve:9
+FNF:3
+FNH:2
+DA:2,3
+DA:3,0
+DA:6,1
+LF:3
+LH:2
+end_of_record
+"
+        );
+    }
+
+    #[test]
+    fn lcov_of_selected_benchmarks() {
+        // The failed run of b.smt2 under configuration 1 is not counted
+        let conn = report_db(DATA);
+        assert_eq!(
+            lcov(&conn, Some("qf_lia/")),
+            "\
+TN:
+SF:/src/cvc5/src/solver.cpp
+FN:2,solve
+FN:6,check
+FN:9,solve:9
+FNDA:2,solve
+FNDA:1,check
+FNDA:0,solve:9
+FNF:3
+FNH:2
+DA:2,2
+DA:3,0
+DA:6,1
+LF:3
+LH:2
+end_of_record
+"
+        );
+
+        // Without line bitvectors only the functions can be counted
+        conn.execute_batch("DROP TABLE \"line_bitvecs\"").unwrap();
+        assert!(!lcov(&conn, Some("qf_lia/")).contains("\nDA:"));
+
+        let error = CoverageExport::read(&conn, Path::new("report.sqlite"), Some("qf_lra"), None);
+        assert_eq!(
+            error.err().unwrap().to_string(),
+            "No benchmark matches 'qf_lra'"
+        );
+    }
+
+    #[test]
+    fn source_lines_take_the_hits_of_their_function() {
+        let path = std::env::temp_dir().join("code_remover_export_test.cpp");
+        fs::write(
+            &path,
+            "#include <x>\nint solve() {\n  return 0;\n}\n\nint check() {\n}\n",
+        )
+        .unwrap();
+        let function = |name: &str, start_line, end_line, hits| FunctionHits {
+            name: name.to_string(),
+            start_line,
+            end_line,
+            hits,
+        };
+        let file = FileHits {
+            functions: vec![
+                function("solve", 2, 4, 3),
+                function("check", 6, 7, 0),
+                // Beyond the end of the source, which changed since the run
+                function("moved", 20, 30, 1),
+            ],
+            lines: BTreeMap::new(),
+        };
+        let page = file_page(&path.display().to_string(), &file, "all benchmarks");
+        fs::remove_file(&path).unwrap();
+
+        let classes: Vec<&str> = page
+            .lines()
+            .filter(|line| line.contains("<td class=\"no\">"))
+            .map(|line| line.split('"').nth(1).unwrap())
+            .collect();
+        assert_eq!(classes, vec!["", "hit", "hit", "hit", "", "miss", "miss"]);
+        assert!(page.contains(
+            "<tr class=\"function hit\"><td></td><td>3</td><td>solve &mdash; used by 3 benchmark runs</td></tr>\n<tr class=\"hit\"><td class=\"no\">2</td>"
+        ));
+    }
+}
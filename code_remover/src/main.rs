@@ -1,11 +1,19 @@
 mod analysis;
+mod diff;
+mod export;
+mod matrix;
 mod remover;
 mod remover_config;
+mod report;
+mod schema;
 
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
+use diff::DiffKind;
+use matrix::MatrixFormat;
 use remover::FunctionKind;
+use report::{OutputFormat, ReportKind};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -60,6 +68,131 @@ enum Commands {
         path_rewrite: Option<Vec<String>>,
     },
 
+    /// Lists the used functions of which only a small share of blocks was executed, as
+    /// candidates for partial removal
+    RetrievePartiallyUsedFunctions {
+        #[arg(long)]
+        db: PathBuf,
+
+        /// Largest share of executed blocks, in range [0,1]
+        #[arg(short, long, default_value_t = 0.1)]
+        max_coverage: f64,
+
+        /// Replaces substring in paths extracted from DB, to accomodate for a system change
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
+    /// Lists per file the conditions of decisions that never varied in their outcome, which
+    /// requires condition coverage to be collected
+    RetrieveConstantConditions {
+        #[arg(long)]
+        db: PathBuf,
+
+        /// Replaces substring in paths extracted from DB, to accomodate for a system change
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
+    /// Prints a canned report of the coverage in the DB
+    Report {
+        #[arg(long)]
+        db: PathBuf,
+
+        #[arg(value_enum)]
+        kind: ReportKind,
+
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Number of functions listed by least-used and most-used
+        #[arg(short = 'n', long, default_value_t = 20)]
+        top: usize,
+
+        /// Benchmark whose used functions are listed, by id or (the end of) its path
+        #[arg(short, long)]
+        benchmark: Option<String>,
+
+        /// Configuration whose usage is reported by histogram, least-used, most-used and
+        /// benchmark
+        #[arg(short, long, default_value_t = 1)]
+        config_id: u64,
+
+        /// Replaces substring in paths extracted from DB, to accomodate for a system change
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
+    /// Exports the usage counts as lcov tracefile and as static HTML report, a count being
+    /// the number of benchmark runs using a function or line
+    ExportCoverage {
+        #[arg(long)]
+        db: PathBuf,
+
+        /// Path of the lcov tracefile (.info) to write
+        #[arg(long)]
+        lcov: Option<PathBuf>,
+
+        /// Directory to write the HTML report to
+        #[arg(long)]
+        html: Option<PathBuf>,
+
+        /// Only count the benchmarks whose path matches this regex
+        #[arg(long)]
+        benchmark_filter: Option<String>,
+
+        /// Replaces substring in paths extracted from DB, to accomodate for a system change
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
+    /// Exports the benchmark-by-function usage matrix of every configuration, together with
+    /// the benchmark and function metadata
+    ExportMatrix {
+        #[arg(long)]
+        db: PathBuf,
+
+        /// Directory to write the matrices and metadata to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(short, long, value_enum, value_delimiter = ',', default_values_t = vec![MatrixFormat::Mtx, MatrixFormat::Csv, MatrixFormat::Dense])]
+        format: Vec<MatrixFormat>,
+
+        /// Only export the matrix of this configuration
+        #[arg(short, long)]
+        config_id: Option<u64>,
+
+        /// Replaces substring in paths extracted from DB, to accomodate for a system change
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
+    /// Compares two report DBs, matching sources and benchmarks by path and functions by
+    /// source, name and position
+    Diff {
+        /// Report DB to compare against, e.g. of the previous cvc5 version
+        #[arg(long)]
+        old: PathBuf,
+
+        #[arg(long)]
+        new: PathBuf,
+
+        #[arg(value_enum)]
+        kind: DiffKind,
+
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+
+        /// Configuration whose usage is compared for the benchmarks
+        #[arg(short, long, default_value_t = 1)]
+        config_id: u64,
+
+        /// Replaces substring in the paths of the old DB, to match them with the new one
+        #[arg(long, num_args = 2, value_names=vec!["FROM", "TO"])]
+        path_rewrite: Option<Vec<String>>,
+    },
+
     /// Remove the functions that have been determined as unneccessary by our optimization step
     Remove {
         #[arg(long)]
@@ -126,6 +259,72 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let mut analyzer = analysis::Analyzer::new(db.display().to_string(), path_rewrite);
             analyzer.analyze_working_benches(p)?;
         }
+        Some(Commands::RetrievePartiallyUsedFunctions {
+            db,
+            max_coverage,
+            path_rewrite,
+        }) => {
+            let mut analyzer = analysis::Analyzer::new(db.display().to_string(), path_rewrite);
+            analyzer.analyze_block_coverage(max_coverage)?;
+        }
+        Some(Commands::RetrieveConstantConditions { db, path_rewrite }) => {
+            let mut analyzer = analysis::Analyzer::new(db.display().to_string(), path_rewrite);
+            analyzer.analyze_constant_conditions()?;
+        }
+        Some(Commands::Report {
+            db,
+            kind,
+            format,
+            top,
+            benchmark,
+            config_id,
+            path_rewrite,
+        }) => {
+            let reporter = report::Reporter::new(&db, path_rewrite)?;
+            let report = reporter.report(kind, top, benchmark.as_deref(), config_id)?;
+            report.write(format, &mut std::io::stdout().lock())?;
+        }
+        Some(Commands::ExportCoverage {
+            db,
+            lcov,
+            html,
+            benchmark_filter,
+            path_rewrite,
+        }) => {
+            if lcov.is_none() && html.is_none() {
+                return Err(Box::from("Expected an --lcov or --html output"));
+            }
+            let export =
+                export::CoverageExport::load(&db, benchmark_filter.as_deref(), path_rewrite)?;
+            if let Some(lcov) = lcov {
+                export.write_lcov(&lcov)?;
+            }
+            if let Some(html) = html {
+                export.write_html(&html)?;
+            }
+        }
+        Some(Commands::ExportMatrix {
+            db,
+            output,
+            format,
+            config_id,
+            path_rewrite,
+        }) => {
+            let export = matrix::MatrixExport::new(&db, path_rewrite)?;
+            export.write(&output, &format, config_id)?;
+        }
+        Some(Commands::Diff {
+            old,
+            new,
+            kind,
+            format,
+            config_id,
+            path_rewrite,
+        }) => {
+            let diff = diff::ReportDiff::new(&old, &new, path_rewrite)?;
+            let report = diff.report(kind, config_id)?;
+            report.write(format, &mut std::io::stdout().lock())?;
+        }
         None => {}
     }
 
@@ -0,0 +1,433 @@
+//! Exports the benchmark-by-function usage matrix in open formats, so it can be loaded
+//! without decoding the `function_bitvecs` blobs (`BitVec<u8, Msb0>`, bit `bench_id - 1`).
+//!
+//! Row `i` (1-based) is the `i`-th benchmark by id and column `j` the `j`-th function by id,
+//! as listed in `benchmarks.csv` and `functions.csv`. Every configuration gets a matrix of
+//! its own.
+
+use bitvec::prelude::*;
+use clap::ValueEnum;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::export::failed_runs;
+use crate::report::csv_field;
+use crate::schema;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MatrixFormat {
+    /// Matrix Market coordinate format (`.mtx`)
+    Mtx,
+    /// `row,column,value` triplets (`.csv`)
+    Csv,
+    /// Row-major bit-packed matrix (`.bin`) with a JSON header (`.json`)
+    Dense,
+}
+
+pub struct MatrixExport {
+    db: PathBuf,
+    conn: Connection,
+    path_rewrite: Option<(String, String)>,
+    /// Benchmark ids in row order
+    benchmarks: Vec<u64>,
+    /// Function ids in column order
+    functions: Vec<u64>,
+}
+
+impl MatrixExport {
+    pub fn new(
+        db: &Path,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = schema::open(db)?;
+        Self::read(conn, db, path_rewrite)
+    }
+
+    fn read(
+        conn: Connection,
+        db: &Path,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let benchmarks = ids(&conn, "SELECT id FROM \"benchmarks\" ORDER BY id")?;
+        let functions = ids(&conn, "SELECT id FROM \"functions\" ORDER BY id")?;
+        Ok(MatrixExport {
+            db: db.to_path_buf(),
+            conn,
+            path_rewrite: path_rewrite.map(|v| (v[0].to_owned(), v[1].to_owned())),
+            benchmarks,
+            functions,
+        })
+    }
+
+    /// Writes the metadata and the matrices of the configurations (all if `None`) to `dst`
+    pub fn write(
+        &self,
+        dst: &Path,
+        formats: &[MatrixFormat],
+        config_id: Option<u64>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(config_id) = config_id {
+            schema::check_config(&self.conn, &self.db, config_id)?;
+        }
+        fs::create_dir_all(dst)?;
+        self.write_benchmarks(&dst.join("benchmarks.csv"))?;
+        self.write_functions(&dst.join("functions.csv"))?;
+
+        let config_ids = match config_id {
+            Some(id) => vec![id],
+            None => ids(
+                &self.conn,
+                "SELECT DISTINCT config_id FROM \"function_bitvecs\" ORDER BY config_id",
+            )?,
+        };
+        for config_id in config_ids {
+            let columns = self.columns(config_id)?;
+            let nnz: usize = columns.iter().map(|c| c.len()).sum();
+            let name = format!("matrix.{}", config_id);
+            for format in formats {
+                match format {
+                    MatrixFormat::Mtx => {
+                        self.write_mtx(&dst.join(format!("{}.mtx", name)), &columns, nnz)?
+                    }
+                    MatrixFormat::Csv => {
+                        self.write_csv(&dst.join(format!("{}.csv", name)), &columns)?
+                    }
+                    MatrixFormat::Dense => {
+                        self.write_dense(dst, &name, config_id, &columns, nnz)?
+                    }
+                }
+            }
+            println!(
+                "Exported the {}x{} matrix of configuration {} ({} entries) to {}",
+                self.benchmarks.len(),
+                self.functions.len(),
+                config_id,
+                nnz,
+                dst.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn rewrite(&self, path: String) -> String {
+        match &self.path_rewrite {
+            Some((old, new)) => path.replace(old.as_str(), new.as_str()),
+            None => path,
+        }
+    }
+
+    /// Rows (0-based) of the benchmarks using each function, in column order. Failed runs
+    /// are left out like in the usage counts, report DBs written before they were left out
+    /// of the bitvectors still have their bits set.
+    fn columns(&self, config_id: u64) -> Result<Vec<Vec<u32>>, Box<dyn std::error::Error>> {
+        let failed_runs = failed_runs(&self.conn)?;
+        let rows: HashMap<u64, u32> = self
+            .benchmarks
+            .iter()
+            .enumerate()
+            .map(|(row, id)| (*id, row as u32))
+            .collect();
+        let column_of: HashMap<u64, usize> = self
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(column, id)| (*id, column))
+            .collect();
+
+        let mut columns = vec![vec![]; self.functions.len()];
+        let mut stmt = self
+            .conn
+            .prepare("SELECT function_id, data FROM \"function_bitvecs\" WHERE config_id = ?1")?;
+        let mut result = stmt.query(params![config_id])?;
+        while let Some(row) = result.next()? {
+            let function_id: u64 = row.get(0)?;
+            let Some(&column) = column_of.get(&function_id) else {
+                continue;
+            };
+            let bits = BitSlice::<u8, Msb0>::from_slice(row.get_ref(1)?.as_blob()?);
+            let column: &mut Vec<u32> = &mut columns[column];
+            column.extend(
+                bits.iter_ones()
+                    .map(|bit| bit as u64 + 1)
+                    .filter(|bench_id| !failed_runs.contains(&(*bench_id, config_id)))
+                    .filter_map(|bench_id| rows.get(&bench_id)),
+            );
+            column.sort_unstable();
+        }
+        Ok(columns)
+    }
+
+    fn write_benchmarks(&self, dst: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(dst)?);
+        writeln!(out, "row,bench_id,path")?;
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, path FROM \"benchmarks\" ORDER BY id")?;
+        let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for (i, row) in rows.enumerate() {
+            let (id, path): (u64, String) = row?;
+            writeln!(out, "{},{},{}", i + 1, id, csv_field(&path))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_functions(&self, dst: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(dst)?);
+        writeln!(
+            out,
+            "column,function_id,path,name,start_line,end_line,benchmark_usage_count"
+        )?;
+        let mut stmt = self.conn.prepare(
+            "SELECT f.id, s.path, f.name, f.start_line, f.end_line, f.benchmark_usage_count
+            FROM \"functions\" AS f
+            JOIN \"sources\" AS s ON s.id = f.source_id
+            ORDER BY f.id",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?;
+        for (i, row) in rows.enumerate() {
+            let (id, path, name, start_line, end_line, usage): (
+                u64,
+                String,
+                String,
+                u64,
+                u64,
+                u64,
+            ) = row?;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                i + 1,
+                id,
+                csv_field(&self.rewrite(path)),
+                csv_field(&name),
+                start_line,
+                end_line,
+                usage
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_mtx(
+        &self,
+        dst: &Path,
+        columns: &[Vec<u32>],
+        nnz: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(dst)?);
+        writeln!(out, "%%MatrixMarket matrix coordinate pattern general")?;
+        writeln!(out, "% rows: benchmarks.csv, columns: functions.csv")?;
+        writeln!(
+            out,
+            "{} {} {}",
+            self.benchmarks.len(),
+            self.functions.len(),
+            nnz
+        )?;
+        for (column, rows) in columns.iter().enumerate() {
+            for row in rows {
+                writeln!(out, "{} {}", row + 1, column + 1)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_csv(
+        &self,
+        dst: &Path,
+        columns: &[Vec<u32>],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = BufWriter::new(File::create(dst)?);
+        writeln!(out, "row,column,value")?;
+        for (column, rows) in columns.iter().enumerate() {
+            for row in rows {
+                writeln!(out, "{},{},1", row + 1, column + 1)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Every row is padded to whole bytes, the first column being the most significant bit
+    /// of its first byte
+    fn write_dense(
+        &self,
+        dst: &Path,
+        name: &str,
+        config_id: u64,
+        columns: &[Vec<u32>],
+        nnz: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let row_bytes = self.functions.len().div_ceil(8);
+        let mut bits: BitVec<u8, Msb0> =
+            BitVec::repeat(false, self.benchmarks.len() * row_bytes * 8);
+        for (column, rows) in columns.iter().enumerate() {
+            for row in rows {
+                bits.set(*row as usize * row_bytes * 8 + column, true);
+            }
+        }
+        let data = format!("{}.bin", name);
+        fs::write(dst.join(&data), bits.as_raw_slice())?;
+
+        let header = serde_json::json!({
+            "format": "bit-packed dense matrix",
+            "data": data,
+            "config_id": config_id,
+            "rows": self.benchmarks.len(),
+            "columns": self.functions.len(),
+            "nnz": nnz,
+            "row_bytes": row_bytes,
+            "layout": "row-major",
+            "bit_order": "msb0",
+            "row_metadata": "benchmarks.csv",
+            "column_metadata": "functions.csv",
+        });
+        fs::write(
+            dst.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&header)? + "\n",
+        )?;
+        Ok(())
+    }
+}
+
+fn ids(conn: &Connection, query: &str) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(query)?;
+    let ids = stmt
+        .query_map(params![], |row| row.get(0))?
+        .collect::<Result<Vec<u64>, _>>()?;
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::report_db;
+
+    /// Benchmark 2 failed under configuration 1 with its bits set, function ids have a gap
+    const DATA: &str = "
+        INSERT INTO \"benchmarks\" (id, path) VALUES
+            (1, '/bench/a.smt2'), (2, '/bench/b,c.smt2'), (3, '/bench/d.smt2');
+        INSERT INTO \"configurations\" VALUES (1, 'default', ''), (2, 'proofs', '--produce-proofs');
+        INSERT INTO \"result_benchmarks\" (bench_id, config_id, failed_coverage) VALUES
+            (1, 1, 0), (2, 1, 1), (3, 1, 0), (1, 2, 0), (2, 2, 0), (3, 2, 0);
+        INSERT INTO \"sources\" VALUES (1, '/repo/src/solver.cpp');
+        INSERT INTO \"functions\" (id, source_id, name, start_line, end_line, benchmark_usage_count) VALUES
+            (1, 1, 'solve', 2, 4, 2), (2, 1, 'check', 6, 7, 2), (5, 1, 'hash', 9, 10, 1);
+        INSERT INTO \"function_bitvecs\" VALUES
+            (1, 1, 1, X'E0'), (1, 5, 1, X'20'), (1, 2, 2, X'C0');";
+
+    fn export(name: &str, formats: &[MatrixFormat], config_id: Option<u64>) -> PathBuf {
+        let dst = std::env::temp_dir().join(format!("code_remover_matrix_{}", name));
+        let _ = fs::remove_dir_all(&dst);
+        let export = MatrixExport::read(
+            report_db(DATA),
+            Path::new("report.sqlite"),
+            Some(vec!["/repo/".to_string(), "/src/cvc5/".to_string()]),
+        )
+        .unwrap();
+        export.write(&dst, formats, config_id).unwrap();
+        dst
+    }
+
+    fn read(dst: &Path, file: &str) -> String {
+        fs::read_to_string(dst.join(file)).unwrap()
+    }
+
+    #[test]
+    fn metadata_and_sparse_formats() {
+        let dst = export("sparse", &[MatrixFormat::Mtx, MatrixFormat::Csv], Some(1));
+        assert_eq!(
+            read(&dst, "benchmarks.csv"),
+            "row,bench_id,path\n1,1,/bench/a.smt2\n2,2,\"/bench/b,c.smt2\"\n3,3,/bench/d.smt2\n"
+        );
+        assert_eq!(
+            read(&dst, "functions.csv"),
+            "\
+column,function_id,path,name,start_line,end_line,benchmark_usage_count
+1,1,/src/cvc5/src/solver.cpp,solve,2,4,2
+2,2,/src/cvc5/src/solver.cpp,check,6,7,2
+3,5,/src/cvc5/src/solver.cpp,hash,9,10,1
+"
+        );
+        // The failed run of b,c.smt2 is left out
+        assert_eq!(
+            read(&dst, "matrix.1.mtx"),
+            "\
+%%MatrixMarket matrix coordinate pattern general
+% rows: benchmarks.csv, columns: functions.csv
+3 3 3
+1 1
+3 1
+3 3
+"
+        );
+        assert_eq!(
+            read(&dst, "matrix.1.csv"),
+            "row,column,value\n1,1,1\n3,1,1\n3,3,1\n"
+        );
+        assert!(!dst.join("matrix.1.bin").exists());
+        assert!(!dst.join("matrix.2.mtx").exists());
+        fs::remove_dir_all(&dst).unwrap();
+    }
+
+    #[test]
+    fn dense_format_of_every_configuration() {
+        let dst = export("dense", &[MatrixFormat::Dense], None);
+        // One byte per row, the first column in the most significant bit
+        assert_eq!(
+            fs::read(dst.join("matrix.1.bin")).unwrap(),
+            vec![0x80, 0x00, 0xA0]
+        );
+        assert_eq!(
+            fs::read(dst.join("matrix.2.bin")).unwrap(),
+            vec![0x40, 0x40, 0x00]
+        );
+        let header: serde_json::Value = serde_json::from_str(&read(&dst, "matrix.2.json")).unwrap();
+        assert_eq!(
+            header,
+            serde_json::json!({
+                "format": "bit-packed dense matrix",
+                "data": "matrix.2.bin",
+                "config_id": 2,
+                "rows": 3,
+                "columns": 3,
+                "nnz": 2,
+                "row_bytes": 1,
+                "layout": "row-major",
+                "bit_order": "msb0",
+                "row_metadata": "benchmarks.csv",
+                "column_metadata": "functions.csv",
+            })
+        );
+        fs::remove_dir_all(&dst).unwrap();
+    }
+
+    #[test]
+    fn unknown_configuration() {
+        let dst = std::env::temp_dir().join("code_remover_matrix_unknown");
+        let export = MatrixExport::read(report_db(DATA), Path::new("report.sqlite"), None).unwrap();
+        let error = export
+            .write(&dst, &[MatrixFormat::Mtx], Some(3))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Report DB report.sqlite has no configuration 3"
+        );
+        assert!(!dst.exists());
+    }
+}
@@ -1,14 +1,18 @@
 use regex::Regex;
-use rusqlite::params;
+use rusqlite::{params, params_from_iter};
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::path::PathBuf;
 
 use crate::remover_config::Config;
+use crate::schema;
 
 const DEBUG: bool = false;
 
+/// Functions per file, paired as (function range from the DB, detected function range)
+pub type FileFunctionRanges = Vec<(PathBuf, Vec<(FunctionRange, FunctionRange)>)>;
+
 #[derive(Clone)]
 pub struct FunctionRange {
     pub name: String,
@@ -18,6 +22,25 @@ pub struct FunctionRange {
     pub end_col: usize,
 }
 
+/// Share of the blocks of a used function that were executed
+pub struct BlockCoverage {
+    pub path: PathBuf,
+    pub name: String,
+    pub start_line: usize,
+    pub blocks: usize,
+    /// Most blocks executed by a single benchmark
+    pub max_executed: usize,
+    /// Blocks executed by any benchmark, only known if gen_coverage ran with `--block-union`
+    pub union_executed: Option<usize>,
+}
+
+impl BlockCoverage {
+    /// Share of executed blocks, using the union if it is known
+    pub fn coverage(&self) -> f64 {
+        self.union_executed.unwrap_or(self.max_executed) as f64 / self.blocks as f64
+    }
+}
+
 struct StatPair {
     removable: Option<usize>,
     removed: Option<usize>,
@@ -98,7 +121,8 @@ impl Remover {
 
             let imports = self.config.get_imports();
             let replacement = self.config.get_placeholder();
-            let line_ranges = line_ranges.iter().map(|(v, _)| (*v).clone()).collect();
+            let line_ranges: Vec<FunctionRange> =
+                line_ranges.iter().map(|(v, _)| (*v).clone()).collect();
             self.replace_lines_in_file(&file, &replacement, &imports, &line_ranges, no_change)?;
         }
 
@@ -114,9 +138,60 @@ impl Remover {
             self.stats.lines.total.unwrap()
         );
 
+        if let Some(max_coverage) = self.config.max_block_coverage {
+            let partial = self.get_block_coverage(max_coverage)?;
+            println!(
+                "[STATS] \t{} used functions executed at most {:.1}% of their blocks",
+                partial.len(),
+                max_coverage * 100.0
+            );
+            for function in partial {
+                println!(
+                    "[PARTIAL] \t{:.1}%\t{}:{} {}",
+                    function.coverage() * 100.0,
+                    function.path.display(),
+                    function.start_line,
+                    function.name
+                );
+            }
+        }
+
         Ok(())
     }
 
+    /// Used functions of which at most `max_coverage` of the blocks were executed, least
+    /// covered first
+    pub fn get_block_coverage(
+        &self,
+        max_coverage: f64,
+    ) -> Result<Vec<BlockCoverage>, Box<dyn std::error::Error>> {
+        let conn = self.config.connect_to_db()?;
+        let mut stmt = conn.prepare(
+            "SELECT s.path, f.name, f.start_line, f.blocks, f.max_blocks_executed, f.union_blocks_executed
+            FROM \"functions\" AS f
+            JOIN \"sources\" AS s ON s.id = f.source_id
+            WHERE f.benchmark_usage_count > 0 AND f.blocks > 0",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            let path: String = row.get(0)?;
+            Ok(BlockCoverage {
+                path: self.config.replace_path_prefix(PathBuf::from(path)),
+                name: row.get(1)?,
+                start_line: row.get(2)?,
+                blocks: row.get(3)?,
+                max_executed: row.get(4)?,
+                union_executed: row.get(5)?,
+            })
+        })?;
+
+        let mut functions: Vec<BlockCoverage> = rows
+            .flatten()
+            .filter(|f| f.coverage() <= max_coverage)
+            .collect();
+        functions.sort_by(|a, b| a.coverage().total_cmp(&b.coverage()));
+        Ok(functions)
+    }
+
     pub fn get_rarely_used_functions(
         &self,
         usage_kind: FunctionKind,
@@ -125,23 +200,49 @@ impl Remover {
         let table_name = self.config.get_table_name()?;
         println!("[INFO] Table name: {}", table_name);
 
+        // Functions with nondeterministic usage might be needed after all, keep them. The
+        // reduced binary serves all configurations, so an unstable usage under any of them
+        // keeps the function.
+        let min_stability = match self.config.min_stability {
+            Some(min_stability) if schema::has_table(&conn, "function_stability")? => {
+                Some(min_stability)
+            }
+            Some(_) => {
+                println!("[WARN] No function_stability table in DB, ignoring min_stability");
+                None
+            }
+            None => None,
+        };
+        let stability_filter = match min_stability {
+            Some(_) => {
+                "AND f.id NOT IN (SELECT function_id FROM \"function_stability\" WHERE score < ?1)"
+            }
+            None => "",
+        };
+        // Functions needed by failed runs are only known if their coverage was collected
+        let failed_filter = if !self.config.ignore_failed_runs {
+            "AND f.failed_benchmark_usage_count = 0"
+        } else {
+            ""
+        };
+
         let stmt = match usage_kind {
-            FunctionKind::Unused => {
+            FunctionKind::Unused => format!(
                 "SELECT s.path, f.name, f.start_line, f.start_col, f.end_line, f.end_col
                 FROM \"functions\" AS f
                 JOIN \"sources\" AS s ON s.id = f.source_id
-                WHERE f.benchmark_usage_count = 0
-                ORDER BY s.path, f.start_line"
-                    .to_string()
-            }
+                WHERE f.benchmark_usage_count = 0 {} {}
+                ORDER BY s.path, f.start_line",
+                stability_filter, failed_filter
+            ),
             FunctionKind::RarelyUsed => format!(
                 "SELECT s.path, f.name, f.start_line, f.start_col, f.end_line, f.end_col
                 FROM \"functions\" AS f
                 JOIN \"sources\" AS s ON s.id = f.source_id
                 JOIN \"{}\" AS u ON f.id = u.func_id
-                WHERE u.use_function = 0
+                WHERE u.use_function = 0 {} {}
                 ORDER BY s.path, f.start_line",
-                table_name
+                table_name, stability_filter, failed_filter
             ),
             FunctionKind::All => {
                 "SELECT s.path, f.name, f.start_line, f.start_col, f.end_line, f.end_col
@@ -151,8 +252,12 @@ impl Remover {
                     .to_string()
             }
         };
+        let args = match usage_kind {
+            FunctionKind::All => None,
+            _ => min_stability,
+        };
         let mut stmt = conn.prepare(&stmt)?;
-        let rows = stmt.query_map(params![], |row| {
+        let rows = stmt.query_map(params_from_iter(args), |row| {
             let path: String = row.get(0)?;
             let name: String = row.get(1)?;
             let start_line: usize = row.get(2)?;
@@ -166,21 +271,19 @@ impl Remover {
         // Aggregate query results into file_map, which groups the functions by files
         let file_map: HashMap<PathBuf, Vec<FunctionRange>> = {
             let mut result_map: HashMap<PathBuf, Vec<FunctionRange>> = HashMap::new();
-            for row in rows {
-                if let Ok((path, name, start_line, start_col, end_line, end_col)) = row {
-                    let path = PathBuf::from(path);
-                    let curr_func = FunctionRange {
-                        name,
-                        start_line,
-                        start_col,
-                        end_line,
-                        end_col,
-                    };
-                    if let Some(funcs) = result_map.get_mut(&path) {
-                        funcs.push(curr_func)
-                    } else {
-                        result_map.insert(path, vec![curr_func]);
-                    }
+            for (path, name, start_line, start_col, end_line, end_col) in rows.flatten() {
+                let path = PathBuf::from(path);
+                let curr_func = FunctionRange {
+                    name,
+                    start_line,
+                    start_col,
+                    end_line,
+                    end_col,
+                };
+                if let Some(funcs) = result_map.get_mut(&path) {
+                    funcs.push(curr_func)
+                } else {
+                    result_map.insert(path, vec![curr_func]);
                 }
             }
 
@@ -192,8 +295,7 @@ impl Remover {
     pub fn find_function_ranges(
         &mut self,
         file_map: HashMap<PathBuf, Vec<FunctionRange>>,
-    ) -> Result<Vec<(PathBuf, Vec<(FunctionRange, FunctionRange)>)>, Box<dyn std::error::Error>>
-    {
+    ) -> Result<FileFunctionRanges, Box<dyn std::error::Error>> {
         // Keep track of some statistics
         let mut total_func_count = 0;
         let mut removable_func_count = 0;
@@ -202,6 +304,12 @@ impl Remover {
 
         let mut result = vec![];
 
+        // Regex used to extract the function name.
+        // A function name can only contain alphanumeric characters and underscores
+        // Additionally it can not start with a number (we also allow ~, for destructors)
+        // We also allow this repeated over and joined by :: for classes
+        let func_name_regex = Regex::new(r"(([a-zA-Z_~]\w*::)?[a-zA-Z_~]\w*)\(").unwrap();
+
         for (path, functions) in file_map.iter() {
             total_func_count += functions.len();
             total_line_count += functions
@@ -250,12 +358,6 @@ impl Remover {
             let mut func_end: usize;
             let mut func_end_col: usize;
 
-            // Regex used to extract the function name.
-            // A function name can only contain alphanumeric characters and underscores
-            // Additionally it can not start with a number (we also allow ~, for destructors)
-            // We also allow this repeated over and joined by :: for classes
-            let func_name_regex = Regex::new(r"(([a-zA-Z_~]\w*::)?[a-zA-Z_~]\w*)\(").unwrap();
-
             // Try to parse all functions manually and make them accessible via range and via name
             let mut funcs_by_name = HashMap::new();
             let mut funcs_by_lines = HashMap::new();
@@ -328,10 +430,7 @@ impl Remover {
                     func_depth += (open_count as i64) - (close_count as i64);
                 }
 
-                namespace_prefix = namespace_prefix
-                    .into_iter()
-                    .filter(|(d, _)| d <= &depth)
-                    .collect();
+                namespace_prefix.retain(|(d, _)| d <= &depth);
 
                 // Only start counting the brackets once we have entered the body
                 entered_body = entered_body && func_depth > 0;
@@ -353,8 +452,7 @@ impl Remover {
                             body_chance = str == "()"
                                 || str == "("
                                 || str == ")"
-                                || (body_chance && line.trim_end().ends_with(","))
-                                || (body_chance && str == ")");
+                                || (body_chance && line.trim_end().ends_with(","));
                         }
                         res
                     };
@@ -534,7 +632,7 @@ impl Remover {
                         function.name,
                         function.start_line,
                         function.end_line,
-                        path.display().to_string()
+                        path.display()
                     );
                     if DEBUG {
                         println!(
@@ -578,10 +676,10 @@ impl Remover {
         file_path: &PathBuf,
         replacement_str: &str,
         additional_imports: &Vec<String>,
-        skip_ranges: &Vec<FunctionRange>,
+        skip_ranges: &[FunctionRange],
         no_change: bool,
     ) -> io::Result<()> {
-        if skip_ranges.len() == 0 {
+        if skip_ranges.is_empty() {
             return Ok(());
         }
 
@@ -630,9 +728,9 @@ impl Remover {
                     removed_lines += 1;
                     if line_no == start {
                         if no_change {
-                            print!("{}{{", line[..start_col].to_string());
+                            print!("{}{{", &line[..start_col]);
                         } else {
-                            write!(writer, "{}{{", line[..start_col].to_string())?;
+                            write!(writer, "{}{{", &line[..start_col])?;
                         }
                     }
                     // We reached the end of the current skip range
@@ -648,9 +746,9 @@ impl Remover {
                         };
                         // Insert our "dummy code" and the remainder
                         if no_change {
-                            print!("{}}}{}", replacement_str, remainder.to_string());
+                            print!("{}}}{}", replacement_str, remainder);
                         } else {
-                            write!(writer, "{}}}{}", replacement_str, remainder.to_string())?;
+                            write!(writer, "{}}}{}", replacement_str, remainder)?;
                         }
 
                         // Fetch the next skip range
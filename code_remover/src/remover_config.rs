@@ -1,7 +1,12 @@
 use ordered_float::OrderedFloat;
-use rusqlite::{Connection, OpenFlags};
+use rusqlite::Connection;
 use serde_derive::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::schema;
 
 fn mk_true() -> bool {
     true
@@ -57,6 +62,21 @@ pub struct Config {
     #[serde(default)]
     pub ignore: ConfigIgnore,
 
+    /// Keeps functions whose stability score (from the nondeterminism detection of
+    /// gen_coverage) is below this value under any configuration
+    #[serde(default)]
+    pub min_stability: Option<f64>,
+
+    /// Also removes functions only used by failed or timed out runs, if gen_coverage
+    /// collected their coverage
+    #[serde(default)]
+    pub ignore_failed_runs: bool,
+
+    /// Lists the kept functions of which at most this share of blocks was executed, as
+    /// candidates for partial removal
+    #[serde(default)]
+    pub max_block_coverage: Option<f64>,
+
     #[serde(default)]
     pub path: HashMap<PathBuf, PathConfig>,
 }
@@ -75,6 +95,9 @@ impl Config {
             imports: None,
             path: HashMap::new(),
             replace_path_prefix: replace_path,
+            min_stability: None,
+            ignore_failed_runs: false,
+            max_block_coverage: None,
             ignore: ConfigIgnore {
                 path_prefix: vec![],
                 constructors: true,
@@ -96,8 +119,8 @@ impl Config {
     }
 
     pub fn connect_to_db(&self) -> Result<Connection, Box<dyn std::error::Error>> {
-        println!("db path: {}", self.db.display().to_string());
-        let conn = Connection::open_with_flags(&self.db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        println!("db path: {}", self.db.display());
+        let conn = schema::open(&self.db)?;
         Ok(conn)
     }
 
@@ -112,7 +135,7 @@ impl Config {
             )
     }
 
-    pub fn ignore_path_prefix(&self, path: &PathBuf) -> bool {
+    pub fn ignore_path_prefix(&self, path: &Path) -> bool {
         for k in &self.ignore.path_prefix {
             if path.starts_with(k) {
                 return true;
@@ -0,0 +1,754 @@
+//! Canned reports over a report DB, for the questions that otherwise need hand-written SQL
+//! against `functions`, `sources` and `function_bitvecs`
+
+use bitvec::prelude::*;
+use clap::ValueEnum;
+use rusqlite::{params, Connection};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::schema;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ReportKind {
+    /// Function and line coverage per source file, most unused functions first
+    Files,
+    /// Function and line coverage per directory
+    Dirs,
+    /// Number of functions by the number of benchmarks using them
+    Histogram,
+    /// Used functions with the fewest benchmarks using them
+    LeastUsed,
+    /// Functions with the most benchmarks using them
+    MostUsed,
+    /// Functions used by a single benchmark
+    Benchmark,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Null,
+}
+
+impl Cell {
+    fn ratio(part: u64, total: u64) -> Cell {
+        if total == 0 {
+            Cell::Null
+        } else {
+            Cell::Float(part as f64 / total as f64)
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Cell::Int(_) | Cell::Float(_))
+    }
+}
+
+impl std::fmt::Display for Cell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cell::Int(v) => write!(f, "{}", v),
+            Cell::Float(v) => write!(f, "{:.4}", v),
+            Cell::Text(v) => write!(f, "{}", v),
+            Cell::Null => Ok(()),
+        }
+    }
+}
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Int(v) => serializer.serialize_i64(*v),
+            Cell::Float(v) => serializer.serialize_f64(*v),
+            Cell::Text(v) => serializer.serialize_str(v),
+            Cell::Null => serializer.serialize_none(),
+        }
+    }
+}
+
+impl From<u64> for Cell {
+    fn from(v: u64) -> Self {
+        Cell::Int(v as i64)
+    }
+}
+
+impl From<String> for Cell {
+    fn from(v: String) -> Self {
+        Cell::Text(v)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Cell::Null, Into::into)
+    }
+}
+
+/// Rows of a report, printable in all output formats
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+impl Report {
+    pub fn new(columns: Vec<&'static str>) -> Self {
+        Report {
+            columns,
+            rows: vec![],
+        }
+    }
+
+    pub fn write(&self, format: OutputFormat, out: &mut impl Write) -> io::Result<()> {
+        match format {
+            OutputFormat::Table => self.write_table(out),
+            OutputFormat::Csv => self.write_csv(out),
+            OutputFormat::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            }
+        }
+    }
+
+    fn write_table(&self, out: &mut impl Write) -> io::Result<()> {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|c| c.to_string()).collect())
+            .collect();
+        let widths: Vec<usize> = (0..self.columns.len())
+            .map(|i| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([self.columns[i].len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let header: Vec<String> = self
+            .columns
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect();
+        writeln!(out, "{}", header.join("  ").trim_end())?;
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+        writeln!(out, "{}", rule.join("  "))?;
+        for (row, strings) in self.rows.iter().zip(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(strings)
+                .zip(&widths)
+                .map(|((cell, s), w)| {
+                    if cell.is_numeric() {
+                        format!("{:>w$}", s, w = w)
+                    } else {
+                        format!("{:<w$}", s, w = w)
+                    }
+                })
+                .collect();
+            writeln!(out, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.columns.join(","))?;
+        for row in &self.rows {
+            let line: Vec<String> = row.iter().map(|c| csv_field(&c.to_string())).collect();
+            writeln!(out, "{}", line.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// One JSON object per row, keeping the column order
+impl Serialize for Report {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Row<'a>(&'a [&'static str], &'a [Cell]);
+        impl Serialize for Row<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut map = serializer.serialize_map(Some(self.0.len()))?;
+                for (column, cell) in self.0.iter().zip(self.1) {
+                    map.serialize_entry(column, cell)?;
+                }
+                map.end()
+            }
+        }
+
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in &self.rows {
+            seq.serialize_element(&Row(&self.columns, row))?;
+        }
+        seq.end()
+    }
+}
+
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Function and line counts of a file or directory
+#[derive(Debug, Clone, Default)]
+struct Coverage {
+    functions: u64,
+    used_functions: u64,
+    lines: u64,
+    used_lines: u64,
+}
+
+impl Coverage {
+    fn add(&mut self, other: &Coverage) {
+        self.functions += other.functions;
+        self.used_functions += other.used_functions;
+        self.lines += other.lines;
+        self.used_lines += other.used_lines;
+    }
+
+    fn row(&self, path: String, has_lines: bool) -> Vec<Cell> {
+        let lines = |v: u64| if has_lines { Cell::from(v) } else { Cell::Null };
+        vec![
+            Cell::Text(path),
+            self.functions.into(),
+            self.used_functions.into(),
+            (self.functions - self.used_functions).into(),
+            Cell::ratio(self.used_functions, self.functions),
+            lines(self.lines),
+            lines(self.used_lines),
+            if has_lines {
+                Cell::ratio(self.used_lines, self.lines)
+            } else {
+                Cell::Null
+            },
+        ]
+    }
+}
+
+const COVERAGE_COLUMNS: [&str; 8] = [
+    "path",
+    "functions",
+    "used_functions",
+    "unused_functions",
+    "function_coverage",
+    "lines",
+    "used_lines",
+    "line_coverage",
+];
+
+/// The usage count is the one under the reported configuration
+const FUNCTION_COLUMNS: [&str; 5] = [
+    "function_id",
+    "path",
+    "name",
+    "start_line",
+    "benchmark_usage_count",
+];
+
+pub struct Reporter {
+    db: PathBuf,
+    conn: Connection,
+    path_rewrite: Option<(String, String)>,
+}
+
+impl Reporter {
+    pub fn new(
+        db: &Path,
+        path_rewrite: Option<Vec<String>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = schema::open(db)?;
+        Ok(Reporter {
+            db: db.to_path_buf(),
+            conn,
+            path_rewrite: path_rewrite.map(|v| (v[0].to_owned(), v[1].to_owned())),
+        })
+    }
+
+    pub fn report(
+        &self,
+        kind: ReportKind,
+        top: usize,
+        benchmark: Option<&str>,
+        config_id: u64,
+    ) -> Result<Report, Box<dyn std::error::Error>> {
+        match kind {
+            ReportKind::Files => self.files(),
+            ReportKind::Dirs => self.dirs(),
+            ReportKind::Histogram => self.histogram(config_id),
+            ReportKind::LeastUsed => self.top_functions(top, false, config_id),
+            ReportKind::MostUsed => self.top_functions(top, true, config_id),
+            ReportKind::Benchmark => match benchmark {
+                Some(benchmark) => self.benchmark_functions(benchmark, config_id),
+                None => Err(Box::from("The benchmark report needs a --benchmark")),
+            },
+        }
+    }
+
+    fn rewrite(&self, path: String) -> String {
+        match &self.path_rewrite {
+            Some((old, new)) => path.replace(old.as_str(), new.as_str()),
+            None => path,
+        }
+    }
+
+    /// Coverage of every source file, and whether line coverage is known
+    fn file_coverage(
+        &self,
+    ) -> Result<(BTreeMap<String, Coverage>, bool), Box<dyn std::error::Error>> {
+        let mut files: BTreeMap<u64, (String, Coverage)> = BTreeMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT s.id, s.path, COUNT(f.id), COALESCE(SUM(f.benchmark_usage_count > 0), 0)
+            FROM \"sources\" AS s
+            LEFT JOIN \"functions\" AS f ON f.source_id = s.id
+            GROUP BY s.id",
+        )?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        for row in rows {
+            let (id, path, functions, used_functions): (u64, String, u64, u64) = row?;
+            let coverage = Coverage {
+                functions,
+                used_functions,
+                ..Default::default()
+            };
+            files.insert(id, (self.rewrite(path), coverage));
+        }
+
+        let has_lines = schema::has_table(&self.conn, "lines")?;
+        if has_lines {
+            let mut stmt = self.conn.prepare(
+                "SELECT source_id, COUNT(*), SUM(benchmark_usage_count > 0)
+                FROM \"lines\"
+                GROUP BY source_id",
+            )?;
+            let rows =
+                stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+            for row in rows {
+                let (id, lines, used_lines): (u64, u64, u64) = row?;
+                if let Some((_, coverage)) = files.get_mut(&id) {
+                    coverage.lines = lines;
+                    coverage.used_lines = used_lines;
+                }
+            }
+        }
+
+        // Sources can only be told apart by their path after the rewrite
+        let mut by_path: BTreeMap<String, Coverage> = BTreeMap::new();
+        for (path, coverage) in files.into_values() {
+            by_path.entry(path).or_default().add(&coverage);
+        }
+        Ok((by_path, has_lines))
+    }
+
+    fn files(&self) -> Result<Report, Box<dyn std::error::Error>> {
+        let (files, has_lines) = self.file_coverage()?;
+        let mut files: Vec<(String, Coverage)> = files.into_iter().collect();
+        files.sort_by_key(|(_, c)| std::cmp::Reverse(c.functions - c.used_functions));
+
+        let mut report = Report::new(COVERAGE_COLUMNS.to_vec());
+        for (path, coverage) in files {
+            report.rows.push(coverage.row(path, has_lines));
+        }
+        Ok(report)
+    }
+
+    fn dirs(&self) -> Result<Report, Box<dyn std::error::Error>> {
+        let (files, has_lines) = self.file_coverage()?;
+        let mut dirs: BTreeMap<String, Coverage> = BTreeMap::new();
+        for (path, coverage) in files {
+            let dir = PathBuf::from(path)
+                .parent()
+                .map_or(String::new(), |p| p.display().to_string());
+            dirs.entry(dir).or_default().add(&coverage);
+        }
+
+        let mut report = Report::new(COVERAGE_COLUMNS.to_vec());
+        for (dir, coverage) in dirs {
+            report.rows.push(coverage.row(dir, has_lines));
+        }
+        Ok(report)
+    }
+
+    /// Functions bucketed by their usage count under the configuration, in decades above 1
+    fn histogram(&self, config_id: u64) -> Result<Report, Box<dyn std::error::Error>> {
+        schema::check_config(&self.conn, &self.db, config_id)?;
+        // Functions unused under the configuration have no usage row
+        let mut stmt = self.conn.prepare(
+            "SELECT COALESCE(u.benchmark_usage_count, 0)
+            FROM \"functions\" AS f
+            LEFT JOIN \"function_usage\" AS u ON u.function_id = f.id AND u.config_id = ?1",
+        )?;
+        let counts = stmt
+            .query_map(params![config_id], |row| row.get::<_, u64>(0))?
+            .collect::<Result<Vec<u64>, _>>()?;
+
+        // Bucket bounds (inclusive), 0, 1, 2-9, 10-99, ...
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let mut buckets = vec![(0, 0), (1, 1)];
+        let mut low = 2;
+        let mut high = 9;
+        while low <= max {
+            buckets.push((low, high));
+            low = high + 1;
+            high = high * 10 + 9;
+        }
+
+        let mut report = Report::new(vec![
+            "min_benchmarks",
+            "max_benchmarks",
+            "functions",
+            "share",
+        ]);
+        for (low, high) in buckets {
+            let functions = counts.iter().filter(|c| (low..=high).contains(*c)).count() as u64;
+            report.rows.push(vec![
+                low.into(),
+                high.into(),
+                functions.into(),
+                Cell::ratio(functions, counts.len() as u64),
+            ]);
+        }
+        Ok(report)
+    }
+
+    fn top_functions(
+        &self,
+        top: usize,
+        most_used: bool,
+        config_id: u64,
+    ) -> Result<Report, Box<dyn std::error::Error>> {
+        schema::check_config(&self.conn, &self.db, config_id)?;
+        // Unused functions are only in the DB if gen_coverage tracked all of them, they are
+        // not what least used refers to
+        let query = format!(
+            "SELECT f.id, s.path, f.name, f.start_line, u.benchmark_usage_count
+            FROM \"functions\" AS f
+            JOIN \"sources\" AS s ON s.id = f.source_id
+            JOIN \"function_usage\" AS u ON u.function_id = f.id AND u.config_id = ?2
+            WHERE u.benchmark_usage_count > 0
+            ORDER BY u.benchmark_usage_count {}, s.path, f.start_line
+            LIMIT ?1",
+            if most_used { "DESC" } else { "ASC" }
+        );
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map(params![top as u64, config_id], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?;
+
+        let mut report = Report::new(FUNCTION_COLUMNS.to_vec());
+        for row in rows {
+            let (id, path, name, start_line, usage): (u64, String, String, u64, u64) = row?;
+            report.rows.push(vec![
+                id.into(),
+                self.rewrite(path).into(),
+                name.into(),
+                start_line.into(),
+                usage.into(),
+            ]);
+        }
+        Ok(report)
+    }
+
+    /// Functions whose usage bitvector of the configuration has the bit of the benchmark
+    /// set. The benchmark is given by its id or by (the end of) its path.
+    fn benchmark_functions(
+        &self,
+        benchmark: &str,
+        config_id: u64,
+    ) -> Result<Report, Box<dyn std::error::Error>> {
+        schema::check_config(&self.conn, &self.db, config_id)?;
+        let bench_id: u64 = match benchmark.parse() {
+            Ok(id) => id,
+            Err(_) => {
+                let mut stmt = self.conn.prepare(
+                    "SELECT id FROM \"benchmarks\" WHERE path = ?1 OR path LIKE '%/' || ?1",
+                )?;
+                let ids = stmt
+                    .query_map(params![benchmark], |row| row.get(0))?
+                    .collect::<Result<Vec<u64>, _>>()?;
+                match ids.as_slice() {
+                    [id] => *id,
+                    [] => return Err(Box::from(format!("No benchmark matches '{}'", benchmark))),
+                    _ => {
+                        return Err(Box::from(format!(
+                            "{} benchmarks match '{}', pass its id instead",
+                            ids.len(),
+                            benchmark
+                        )))
+                    }
+                }
+            }
+        };
+        if bench_id == 0 {
+            return Err(Box::from("Benchmark ids start at 1"));
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT f.id, s.path, f.name, f.start_line, COALESCE(u.benchmark_usage_count, 0), b.data
+            FROM \"function_bitvecs\" AS b
+            JOIN \"functions\" AS f ON f.id = b.function_id
+            JOIN \"sources\" AS s ON s.id = f.source_id
+            LEFT JOIN \"function_usage\" AS u ON u.function_id = f.id AND u.config_id = b.config_id
+            WHERE b.config_id = ?1
+            ORDER BY s.path, f.start_line",
+        )?;
+        let bit = (bench_id - 1) as usize;
+        let rows = stmt.query_map(params![config_id], |row| {
+            let slice: &[u8] = row.get_ref(5)?.as_blob()?;
+            let bitvec = BitSlice::<u8, Msb0>::from_slice(slice);
+            let used = bitvec.get(bit).is_some_and(|b| *b);
+            Ok((
+                used,
+                (
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ),
+            ))
+        })?;
+
+        let mut report = Report::new(FUNCTION_COLUMNS.to_vec());
+        for row in rows {
+            let (used, (id, path, name, start_line, usage)): (
+                bool,
+                (u64, String, String, u64, u64),
+            ) = row?;
+            if used {
+                report.rows.push(vec![
+                    id.into(),
+                    self.rewrite(path).into(),
+                    name.into(),
+                    start_line.into(),
+                    usage.into(),
+                ]);
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::tests::report_db;
+
+    /// Three benchmarks run under two configurations. Bit `bench_id - 1` of a bitvector is
+    /// its most significant bit first.
+    const DATA: &str = "
+        INSERT INTO \"benchmarks\" (id, path) VALUES
+            (1, '/bench/a.smt2'), (2, '/bench/sub/b.smt2'), (3, '/bench/c.smt2');
+        INSERT INTO \"configurations\" VALUES (1, 'default', ''), (2, 'proofs', '--produce-proofs');
+        INSERT INTO \"sources\" VALUES (1, '/repo/src/solver.cpp'), (2, '/repo/src/util/hash.cpp');
+        INSERT INTO \"functions\" (id, source_id, name, start_line, end_line, benchmark_usage_count) VALUES
+            (1, 1, 'solve', 10, 20, 5), (2, 1, 'check', 30, 40, 1),
+            (3, 2, 'hash', 5, 8, 2), (4, 2, 'rehash', 12, 14, 0);
+        INSERT INTO \"function_usage\" (function_id, config_id, benchmark_usage_count) VALUES
+            (1, 1, 3), (1, 2, 2), (2, 1, 1), (3, 2, 2);
+        INSERT INTO \"function_bitvecs\" VALUES
+            (1, 1, 1, X'E0'), (1, 2, 1, X'40'), (1, 1, 2, X'A0'), (2, 3, 2, X'C0');
+        INSERT INTO \"lines\" (id, source_id, line_no, benchmark_usage_count) VALUES
+            (1, 1, 10, 3), (2, 1, 11, 0), (3, 1, 30, 1), (4, 2, 5, 2), (5, 2, 12, 0);";
+
+    fn reporter(path_rewrite: Option<(&str, &str)>) -> Reporter {
+        Reporter {
+            db: PathBuf::from("report.sqlite"),
+            conn: report_db(DATA),
+            path_rewrite: path_rewrite.map(|(old, new)| (old.to_string(), new.to_string())),
+        }
+    }
+
+    fn render(report: &Report, format: OutputFormat) -> String {
+        let mut out = vec![];
+        report.write(format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn report(kind: ReportKind, config_id: u64) -> Report {
+        reporter(None).report(kind, 20, None, config_id).unwrap()
+    }
+
+    #[test]
+    fn files_as_table() {
+        let report = reporter(Some(("/repo/", ""))).report(ReportKind::Files, 20, None, 1);
+        assert_eq!(
+            render(&report.unwrap(), OutputFormat::Table),
+            "\
+path               functions  used_functions  unused_functions  function_coverage  lines  used_lines  line_coverage
+-----------------  ---------  --------------  ----------------  -----------------  -----  ----------  -------------
+src/util/hash.cpp          2               1                 1             0.5000      2           1         0.5000
+src/solver.cpp             2               2                 0             1.0000      3           2         0.6667
+"
+        );
+    }
+
+    #[test]
+    fn dirs_as_csv() {
+        let mut report = report(ReportKind::Dirs, 1);
+        assert_eq!(
+            render(&report, OutputFormat::Csv),
+            "\
+path,functions,used_functions,unused_functions,function_coverage,lines,used_lines,line_coverage
+/repo/src,2,2,0,1.0000,3,2,0.6667
+/repo/src/util,2,1,1,0.5000,2,1,0.5000
+"
+        );
+        // Fields are quoted only if needed
+        report.rows[0][0] = Cell::Text("/repo/a,\"b\"".to_string());
+        report.rows.truncate(1);
+        assert_eq!(
+            render(&report, OutputFormat::Csv),
+            "\
+path,functions,used_functions,unused_functions,function_coverage,lines,used_lines,line_coverage
+\"/repo/a,\"\"b\"\"\",2,2,0,1.0000,3,2,0.6667
+"
+        );
+    }
+
+    #[test]
+    fn functions_as_json() {
+        let report = reporter(None)
+            .report(ReportKind::Benchmark, 20, Some("sub/b.smt2"), 1)
+            .unwrap();
+        assert_eq!(
+            render(&report, OutputFormat::Json),
+            r#"[
+  {
+    "function_id": 1,
+    "path": "/repo/src/solver.cpp",
+    "name": "solve",
+    "start_line": 10,
+    "benchmark_usage_count": 3
+  },
+  {
+    "function_id": 2,
+    "path": "/repo/src/solver.cpp",
+    "name": "check",
+    "start_line": 30,
+    "benchmark_usage_count": 1
+  }
+]
+"#
+        );
+    }
+
+    /// Rows of (min_benchmarks, max_benchmarks, functions)
+    fn buckets(report: &Report) -> Vec<(i64, i64, i64)> {
+        report
+            .rows
+            .iter()
+            .map(|row| match row[..3] {
+                [Cell::Int(low), Cell::Int(high), Cell::Int(functions)] => (low, high, functions),
+                _ => panic!("unexpected row {:?}", row),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn histogram_per_configuration() {
+        let report = report(ReportKind::Histogram, 1);
+        assert_eq!(buckets(&report), vec![(0, 0, 2), (1, 1, 1), (2, 9, 1)]);
+        assert_eq!(report.rows[0][3], Cell::Float(0.5));
+
+        // Decades up to the largest count
+        let reporter = reporter(None);
+        reporter
+            .conn
+            .execute_batch("UPDATE \"function_usage\" SET benchmark_usage_count = 100 WHERE function_id = 1 AND config_id = 2")
+            .unwrap();
+        let report = reporter.report(ReportKind::Histogram, 20, None, 2).unwrap();
+        assert_eq!(
+            buckets(&report),
+            vec![(0, 0, 2), (1, 1, 0), (2, 9, 1), (10, 99, 0), (100, 999, 1)]
+        );
+    }
+
+    #[test]
+    fn top_functions_per_configuration() {
+        let names = |report: Report| -> Vec<(String, i64)> {
+            report
+                .rows
+                .into_iter()
+                .map(|row| (row[2].to_string(), row[4].to_string().parse().unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            names(report(ReportKind::LeastUsed, 1)),
+            vec![("check".to_string(), 1), ("solve".to_string(), 3)]
+        );
+        // Ties are ordered by path and line
+        assert_eq!(
+            names(report(ReportKind::MostUsed, 2)),
+            vec![("solve".to_string(), 2), ("hash".to_string(), 2)]
+        );
+        let report = reporter(None)
+            .report(ReportKind::MostUsed, 1, None, 1)
+            .unwrap();
+        assert_eq!(names(report), vec![("solve".to_string(), 3)]);
+    }
+
+    #[test]
+    fn benchmark_by_id_or_path() {
+        let reporter = reporter(None);
+        let names = |benchmark: &str, config_id: u64| -> Vec<String> {
+            let report = reporter
+                .report(ReportKind::Benchmark, 20, Some(benchmark), config_id)
+                .unwrap();
+            report.rows.iter().map(|row| row[2].to_string()).collect()
+        };
+        assert_eq!(names("2", 2), vec!["hash"]);
+        assert_eq!(names("/bench/c.smt2", 2), vec!["solve"]);
+        assert_eq!(names("a.smt2", 1), vec!["solve"]);
+
+        let error = |benchmark: &str, config_id: u64| -> String {
+            reporter
+                .report(ReportKind::Benchmark, 20, Some(benchmark), config_id)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("d.smt2", 1), "No benchmark matches 'd.smt2'");
+        assert_eq!(error("0", 1), "Benchmark ids start at 1");
+    }
+
+    #[test]
+    fn unknown_configuration() {
+        for kind in [
+            ReportKind::Histogram,
+            ReportKind::LeastUsed,
+            ReportKind::MostUsed,
+            ReportKind::Benchmark,
+        ] {
+            let error = reporter(None).report(kind, 20, Some("1"), 3).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "Report DB report.sqlite has no configuration 3"
+            );
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Check of the report DB layout before reading it, see `gen_coverage::db::migrations`
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::Path;
+
+/// Schema version of the report DBs this code_remover reads, has to match the one of
+/// gen_coverage
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// Opens the report DB read only, failing if it has a different schema version
+pub fn open(db: &Path) -> Result<Connection, Box<dyn std::error::Error>> {
+    let conn = Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let version: Option<String> = conn
+        .query_row(
+            "SELECT value FROM \"config\" WHERE key = 'schema_version'",
+            params![],
+            |row| row.get(0),
+        )
+        .optional()?;
+    // Reports written before versioning count as version 0
+    let version: u64 = version.as_deref().unwrap_or("0").parse()?;
+    if version < SCHEMA_VERSION {
+        return Err(format!(
+            "Report DB {} has schema version {}, upgrade it to version {} via 'gen_coverage {} migrate'",
+            db.display(),
+            version,
+            SCHEMA_VERSION,
+            db.display()
+        )
+        .into());
+    }
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "Report DB {} has schema version {}, newer than the supported version {}, update code_remover",
+            db.display(),
+            version,
+            SCHEMA_VERSION
+        )
+        .into());
+    }
+    Ok(conn)
+}
+
+/// Whether the report DB has the table, which depends on the tracked coverage kinds
+pub fn has_table(conn: &Connection, table: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let count: u64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Fails unless the report DB has the configuration, its usage would read as all unused
+pub fn check_config(
+    conn: &Connection,
+    db: &Path,
+    config_id: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let known: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM \"configurations\" WHERE id = ?1",
+        params![config_id],
+        |row| row.get(0),
+    )?;
+    if !known {
+        return Err(format!(
+            "Report DB {} has no configuration {}",
+            db.display(),
+            config_id
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Layout of a report with tracked functions and lines, as created by gen_coverage
+    const LAYOUT: &str = "
+        CREATE TABLE \"config\" (key TEXT NOT NULL PRIMARY KEY, value TEXT NOT NULL);
+        INSERT INTO \"config\" VALUES ('schema_version', '1');
+        CREATE TABLE \"benchmarks\" (
+            id INTEGER PRIMARY KEY,
+            prefix TEXT,
+            path TEXT NOT NULL,
+            archive TEXT,
+            member TEXT
+        );
+        CREATE TABLE \"configurations\" (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            args TEXT NOT NULL
+        );
+        CREATE TABLE \"sources\" (id INTEGER PRIMARY KEY, path INTEGER NOT NULL UNIQUE);
+        CREATE TABLE \"result_benchmarks\" (
+            id INTEGER PRIMARY KEY,
+            bench_id INTEGER NOT NULL,
+            config_id INTEGER NOT NULL,
+            time_ms INTEGER NOT NULL DEFAULT 0,
+            exit_code INTEGER NOT NULL DEFAULT 0,
+            stdout TEXT NOT NULL DEFAULT '',
+            stderr TEXT NOT NULL DEFAULT '',
+            failed_coverage INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE \"functions\" (
+            id INTEGER PRIMARY KEY,
+            source_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            start_line INTEGER NOT NULL,
+            start_col INTEGER NOT NULL DEFAULT 1,
+            end_line INTEGER NOT NULL,
+            end_col INTEGER NOT NULL DEFAULT 1,
+            benchmark_usage_count INTEGER NOT NULL,
+            failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+            UNIQUE(source_id, start_line, start_col)
+        );
+        CREATE TABLE \"function_usage\" (
+            function_id INTEGER NOT NULL,
+            config_id INTEGER NOT NULL,
+            benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+            failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+            UNIQUE(function_id, config_id)
+        );
+        CREATE TABLE \"function_bitvecs\" (
+            source_id INTEGER NOT NULL,
+            function_id INTEGER NOT NULL,
+            config_id INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            UNIQUE(source_id, function_id, config_id)
+        );
+        CREATE TABLE \"lines\" (
+            id INTEGER PRIMARY KEY,
+            source_id INTEGER NOT NULL,
+            line_no INTEGER NOT NULL,
+            function_id INTEGER,
+            benchmark_usage_count INTEGER NOT NULL,
+            failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+            UNIQUE(source_id, line_no)
+        );
+        CREATE TABLE \"line_bitvecs\" (
+            source_id INTEGER NOT NULL,
+            line_id INTEGER NOT NULL,
+            config_id INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            UNIQUE(source_id, line_id, config_id)
+        );";
+
+    /// In-memory report DB holding the rows inserted by `data`
+    pub fn report_db(data: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(LAYOUT).unwrap();
+        conn.execute_batch(data).unwrap();
+        conn
+    }
+}
@@ -0,0 +1,106 @@
+//! Measures how fast gcov results are parsed by the workers and merged by the DB writer on a
+//! synthetic workload, without running any benchmark.
+//!
+//! Usage: cargo run --release --example gcov_throughput [FUNCTIONS] [BENCHMARKS]
+
+use gen_coverage::runner::gcov::{
+    parse_gcov_json, res_to_bitvec, GcovBitvec, GcovCounts, GcovOptions,
+};
+use gen_coverage::runner::CoverageIndex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+const FUNCTIONS_PER_FILE: usize = 100;
+/// Distinct gcov outputs, benchmarks cycle through them
+const DISTINCT_OUTPUTS: usize = 20;
+/// Share of the functions used by a single benchmark
+const USAGE: f64 = 0.1;
+
+fn gcov_json(rng: &mut StdRng, functions: usize) -> String {
+    let files: Vec<String> = (0..functions.div_ceil(FUNCTIONS_PER_FILE))
+        .map(|f| {
+            let funcs: Vec<String> = (0..FUNCTIONS_PER_FILE)
+                .map(|i| {
+                    let count = rng.gen_bool(USAGE) as u32;
+                    format!(
+                        "{{\"blocks\":4,\"blocks_executed\":{},\"demangled_name\":\"ns::Class{}::method{}(int, std::string const&)\",\"end_column\":1,\"end_line\":{},\"execution_count\":{},\"name\":\"_ZN2ns6Class{}7method{}EiRKSs\",\"start_column\":1,\"start_line\":{}}}",
+                        count * 4,
+                        f,
+                        i,
+                        i * 10 + 8,
+                        count,
+                        f,
+                        i,
+                        i * 10 + 1
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"file\":\"/src/solver/module{}/file{}.cpp\",\"functions\":[{}],\"lines\":[]}}",
+                f % 10,
+                f,
+                funcs.join(",")
+            )
+        })
+        .collect();
+    format!(
+        "{{\"format_version\":\"2\",\"gcc_version\":\"12.2.0\",\"files\":[{}]}}",
+        files.join(",")
+    )
+}
+
+fn per_second(count: usize, elapsed: Duration) -> f64 {
+    count as f64 / elapsed.as_secs_f64()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let functions: usize = args.next().map_or(60_000, |a| a.parse().unwrap());
+    let benchmarks: usize = args.next().map_or(1_000, |a| a.parse().unwrap());
+
+    let options = GcovOptions {
+        track_funcs: true,
+        track_lines: false,
+        track_branches: false,
+        track_conditions: false,
+        no_ignore_libs: false,
+        build_dirs: vec!["/repo/build/".to_string()],
+    };
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let outputs: Vec<String> = (0..DISTINCT_OUTPUTS)
+        .map(|_| gcov_json(&mut rng, functions))
+        .collect();
+    println!(
+        "{} functions, {} benchmarks, {:.1} MiB gcov output per benchmark",
+        functions,
+        benchmarks,
+        outputs[0].len() as f64 / (1024.0 * 1024.0)
+    );
+
+    let mut parse_time = Duration::ZERO;
+    let mut merge_time = Duration::ZERO;
+    let index = CoverageIndex::default();
+    let mut bitvecs = GcovBitvec::default();
+    let mut counts = GcovCounts::default();
+    for (i, output) in outputs.iter().cycle().take(benchmarks).enumerate() {
+        // Worker side: parsing the gcov output of a benchmark
+        let start = Instant::now();
+        let result = parse_gcov_json(output, &options, &index).unwrap();
+        parse_time += start.elapsed();
+
+        // DB writer side: indicator matrix and merged usage counts
+        let start = Instant::now();
+        res_to_bitvec(&mut bitvecs, benchmarks, i + 1, &result);
+        counts.add(&index.read(), &result);
+        merge_time += start.elapsed();
+    }
+
+    println!(
+        "parse: {:.1} benchmarks/s, merge: {:.1} benchmarks/s",
+        per_second(benchmarks, parse_time),
+        per_second(benchmarks, merge_time)
+    );
+}
@@ -0,0 +1,411 @@
+//! Benchmarks stored compressed (`.zst`, `.gz`, `.xz`) or inside of (compressed) tar archives.
+//! They are listed when the benchmarks table is populated and decompressed into a scratch
+//! directory right before they are run.
+
+use crate::types::{Benchmark, ResultT};
+
+use crossbeam::channel;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+/// Only members with this suffix are benchmarks, archives often also contain licenses etc.
+pub const MEMBER_SUFFIX: &str = ".smt2";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+enum Container {
+    Plain,
+    /// A single compressed file, with its name after decompression
+    Compressed(Compression, String),
+    Tar(Compression),
+}
+
+fn container(path: &Path) -> Container {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if name.ends_with(".tgz") {
+        return Container::Tar(Compression::Gzip);
+    }
+    let (compression, stem) = [
+        (".gz", Compression::Gzip),
+        (".zst", Compression::Zstd),
+        (".xz", Compression::Xz),
+    ]
+    .into_iter()
+    .find_map(|(suffix, c)| name.strip_suffix(suffix).map(|stem| (c, stem)))
+    .unwrap_or((Compression::None, &name));
+
+    if stem.ends_with(".tar") {
+        Container::Tar(compression)
+    } else if compression == Compression::None {
+        Container::Plain
+    } else {
+        Container::Compressed(compression, stem.to_string())
+    }
+}
+
+fn decoder(path: &Path, compression: Compression) -> io::Result<Box<dyn Read + Send>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+    })
+}
+
+/// A benchmark matched by the benchmark pattern of a coverage run
+#[derive(Debug, Clone)]
+pub struct BenchmarkFile {
+    /// Path of the benchmark as if it was not compressed, archive members are placed where
+    /// extracting the archive next to it would put them
+    pub path: PathBuf,
+    /// Compressed file or tar archive containing the benchmark
+    pub archive: Option<PathBuf>,
+    /// Path of the benchmark inside of a tar archive
+    pub member: Option<String>,
+}
+
+/// The benchmarks contained in a file matched by the benchmark pattern
+pub fn expand(file: &Path) -> ResultT<Vec<BenchmarkFile>> {
+    let file = file.canonicalize()?;
+    let dir = file.parent().unwrap_or(Path::new("/")).to_path_buf();
+    match container(&file) {
+        Container::Plain => Ok(vec![BenchmarkFile {
+            path: file,
+            archive: None,
+            member: None,
+        }]),
+        Container::Compressed(_, name) => Ok(vec![BenchmarkFile {
+            path: dir.join(name),
+            archive: Some(file),
+            member: None,
+        }]),
+        Container::Tar(compression) => {
+            let mut archive = tar::Archive::new(decoder(&file, compression)?);
+            let mut benchmarks = vec![];
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let member = entry.path()?.to_string_lossy().to_string();
+                if !member.ends_with(MEMBER_SUFFIX) {
+                    continue;
+                }
+                // Member paths are relative, but may start with ./
+                let relative: PathBuf = Path::new(&member)
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect();
+                benchmarks.push(BenchmarkFile {
+                    path: dir.join(relative),
+                    archive: Some(file.clone()),
+                    member: Some(member),
+                });
+            }
+            Ok(benchmarks)
+        }
+    }
+}
+
+struct TarRequest {
+    archive: PathBuf,
+    member: String,
+    dest: PathBuf,
+    reply: channel::Sender<io::Result<()>>,
+}
+
+/// Makes the benchmarks of a single worker available as plain files
+pub struct Unpacker {
+    scratch_dir: PathBuf,
+    /// Tar archives are read by a helper thread, which keeps the current archive open between
+    /// requests. Benchmarks are mostly requested in archive order, so every archive is only
+    /// read about once per worker.
+    tar_requests: Option<channel::Sender<TarRequest>>,
+}
+
+impl Unpacker {
+    pub fn new(scratch_dir: PathBuf) -> Self {
+        Unpacker {
+            scratch_dir,
+            tar_requests: None,
+        }
+    }
+
+    /// Path the benchmark can be run from, compressed benchmarks are decompressed into the
+    /// scratch directory and have to be removed via `release` after the run
+    pub fn materialize(&mut self, benchmark: &Benchmark) -> io::Result<PathBuf> {
+        let archive = match &benchmark.archive {
+            None => return Ok(benchmark.path.clone()),
+            Some(archive) => archive,
+        };
+        fs::create_dir_all(&self.scratch_dir)?;
+        // Keep the file name, executables might look at the extension
+        let dest = self
+            .scratch_dir
+            .join(benchmark.path.file_name().unwrap_or_default());
+
+        match (&benchmark.member, container(archive)) {
+            (Some(member), Container::Tar(_)) => {
+                let (reply, result) = channel::bounded(1);
+                self.tar_reader()
+                    .send(TarRequest {
+                        archive: archive.clone(),
+                        member: member.clone(),
+                        dest: dest.clone(),
+                        reply,
+                    })
+                    .map_err(|_| io::Error::other("Archive reader terminated unexpectedly"))?;
+                result
+                    .recv()
+                    .map_err(|_| io::Error::other("Archive reader terminated unexpectedly"))??;
+            }
+            (None, Container::Compressed(compression, _)) => {
+                io::copy(
+                    &mut decoder(archive, compression)?,
+                    &mut File::create(&dest)?,
+                )?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Unsupported benchmark container {} for {}",
+                        archive.display(),
+                        benchmark.path.display()
+                    ),
+                ))
+            }
+        }
+        Ok(dest)
+    }
+
+    /// Removes a benchmark decompressed by `materialize`
+    pub fn release(&self, benchmark: &Benchmark, path: &Path) {
+        if benchmark.archive.is_some() {
+            fs::remove_file(path).unwrap_or(());
+        }
+    }
+
+    fn tar_reader(&mut self) -> &channel::Sender<TarRequest> {
+        self.tar_requests.get_or_insert_with(|| {
+            let (sender, receiver) = channel::unbounded();
+            thread::spawn(move || {
+                let mut next = receiver.recv().ok();
+                while let Some(request) = next.take() {
+                    next = serve_archive(request, &receiver);
+                }
+            });
+            sender
+        })
+    }
+}
+
+impl Drop for Unpacker {
+    fn drop(&mut self) {
+        fs::remove_dir(&self.scratch_dir).unwrap_or(());
+    }
+}
+
+/// Opens the archive of the first request and serves all following requests for members
+/// further down in the same archive. Returns the first request it could not serve that way.
+fn serve_archive(
+    first: TarRequest,
+    requests: &channel::Receiver<TarRequest>,
+) -> Option<TarRequest> {
+    let path = first.archive.clone();
+    let compression = match container(&path) {
+        Container::Tar(compression) => compression,
+        _ => Compression::None,
+    };
+    let mut archive = match decoder(&path, compression) {
+        Ok(reader) => tar::Archive::new(reader),
+        Err(e) => {
+            first.reply.send(Err(e)).unwrap_or(());
+            return requests.recv().ok();
+        }
+    };
+    let mut entries = match archive.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            first.reply.send(Err(e)).unwrap_or(());
+            return requests.recv().ok();
+        }
+    };
+
+    let mut request = first;
+    // Whether the current request is searched for from the start of the archive
+    let mut from_start = true;
+    loop {
+        let mut result = None;
+        for entry in entries.by_ref() {
+            let extracted = entry.and_then(|mut entry| {
+                if entry.path()?.to_string_lossy() != request.member {
+                    return Ok(false);
+                }
+                io::copy(&mut entry, &mut File::create(&request.dest)?)?;
+                Ok(true)
+            });
+            match extracted {
+                Ok(false) => continue,
+                Ok(true) => result = Some(Ok(())),
+                Err(e) => result = Some(Err(e)),
+            }
+            break;
+        }
+
+        match result {
+            Some(result) => request.reply.send(result).unwrap_or(()),
+            // The member might come before the current position
+            None if !from_start => return Some(request),
+            None => request
+                .reply
+                .send(Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found in {}", request.member, path.display()),
+                )))
+                .unwrap_or(()),
+        }
+
+        request = requests.recv().ok()?;
+        if request.archive != path {
+            return Some(request);
+        }
+        from_start = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `single.smt2` compressed in every format, and `suite.tar.zst` holding `./a/1.smt2`,
+    /// `./LICENSE` and `b/2.smt2`
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archive")
+    }
+
+    fn benchmark(file: BenchmarkFile) -> Benchmark {
+        Benchmark {
+            id: 1,
+            path: file.path,
+            prefix: None,
+            archive: file.archive,
+            member: file.member,
+            screened_out: vec![],
+        }
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gen_coverage_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn container_by_suffix() {
+        assert!(matches!(container(Path::new("x.smt2")), Container::Plain));
+        assert!(matches!(
+            container(Path::new("x.smt2.zst")),
+            Container::Compressed(Compression::Zstd, name) if name == "x.smt2"
+        ));
+        assert!(matches!(
+            container(Path::new("x.smt2.xz")),
+            Container::Compressed(Compression::Xz, _)
+        ));
+        assert!(matches!(
+            container(Path::new("x.tar")),
+            Container::Tar(Compression::None)
+        ));
+        assert!(matches!(
+            container(Path::new("x.tar.gz")),
+            Container::Tar(Compression::Gzip)
+        ));
+        assert!(matches!(
+            container(Path::new("x.tgz")),
+            Container::Tar(Compression::Gzip)
+        ));
+    }
+
+    #[test]
+    fn expand_lists_benchmarks_in_place() {
+        let dir = fixtures().canonicalize().unwrap();
+        let single = expand(&dir.join("single.smt2.gz")).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].path, dir.join("single.smt2"));
+        assert_eq!(single[0].archive, Some(dir.join("single.smt2.gz")));
+        assert_eq!(single[0].member, None);
+
+        // Directories and members without the benchmark suffix are skipped
+        let suite = expand(&dir.join("suite.tar.zst")).unwrap();
+        let listed: Vec<_> = suite
+            .iter()
+            .map(|b| (b.path.clone(), b.member.clone().unwrap()))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                (dir.join("a/1.smt2"), "./a/1.smt2".to_string()),
+                (dir.join("b/2.smt2"), "b/2.smt2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn materialize_decompresses_every_format() {
+        let scratch = scratch_dir("unpack");
+        let mut unpacker = Unpacker::new(scratch.clone());
+        for name in ["single.smt2.gz", "single.smt2.zst", "single.smt2.xz"] {
+            let file = expand(&fixtures().join(name)).unwrap().remove(0);
+            let benchmark = benchmark(file);
+            let path = unpacker.materialize(&benchmark).unwrap();
+            assert_eq!(path, scratch.join("single.smt2"));
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                "(check-sat)\n",
+                "{}",
+                name
+            );
+            unpacker.release(&benchmark, &path);
+            assert!(!path.exists());
+        }
+        drop(unpacker);
+        assert!(!scratch.exists());
+    }
+
+    #[test]
+    fn materialize_reads_tar_members_in_any_order() {
+        let scratch = scratch_dir("tar");
+        let mut unpacker = Unpacker::new(scratch.clone());
+        let suite = expand(&fixtures().join("suite.tar.zst")).unwrap();
+        let (first, second) = (benchmark(suite[0].clone()), benchmark(suite[1].clone()));
+        // The second member comes first, so the reader starts over for the first one
+        for (benchmark, content) in [
+            (&second, "(assert false)\n"),
+            (&first, "(assert true)\n"),
+            (&second, "(assert false)\n"),
+        ] {
+            let path = unpacker.materialize(benchmark).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), content);
+            unpacker.release(benchmark, &path);
+        }
+
+        let missing = Benchmark {
+            member: Some("c/3.smt2".to_string()),
+            ..first.clone()
+        };
+        let e = unpacker.materialize(&missing).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        drop(unpacker);
+        assert!(!scratch.exists());
+    }
+}
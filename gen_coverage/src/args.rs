@@ -1,107 +1,22 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use gen_coverage::config::{
+    CoverageBackend, CoverageFile, CoverageKind, EvaluateFile, RetryOutcome, RunFile, TimeoutSignal,
+};
+use gen_coverage::types::{Configuration, ResultT};
+use log::info;
 use mktemp::Temp;
-use once_cell::sync::Lazy;
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
     fs::create_dir,
     path::{Path, PathBuf},
 };
 
-use crate::info;
-
-// Global static variable to store parsed CLI arguments
-pub static ARGS: Lazy<CliArgs> = Lazy::new(|| {
-    let mut args = CliArgs::parse();
-
-    if let Commands::Coverage {
-        ref mut tmp_dir, ..
-    } = &mut args.command
-    {
-        // Initialize `tmp_dir` if it hasn't been explicitly provided
-        if tmp_dir.is_none() {
-            let tmp_base_dir = Path::new("/tmp/coverage_reports");
-            if !tmp_base_dir.exists() {
-                create_dir(tmp_base_dir).expect("Could not create tmp dir");
-            }
-            *tmp_dir = Some(Temp::new_dir_in(tmp_base_dir).unwrap().to_path_buf());
-            info!(
-                "Using temp directory '{}' for intermediate gcov results",
-                tmp_dir.as_ref().unwrap().display().to_string()
-            )
-        }
-    }
-    args
-});
-
-pub static TRACK_UNUSED: Lazy<bool> = Lazy::new(|| {
-    if let Commands::Coverage { track_all, .. } = &ARGS.command {
-        track_all.unwrap_or(false)
-    } else {
-        false
-    }
-});
-
-pub static TRACK_FUNCS: Lazy<bool> = Lazy::new(|| {
-    if let Commands::Coverage { coverage_kinds, .. } = &ARGS.command {
-        coverage_kinds.contains(&CoverageKind::Functions)
-    } else {
-        false
-    }
-});
-pub static TRACK_LINES: Lazy<bool> = Lazy::new(|| {
-    if let Commands::Coverage { coverage_kinds, .. } = &ARGS.command {
-        coverage_kinds.contains(&CoverageKind::Lines)
-    } else {
-        false
-    }
-});
-pub static TRACK_BRANCHES: Lazy<bool> = Lazy::new(|| {
-    if let Commands::Coverage { coverage_kinds, .. } = &ARGS.command {
-        coverage_kinds.contains(&CoverageKind::Branches)
-    } else {
-        false
-    }
-});
-pub static EXEC_PLACEHOLDER: Lazy<Vec<String>> = Lazy::new(|| {
-    assert!(
-        ARGS.exec.contains("{}"),
-        "Could not find '{{}}' in exec arg, use this as a placeholder for the benchmark file argument"
-    );
-    shellwords::split(&ARGS.exec).expect("Could not parse executable command")
-});
-pub static RESULT_TABLE_NAME: Lazy<String> = Lazy::new(|| {
-    if let Commands::Evaluate { id } = &ARGS.command {
-        let start = SystemTime::now();
-        let epoch_time = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        match id {
-            Some(v) if v != "" => {
-                format!("evaluation_benchmarks_{}_{}", v, epoch_time.as_millis())
-            }
-            _ => {
-                format!("evaluation_benchmarks_{}", epoch_time.as_millis())
-            }
-        }
-    } else {
-        "result_benchmarks".to_string()
-    }
-});
-
 #[derive(ValueEnum, Clone, Debug, PartialEq)]
 pub enum CoverageMode {
     Aggregated,
     Full,
 }
 
-#[derive(ValueEnum, Clone, Debug, PartialEq)]
-pub enum CoverageKind {
-    Functions,
-    Branches,
-    Lines,
-}
-
 impl fmt::Display for CoverageMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -115,71 +30,115 @@ impl fmt::Display for CoverageMode {
     }
 }
 
-impl fmt::Display for CoverageKind {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                CoverageKind::Functions => "functions",
-                CoverageKind::Lines => "lines",
-                CoverageKind::Branches => "branches",
-            }
-        )
-    }
-}
-
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None, name = "Benchmark coverage script")]
 pub struct CliArgs {
+    /// TOML run file providing defaults for all other arguments, e.g. the `run_config`
+    /// entry of the config table of an existing report DB
+    #[arg(long = "config", value_name = "RUN_FILE")]
+    pub run_file: Option<PathBuf>,
+
     /// Repository directory
     #[arg(long = "repo")]
-    pub repo_dir: PathBuf,
+    pub repo_dir: Option<PathBuf>,
 
-    /// Number of parallel jobs
-    #[arg(short = 'j', long, default_value_t = 1)]
-    pub job_size: usize,
+    /// Number of parallel jobs [default: 1]
+    #[arg(short = 'j', long)]
+    pub job_size: Option<usize>,
 
     /// Verbose output
-    #[arg(short = 'v', long, action = clap::ArgAction::SetTrue)]
-    pub verbose: bool,
+    #[arg(
+        short = 'v',
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub verbose: Option<bool>,
+
+    /// Additional log file that is being logged to [default: ./output.log]
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
 
-    /// Additional log file that is being logged to
-    #[arg(long, default_value = "./output.log")]
-    pub log_file: PathBuf,
+    /// Write a timeline of the run in the Chrome trace-event format to this file
+    #[arg(long)]
+    pub trace_out: Option<PathBuf>,
 
     /// Executable (with args) to call
     #[arg(short, long)]
-    pub exec: String,
+    pub exec: Option<String>,
 
     /// Database which will contain the benchmark results
-    pub result_db: PathBuf,
+    pub result_db: Option<PathBuf>,
+
+    /// Number of times a failed benchmark run is repeated, every attempt is recorded
+    /// [default: 0]
+    #[arg(long)]
+    pub retries: Option<u32>,
+
+    /// Outcomes of a benchmark run that are retried [default: signal]
+    #[arg(long, value_delimiter = ',')]
+    pub retry_on: Option<Vec<RetryOutcome>>,
+
+    /// Wall clock limit of a single benchmark run in milliseconds, enforced by the runner
+    #[arg(long, value_name = "MS")]
+    pub timeout: Option<u64>,
+
+    /// Signal sent to a run exceeding the timeout, it has to make the executable exit
+    /// through exit() for the coverage data to be written [default: int]
+    #[arg(long)]
+    pub timeout_signal: Option<TimeoutSignal>,
+
+    /// Milliseconds a run gets to exit after the timeout signal before it is killed
+    /// [default: 1000]
+    #[arg(long, value_name = "MS")]
+    pub timeout_grace: Option<u64>,
+
+    /// Free megabytes to keep on the filesystems of the temporary data and the result DB.
+    /// Workers are throttled below twice this value, the run stops with the results
+    /// collected so far below it, 0 disables the check [default: 1024]
+    #[arg(long, value_name = "MB")]
+    pub min_free_mb: Option<u64>,
+
+    /// Same as --min-free-mb for the free inodes [default: 10000]
+    #[arg(long)]
+    pub min_free_inodes: Option<u64>,
+
+    /// Pin every worker and its benchmark runs to a dedicated physical core (using a single
+    /// hyperthread of it), for timings that are comparable between runs
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub pin_cpus: Option<bool>,
 
     #[command(subcommand)]
-    pub command: Commands,
+    pub command: Option<Commands>,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Benchmark coverage script.
     Coverage {
-        /// Kinds of code elements for which usage data will be collected
+        /// Kinds of code elements for which usage data will be collected [default: functions]
+        #[arg(short = 'k', long, value_delimiter = ',')]
+        coverage_kinds: Option<Vec<CoverageKind>>,
+
+        /// Use individual GCOV prefixes for each run
         #[arg(
-            short = 'k',
-            long,
-            // default_value = "functions,branches,lines",
-            default_value = "functions",
-            value_delimiter = ','
+            short = 'p',
+            long = "use-prefixes",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "true"
         )]
-        coverage_kinds: Vec<CoverageKind>,
+        individual_prefixes: Option<bool>,
 
-        /// Use individual GCOV prefixes for each run
-        #[arg(short='p', long="use-prefixes", action = clap::ArgAction::SetTrue)]
-        individual_prefixes: bool,
+        /// Use one GCOV prefix per worker, which is reset after every run, instead of one
+        /// per benchmark (implies --use-prefixes)
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        worker_prefixes: Option<bool>,
 
         /// Don't filter out outside libraries from coverage analysis
-        #[arg(long, action = clap::ArgAction::SetTrue)]
-        no_ignore_libs: bool,
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        no_ignore_libs: Option<bool>,
 
         /// Temporary directory where the GCOV outputs are stored
         #[arg(long, default_value = None)]
@@ -192,7 +151,58 @@ pub enum Commands {
         /// Benchmark file pattern, must contain a path to the benchmark directory,
         /// followed by a pattern e.g. /home/user/benchmarks/non-incremental/**/*.smt2
         #[arg(short, long)]
-        benchmarks: String,
+        benchmarks: Option<String>,
+
+        /// Named option set the executable is run with, e.g. "incremental=--incremental".
+        /// Can be passed multiple times, every benchmark is run once per configuration.
+        /// Defaults to a single configuration "default" without additional arguments
+        #[arg(short = 'c', long = "configuration", value_name = "NAME=ARGS")]
+        configurations: Vec<Configuration>,
+
+        /// Number of randomly sampled benchmarks that are run repeatedly to detect functions
+        /// with nondeterministic usage [default: 0]
+        #[arg(long)]
+        stability_samples: Option<usize>,
+
+        /// Number of runs of every sampled benchmark, including the regular one [default: 3]
+        #[arg(long)]
+        stability_runs: Option<u32>,
+
+        /// Seed of the benchmark sampling [default: 0]
+        #[arg(long)]
+        stability_seed: Option<u64>,
+
+        /// Also collect the coverage of failed and timed out runs, it is stored separately
+        /// from the coverage of successful runs
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        collect_failed: Option<bool>,
+
+        /// Also count the blocks of every function executed by any benchmark, by merging
+        /// the gcda data of all successful runs
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+        block_union: Option<bool>,
+
+        /// Executable (with args) to pre-screen all benchmarks with, e.g. a production build.
+        /// Only benchmark runs that succeed within the screening timeout are measured
+        #[arg(long)]
+        screen_exec: Option<String>,
+
+        /// Wall clock limit of a screening run in milliseconds [default: --timeout]
+        #[arg(long, value_name = "MS", requires = "screen_exec")]
+        screen_timeout: Option<u64>,
+
+        /// How usage data is collected, `ptrace` places breakpoints on the function entries
+        /// of an uninstrumented executable instead of reading gcov data [default: gcov]
+        #[arg(long)]
+        backend: Option<CoverageBackend>,
+
+        /// Binary or shared library whose functions are traced by the ptrace backend, can be
+        /// passed multiple times [default: the executable]
+        #[arg(long = "traced-object", value_name = "PATH")]
+        traced_objects: Vec<PathBuf>,
+
+        #[command(flatten)]
+        dry_run: DryRunArgs,
     },
 
     /// Benchmark evaluation script.
@@ -200,5 +210,189 @@ pub enum Commands {
         #[arg(long, default_value = None)]
         /// ID used in table name to easily identify the result table
         id: Option<String>,
+
+        #[command(flatten)]
+        dry_run: DryRunArgs,
     },
+
+    /// Upgrades the result DB written by an older gen_coverage in place to the current schema
+    /// version, as expected by code_remover and the optimizer
+    Migrate,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DryRunArgs {
+    /// Only resolve and print the plan of the run, without running anything or creating the DB
+    #[arg(long = "dry-run", action = clap::ArgAction::SetTrue)]
+    pub enabled: bool,
+
+    /// Report DB with prior timings for the runtime estimate of a dry run, defaults to the
+    /// result DB if it exists
+    #[arg(long, requires = "enabled")]
+    pub timings: Option<PathBuf>,
+
+    /// Additionally write the plan of a dry run as JSON to this file
+    #[arg(long, requires = "enabled")]
+    pub plan_out: Option<PathBuf>,
+}
+
+impl CliArgs {
+    /// Dry run options of the selected subcommand, if a dry run was requested
+    pub fn dry_run(&self) -> Option<DryRunArgs> {
+        match &self.command {
+            Some(Commands::Coverage { dry_run, .. }) | Some(Commands::Evaluate { dry_run, .. }) => {
+                Some(dry_run.clone()).filter(|d| d.enabled)
+            }
+            Some(Commands::Migrate) | None => None,
+        }
+    }
+
+    /// Whether the result DB is only to be upgraded
+    pub fn is_migrate(&self) -> bool {
+        matches!(self.command, Some(Commands::Migrate))
+    }
+
+    /// Combines the run file (if any) with the command line arguments, the latter take
+    /// precedence
+    pub fn into_run_file(self) -> ResultT<RunFile> {
+        let base = match &self.run_file {
+            Some(path) => RunFile::load(path)?,
+            None => RunFile::default(),
+        };
+        Ok(base.merge(self.overrides()))
+    }
+
+    /// The values set on the command line. Flags take an optional value (`--pin-cpus=false`),
+    /// so they can also switch off what a run file switched on.
+    fn overrides(self) -> RunFile {
+        let (coverage, evaluate) = match self.command {
+            Some(Commands::Coverage {
+                coverage_kinds,
+                individual_prefixes,
+                worker_prefixes,
+                no_ignore_libs,
+                tmp_dir,
+                track_all,
+                benchmarks,
+                configurations,
+                stability_samples,
+                stability_runs,
+                stability_seed,
+                collect_failed,
+                block_union,
+                screen_exec,
+                screen_timeout,
+                backend,
+                traced_objects,
+                ..
+            }) => (
+                Some(CoverageFile {
+                    benchmarks,
+                    coverage_kinds,
+                    use_prefixes: individual_prefixes,
+                    worker_prefixes,
+                    no_ignore_libs,
+                    tmp_dir,
+                    track_all,
+                    configurations: (!configurations.is_empty())
+                        .then(|| configurations.iter().map(|c| c.to_string()).collect()),
+                    stability_samples,
+                    stability_runs,
+                    stability_seed,
+                    collect_failed,
+                    block_union,
+                    screen_exec,
+                    screen_timeout_ms: screen_timeout,
+                    backend,
+                    traced_objects: (!traced_objects.is_empty()).then_some(traced_objects),
+                }),
+                None,
+            ),
+            Some(Commands::Evaluate { id, .. }) => (None, Some(EvaluateFile { id })),
+            Some(Commands::Migrate) | None => (None, None),
+        };
+
+        RunFile {
+            repo: self.repo_dir,
+            jobs: self.job_size,
+            verbose: self.verbose,
+            log_file: self.log_file,
+            exec: self.exec,
+            result_db: self.result_db,
+            retries: self.retries,
+            retry_on: self.retry_on,
+            timeout_ms: self.timeout,
+            timeout_signal: self.timeout_signal,
+            timeout_grace_ms: self.timeout_grace,
+            min_free_mb: self.min_free_mb,
+            min_free_inodes: self.min_free_inodes,
+            pin_cpus: self.pin_cpus,
+            coverage,
+            evaluate,
+        }
+    }
+}
+
+/// Creates a fresh temporary directory below /tmp/coverage_reports
+pub fn default_tmp_dir() -> ResultT<PathBuf> {
+    let tmp_base_dir = Path::new("/tmp/coverage_reports");
+    if !tmp_base_dir.exists() {
+        create_dir(tmp_base_dir)?;
+    }
+    let tmp_dir = Temp::new_dir_in(tmp_base_dir)?.release();
+    info!(
+        "Using temp directory '{}' for intermediate gcov results",
+        tmp_dir.display()
+    );
+    Ok(tmp_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(args: &[&str]) -> RunFile {
+        let args = ["gen_coverage"].iter().chain(args);
+        CliArgs::try_parse_from(args).unwrap().overrides()
+    }
+
+    #[test]
+    fn flags_override_run_file() {
+        let base = RunFile::parse(
+            "pin_cpus = true
+            [coverage]
+            use_prefixes = true
+            block_union = false",
+        )
+        .unwrap();
+
+        let merged = base.clone().merge(overrides(&[
+            "--pin-cpus=false",
+            "coverage",
+            "--use-prefixes=false",
+            "--block-union",
+        ]));
+        assert_eq!(merged.pin_cpus, Some(false));
+        let coverage = merged.coverage.unwrap();
+        assert_eq!(coverage.use_prefixes, Some(false));
+        assert_eq!(coverage.block_union, Some(true));
+
+        // Flags that are not passed keep the value of the run file
+        let merged = base.merge(overrides(&["coverage"]));
+        assert_eq!(merged.pin_cpus, Some(true));
+        let coverage = merged.coverage.unwrap();
+        assert_eq!(coverage.use_prefixes, Some(true));
+        assert_eq!(coverage.block_union, Some(false));
+    }
+
+    #[test]
+    fn flags_without_value_are_set() {
+        let run_file = overrides(&["-v", "--pin-cpus", "coverage", "-p", "--collect-failed"]);
+        assert_eq!(run_file.verbose, Some(true));
+        assert_eq!(run_file.pin_cpus, Some(true));
+        let coverage = run_file.coverage.unwrap();
+        assert_eq!(coverage.use_prefixes, Some(true));
+        assert_eq!(coverage.collect_failed, Some(true));
+        assert_eq!(coverage.worker_prefixes, None);
+    }
 }
@@ -0,0 +1,889 @@
+use crate::types::{Configuration, ResultT};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverageKind {
+    Functions,
+    Branches,
+    Lines,
+    /// MC/DC coverage of the conditions of every decision, requires `-fcondition-coverage`
+    Conditions,
+}
+
+impl fmt::Display for CoverageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CoverageKind::Functions => "functions",
+                CoverageKind::Lines => "lines",
+                CoverageKind::Branches => "branches",
+                CoverageKind::Conditions => "conditions",
+            }
+        )
+    }
+}
+
+/// How the usage data of a coverage run is collected
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverageBackend {
+    /// gcov data written by a binary built with `--coverage`
+    #[default]
+    Gcov,
+    /// One-shot breakpoints on every function entry of an uninstrumented binary, only
+    /// supports function coverage
+    Ptrace,
+}
+
+impl fmt::Display for CoverageBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                CoverageBackend::Gcov => "gcov",
+                CoverageBackend::Ptrace => "ptrace",
+            }
+        )
+    }
+}
+
+/// Outcome of a benchmark run that can be retried
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetryOutcome {
+    /// Terminated by a signal, e.g. a crash or a SIGKILL under memory pressure
+    Signal,
+    /// Exited with a non-zero exit code
+    Error,
+}
+
+impl fmt::Display for RetryOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                RetryOutcome::Signal => "signal",
+                RetryOutcome::Error => "error",
+            }
+        )
+    }
+}
+
+/// Which failed benchmark runs get repeated, and how often
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of additional attempts per benchmark run
+    pub retries: u32,
+    pub retry_on: Vec<RetryOutcome>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 0,
+            retry_on: vec![RetryOutcome::Signal],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a run with the given outcome should be attempted again
+    pub fn should_retry(&self, attempt: u32, exit_code: i32, signal: Option<i32>) -> bool {
+        if attempt > self.retries {
+            return false;
+        }
+        match signal {
+            Some(_) => self.retry_on.contains(&RetryOutcome::Signal),
+            None => exit_code != 0 && self.retry_on.contains(&RetryOutcome::Error),
+        }
+    }
+}
+
+/// Signal sent to a benchmark run that exceeds its timeout
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutSignal {
+    Int,
+    Term,
+    Hup,
+    Usr1,
+    Usr2,
+    Kill,
+}
+
+impl TimeoutSignal {
+    pub fn number(&self) -> i32 {
+        match self {
+            TimeoutSignal::Int => libc::SIGINT,
+            TimeoutSignal::Term => libc::SIGTERM,
+            TimeoutSignal::Hup => libc::SIGHUP,
+            TimeoutSignal::Usr1 => libc::SIGUSR1,
+            TimeoutSignal::Usr2 => libc::SIGUSR2,
+            TimeoutSignal::Kill => libc::SIGKILL,
+        }
+    }
+}
+
+impl fmt::Display for TimeoutSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                TimeoutSignal::Int => "int",
+                TimeoutSignal::Term => "term",
+                TimeoutSignal::Hup => "hup",
+                TimeoutSignal::Usr1 => "usr1",
+                TimeoutSignal::Usr2 => "usr2",
+                TimeoutSignal::Kill => "kill",
+            }
+        )
+    }
+}
+
+/// Wall clock limit of a single benchmark run, enforced by the runner
+#[derive(Debug, Clone)]
+pub struct TimeoutPolicy {
+    pub limit_ms: u64,
+    /// Sent once the limit is exceeded. An instrumented binary only writes its gcda data if
+    /// it exits through `exit()` on this signal, so pick one the executable handles
+    pub signal: TimeoutSignal,
+    /// Time the run gets to exit after the signal, before it is killed
+    pub grace_ms: u64,
+}
+
+/// Free space that has to remain on the filesystems of the temporary data and the report DB.
+/// Below twice these limits only a single worker keeps running, below them the run stops
+/// and writes the results collected so far.
+#[derive(Debug, Clone)]
+pub struct DiskPolicy {
+    /// Free megabytes, the report DB of a coverage run still has to be written on top of it.
+    /// 0 disables the check.
+    pub min_free_mb: u64,
+    /// Free inodes, 0 disables the check
+    pub min_free_inodes: u64,
+}
+
+impl Default for DiskPolicy {
+    fn default() -> Self {
+        DiskPolicy {
+            min_free_mb: 1024,
+            min_free_inodes: 10000,
+        }
+    }
+}
+
+/// Settings of the nondeterminism detection, which re-runs a random subset of the benchmarks
+/// to compare their used functions
+#[derive(Debug, Clone)]
+pub struct StabilityConfig {
+    /// Number of sampled benchmarks
+    pub samples: usize,
+    /// Number of runs of every sampled benchmark, including the regular one
+    pub runs: u32,
+    /// Seed of the benchmark sampling
+    pub seed: u64,
+}
+
+/// Pre-screening of all benchmarks with a faster build of the executable, e.g. a production
+/// build. Only the benchmark runs passing it are measured by the coverage run.
+#[derive(Debug, Clone)]
+pub struct ScreeningConfig {
+    /// Executable (with args) of the screening runs, '{}' is replaced by the benchmark file
+    pub exec: String,
+    /// Wall clock limit of a screening run, defaults to the timeout of the run
+    pub timeout_ms: Option<u64>,
+}
+
+/// Settings of a coverage run, which creates a new report DB
+#[derive(Debug, Clone)]
+pub struct CoverageConfig {
+    /// Benchmark file pattern, e.g. /home/user/benchmarks/non-incremental/**/*.smt2
+    pub benchmarks: String,
+    /// Kinds of code elements for which usage data will be collected
+    pub coverage_kinds: Vec<CoverageKind>,
+    /// Use individual GCOV prefixes for each run
+    pub individual_prefixes: bool,
+    /// Every worker reuses a single GCOV prefix, whose gcda files are reset after each run,
+    /// instead of one prefix per benchmark. Implies `individual_prefixes`.
+    pub worker_prefixes: bool,
+    /// Don't filter out outside libraries from coverage analysis
+    pub no_ignore_libs: bool,
+    /// Temporary directory where the GCOV outputs are stored
+    pub tmp_dir: PathBuf,
+    /// Also track unused functions and lines
+    pub track_unused: bool,
+    /// Named option sets every benchmark is run with, an empty list means a single default
+    /// configuration without additional arguments
+    pub configurations: Vec<Configuration>,
+    /// Nondeterminism detection, disabled if not set
+    pub stability: Option<StabilityConfig>,
+    /// Also collect the coverage of runs that failed or timed out, it is stored separately
+    /// from the coverage of successful runs
+    pub collect_failed: bool,
+    /// Merge the gcda data of all successful runs to count the blocks of every function
+    /// executed by any benchmark, requires `gcov-tool` when prefixes are used
+    pub block_union: bool,
+    /// Pre-screening of the benchmarks, disabled if not set
+    pub screening: Option<ScreeningConfig>,
+    pub backend: CoverageBackend,
+    /// Binaries and shared libraries whose functions are traced by the ptrace backend,
+    /// defaults to the executable
+    pub traced_objects: Vec<PathBuf>,
+}
+
+/// Settings of an evaluation run, which appends a new result table to an existing report DB
+#[derive(Debug, Clone, Default)]
+pub struct EvaluateConfig {
+    /// ID used in table name to easily identify the result table
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RunMode {
+    Coverage(CoverageConfig),
+    Evaluate(EvaluateConfig),
+}
+
+/// Everything a `Runner` and `DbWriter` need to know about a run
+#[derive(Debug, Clone)]
+pub struct RunConfig {
+    /// Repository directory of the measured executable
+    pub repo_dir: PathBuf,
+    /// Number of parallel jobs
+    pub job_size: usize,
+    /// Executable (with args) to call, '{}' is replaced by the benchmark file
+    pub exec: String,
+    /// Database which will contain the benchmark results
+    pub result_db: PathBuf,
+    pub mode: RunMode,
+    /// Retry policy for failed benchmark runs, no retries by default
+    pub retry: RetryPolicy,
+    /// Timeout of a single benchmark run, runs are not limited by default
+    pub timeout: Option<TimeoutPolicy>,
+    /// Free disk space below which workers are throttled and the run is stopped
+    pub disk: DiskPolicy,
+    /// Pin every worker, and the runs it starts, to a physical core of its own
+    pub pin_cpus: bool,
+
+    exec_template: Vec<String>,
+    result_table: String,
+}
+
+impl RunConfig {
+    pub fn new(
+        repo_dir: PathBuf,
+        job_size: usize,
+        exec: String,
+        result_db: PathBuf,
+        mut mode: RunMode,
+    ) -> ResultT<Self> {
+        if job_size == 0 {
+            return Err(Box::from("Expected at least one job"));
+        }
+        if !exec.contains("{}") {
+            return Err(Box::from(
+                "Could not find '{}' in exec arg, use this as a placeholder for the benchmark file argument",
+            ));
+        }
+        let exec_template = shellwords::split(&exec)?;
+
+        if let RunMode::Coverage(coverage) = &mut mode {
+            coverage.configurations = normalize_configurations(&coverage.configurations)?;
+            if coverage.stability.as_ref().is_some_and(|s| s.runs < 2) {
+                return Err(Box::from(
+                    "Nondeterminism detection needs at least two runs per sampled benchmark",
+                ));
+            }
+            if coverage.backend == CoverageBackend::Ptrace {
+                if cfg!(not(target_arch = "x86_64")) {
+                    return Err(Box::from("The ptrace backend is only supported on x86_64"));
+                }
+                if coverage
+                    .coverage_kinds
+                    .iter()
+                    .any(|k| *k != CoverageKind::Functions)
+                {
+                    return Err(Box::from(
+                        "The ptrace backend only supports function coverage",
+                    ));
+                }
+                if coverage.block_union {
+                    return Err(Box::from("The ptrace backend does not count blocks"));
+                }
+                if coverage.traced_objects.is_empty() {
+                    coverage
+                        .traced_objects
+                        .push(PathBuf::from(&exec_template[0]));
+                }
+            }
+            if let Some(screening) = &coverage.screening {
+                if !shellwords::split(&screening.exec)?
+                    .iter()
+                    .any(|a| a == "{}")
+                {
+                    return Err(Box::from(
+                        "Could not find '{}' in the screening exec arg, use this as a placeholder for the benchmark file argument",
+                    ));
+                }
+            }
+        }
+
+        let result_table = match &mode {
+            RunMode::Evaluate(EvaluateConfig { id }) => {
+                let epoch_time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards");
+                match id {
+                    Some(v) if !v.is_empty() => {
+                        format!("evaluation_benchmarks_{}_{}", v, epoch_time.as_millis())
+                    }
+                    _ => format!("evaluation_benchmarks_{}", epoch_time.as_millis()),
+                }
+            }
+            RunMode::Coverage(_) => "result_benchmarks".to_string(),
+        };
+
+        Ok(RunConfig {
+            repo_dir,
+            job_size,
+            exec,
+            result_db,
+            mode,
+            retry: RetryPolicy::default(),
+            timeout: None,
+            disk: DiskPolicy::default(),
+            pin_cpus: false,
+            exec_template,
+            result_table,
+        })
+    }
+
+    /// The exec command split into its arguments, still containing the '{}' placeholder
+    pub fn exec_template(&self) -> &[String] {
+        &self.exec_template
+    }
+
+    /// Name of the table the benchmark run results are written to
+    pub fn result_table(&self) -> &str {
+        &self.result_table
+    }
+
+    pub fn coverage(&self) -> Option<&CoverageConfig> {
+        match &self.mode {
+            RunMode::Coverage(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Directory compressed benchmarks are decompressed into before they are run
+    pub fn scratch_dir(&self) -> PathBuf {
+        match self.coverage() {
+            Some(coverage) => coverage.tmp_dir.join("scratch"),
+            None => {
+                std::env::temp_dir().join(format!("gen_coverage_scratch_{}", std::process::id()))
+            }
+        }
+    }
+
+    /// Directory holding all temporary data of the run, it is removed once the run ends
+    pub fn run_tmp_dir(&self) -> PathBuf {
+        match self.coverage() {
+            Some(coverage) => coverage.tmp_dir.clone(),
+            None => self.scratch_dir(),
+        }
+    }
+
+    pub fn is_coverage(&self) -> bool {
+        self.coverage().is_some()
+    }
+
+    pub fn track_unused(&self) -> bool {
+        self.coverage().is_some_and(|c| c.track_unused)
+    }
+
+    /// GCOV prefix reused by all runs of a worker, if prefixes are kept per worker
+    pub fn worker_prefix(&self, worker: usize) -> Option<PathBuf> {
+        self.coverage()
+            .filter(|c| c.worker_prefixes)
+            .map(|c| c.tmp_dir.join("workers").join(worker.to_string()))
+    }
+
+    /// Configuration of the screening runs, which use the screening executable and timeout
+    /// but are otherwise run like evaluation runs, without retries
+    pub fn screening_config(&self) -> Option<RunConfig> {
+        let screening = self.coverage()?.screening.as_ref()?;
+        let timeout_ms = screening
+            .timeout_ms
+            .or(self.timeout.as_ref().map(|t| t.limit_ms));
+        Some(RunConfig {
+            exec: screening.exec.clone(),
+            // Validated when the config was created
+            exec_template: shellwords::split(&screening.exec).unwrap_or_default(),
+            mode: RunMode::Evaluate(EvaluateConfig::default()),
+            retry: RetryPolicy::default(),
+            // Runs of an uninstrumented build don't need to exit gracefully
+            timeout: timeout_ms.map(|limit_ms| TimeoutPolicy {
+                limit_ms,
+                signal: TimeoutSignal::Kill,
+                grace_ms: 0,
+            }),
+            ..self.clone()
+        })
+    }
+
+    pub fn track_funcs(&self) -> bool {
+        self.tracks(CoverageKind::Functions)
+    }
+
+    pub fn track_lines(&self) -> bool {
+        self.tracks(CoverageKind::Lines)
+    }
+
+    pub fn track_branches(&self) -> bool {
+        self.tracks(CoverageKind::Branches)
+    }
+
+    pub fn track_conditions(&self) -> bool {
+        self.tracks(CoverageKind::Conditions)
+    }
+
+    fn tracks(&self, kind: CoverageKind) -> bool {
+        self.coverage()
+            .is_some_and(|c| c.coverage_kinds.contains(&kind))
+    }
+}
+
+impl RunConfig {
+    /// The fully resolved configuration in the run file format, running it again with a new
+    /// result DB reproduces this run. The result DB and the temporary directory are left out,
+    /// as the former exists by then and the latter is removed at the end of the run.
+    pub fn to_run_file(&self) -> RunFile {
+        let (coverage, evaluate) = match &self.mode {
+            RunMode::Coverage(c) => (
+                Some(CoverageFile {
+                    benchmarks: Some(c.benchmarks.clone()),
+                    coverage_kinds: Some(c.coverage_kinds.clone()),
+                    use_prefixes: Some(c.individual_prefixes),
+                    worker_prefixes: Some(c.worker_prefixes),
+                    no_ignore_libs: Some(c.no_ignore_libs),
+                    tmp_dir: None,
+                    track_all: Some(c.track_unused),
+                    configurations: Some(c.configurations.iter().map(|c| c.to_string()).collect()),
+                    stability_samples: c.stability.as_ref().map(|s| s.samples),
+                    stability_runs: c.stability.as_ref().map(|s| s.runs),
+                    stability_seed: c.stability.as_ref().map(|s| s.seed),
+                    collect_failed: Some(c.collect_failed),
+                    block_union: Some(c.block_union),
+                    screen_exec: c.screening.as_ref().map(|s| s.exec.clone()),
+                    screen_timeout_ms: c.screening.as_ref().and_then(|s| s.timeout_ms),
+                    backend: Some(c.backend),
+                    traced_objects: Some(c.traced_objects.clone()),
+                }),
+                None,
+            ),
+            RunMode::Evaluate(e) => (None, Some(EvaluateFile { id: e.id.clone() })),
+        };
+
+        RunFile {
+            repo: Some(self.repo_dir.clone()),
+            jobs: Some(self.job_size),
+            verbose: None,
+            log_file: None,
+            exec: Some(self.exec.clone()),
+            result_db: None,
+            retries: Some(self.retry.retries),
+            retry_on: Some(self.retry.retry_on.clone()),
+            timeout_ms: self.timeout.as_ref().map(|t| t.limit_ms),
+            timeout_signal: self.timeout.as_ref().map(|t| t.signal),
+            timeout_grace_ms: self.timeout.as_ref().map(|t| t.grace_ms),
+            min_free_mb: Some(self.disk.min_free_mb),
+            min_free_inodes: Some(self.disk.min_free_inodes),
+            pin_cpus: Some(self.pin_cpus),
+            coverage,
+            evaluate,
+        }
+    }
+}
+
+/// TOML representation of a run, every field is optional so a file can be partial and be
+/// combined with command line arguments
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunFile {
+    pub repo: Option<PathBuf>,
+    pub jobs: Option<usize>,
+    pub verbose: Option<bool>,
+    pub log_file: Option<PathBuf>,
+    pub exec: Option<String>,
+    pub result_db: Option<PathBuf>,
+    pub retries: Option<u32>,
+    pub retry_on: Option<Vec<RetryOutcome>>,
+    pub timeout_ms: Option<u64>,
+    pub timeout_signal: Option<TimeoutSignal>,
+    pub timeout_grace_ms: Option<u64>,
+    pub min_free_mb: Option<u64>,
+    pub min_free_inodes: Option<u64>,
+    pub pin_cpus: Option<bool>,
+
+    pub coverage: Option<CoverageFile>,
+    pub evaluate: Option<EvaluateFile>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoverageFile {
+    pub benchmarks: Option<String>,
+    pub coverage_kinds: Option<Vec<CoverageKind>>,
+    pub use_prefixes: Option<bool>,
+    pub worker_prefixes: Option<bool>,
+    pub no_ignore_libs: Option<bool>,
+    pub tmp_dir: Option<PathBuf>,
+    pub track_all: Option<bool>,
+    /// Configurations in the "NAME=ARGS" format
+    pub configurations: Option<Vec<String>>,
+    pub stability_samples: Option<usize>,
+    pub stability_runs: Option<u32>,
+    pub stability_seed: Option<u64>,
+    pub collect_failed: Option<bool>,
+    pub block_union: Option<bool>,
+    pub screen_exec: Option<String>,
+    pub screen_timeout_ms: Option<u64>,
+    pub backend: Option<CoverageBackend>,
+    pub traced_objects: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EvaluateFile {
+    pub id: Option<String>,
+}
+
+impl RunFile {
+    pub fn load(path: &Path) -> ResultT<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read run file '{}': {}", path.display(), e))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> ResultT<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    pub fn to_toml(&self) -> ResultT<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Combines two run files, values set in `overrides` take precedence. A subcommand
+    /// selected in `overrides` discards the section of the other subcommand.
+    pub fn merge(self, overrides: RunFile) -> RunFile {
+        let (coverage, evaluate) = match (overrides.coverage, overrides.evaluate) {
+            (Some(c), None) => (Some(self.coverage.unwrap_or_default().merge(c)), None),
+            (None, Some(e)) => (None, Some(self.evaluate.unwrap_or_default().merge(e))),
+            (None, None) => (self.coverage, self.evaluate),
+            (Some(c), Some(e)) => (
+                Some(self.coverage.unwrap_or_default().merge(c)),
+                Some(self.evaluate.unwrap_or_default().merge(e)),
+            ),
+        };
+
+        RunFile {
+            repo: overrides.repo.or(self.repo),
+            jobs: overrides.jobs.or(self.jobs),
+            verbose: overrides.verbose.or(self.verbose),
+            log_file: overrides.log_file.or(self.log_file),
+            exec: overrides.exec.or(self.exec),
+            result_db: overrides.result_db.or(self.result_db),
+            retries: overrides.retries.or(self.retries),
+            retry_on: overrides.retry_on.or(self.retry_on),
+            timeout_ms: overrides.timeout_ms.or(self.timeout_ms),
+            timeout_signal: overrides.timeout_signal.or(self.timeout_signal),
+            timeout_grace_ms: overrides.timeout_grace_ms.or(self.timeout_grace_ms),
+            min_free_mb: overrides.min_free_mb.or(self.min_free_mb),
+            min_free_inodes: overrides.min_free_inodes.or(self.min_free_inodes),
+            pin_cpus: overrides.pin_cpus.or(self.pin_cpus),
+            coverage,
+            evaluate,
+        }
+    }
+
+    /// Fills in the defaults of all unset values and validates the result. `tmp_dir` is only
+    /// called if a coverage run does not specify a temporary directory.
+    pub fn into_run_config(self, tmp_dir: impl FnOnce() -> ResultT<PathBuf>) -> ResultT<RunConfig> {
+        let mode = match (self.coverage, self.evaluate) {
+            (Some(c), None) => {
+                let configurations = c
+                    .configurations
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|c| c.parse())
+                    .collect::<Result<Vec<Configuration>, String>>()?;
+                RunMode::Coverage(CoverageConfig {
+                    benchmarks: c
+                        .benchmarks
+                        .ok_or("Missing benchmark pattern of the coverage run")?,
+                    coverage_kinds: c
+                        .coverage_kinds
+                        .unwrap_or_else(|| vec![CoverageKind::Functions]),
+                    individual_prefixes: c.use_prefixes.unwrap_or(false)
+                        || c.worker_prefixes.unwrap_or(false),
+                    worker_prefixes: c.worker_prefixes.unwrap_or(false),
+                    no_ignore_libs: c.no_ignore_libs.unwrap_or(false),
+                    tmp_dir: match c.tmp_dir {
+                        Some(tmp_dir) => tmp_dir,
+                        None => tmp_dir()?,
+                    },
+                    track_unused: c.track_all.unwrap_or(false),
+                    configurations,
+                    stability: match c.stability_samples {
+                        Some(samples) if samples > 0 => Some(StabilityConfig {
+                            samples,
+                            runs: c.stability_runs.unwrap_or(3),
+                            seed: c.stability_seed.unwrap_or(0),
+                        }),
+                        _ => None,
+                    },
+                    collect_failed: c.collect_failed.unwrap_or(false),
+                    block_union: c.block_union.unwrap_or(false),
+                    screening: c.screen_exec.map(|exec| ScreeningConfig {
+                        exec,
+                        timeout_ms: c.screen_timeout_ms,
+                    }),
+                    backend: c.backend.unwrap_or_default(),
+                    traced_objects: c.traced_objects.unwrap_or_default(),
+                })
+            }
+            (None, Some(e)) => RunMode::Evaluate(EvaluateConfig { id: e.id }),
+            (None, None) => {
+                return Err(Box::from(
+                    "Missing subcommand, expected either 'coverage' or 'evaluate'",
+                ))
+            }
+            (Some(_), Some(_)) => {
+                return Err(Box::from(
+                    "Run file contains both a coverage and an evaluate section, select one via the subcommand",
+                ))
+            }
+        };
+
+        let mut config = RunConfig::new(
+            self.repo.ok_or("Missing repository directory")?,
+            self.jobs.unwrap_or(1),
+            self.exec.ok_or("Missing executable")?,
+            self.result_db.ok_or("Missing result DB")?,
+            mode,
+        )?;
+        let default_retry = RetryPolicy::default();
+        config.retry = RetryPolicy {
+            retries: self.retries.unwrap_or(default_retry.retries),
+            retry_on: self.retry_on.unwrap_or(default_retry.retry_on),
+        };
+        config.timeout = match self.timeout_ms {
+            Some(0) => return Err(Box::from("Expected a timeout of at least 1ms")),
+            Some(limit_ms) => Some(TimeoutPolicy {
+                limit_ms,
+                signal: self.timeout_signal.unwrap_or(TimeoutSignal::Int),
+                grace_ms: self.timeout_grace_ms.unwrap_or(1000),
+            }),
+            None => None,
+        };
+        let default_disk = DiskPolicy::default();
+        config.disk = DiskPolicy {
+            min_free_mb: self.min_free_mb.unwrap_or(default_disk.min_free_mb),
+            min_free_inodes: self.min_free_inodes.unwrap_or(default_disk.min_free_inodes),
+        };
+        config.pin_cpus = self.pin_cpus.unwrap_or(false);
+        Ok(config)
+    }
+}
+
+impl CoverageFile {
+    fn merge(self, overrides: CoverageFile) -> CoverageFile {
+        CoverageFile {
+            benchmarks: overrides.benchmarks.or(self.benchmarks),
+            coverage_kinds: overrides.coverage_kinds.or(self.coverage_kinds),
+            use_prefixes: overrides.use_prefixes.or(self.use_prefixes),
+            worker_prefixes: overrides.worker_prefixes.or(self.worker_prefixes),
+            no_ignore_libs: overrides.no_ignore_libs.or(self.no_ignore_libs),
+            tmp_dir: overrides.tmp_dir.or(self.tmp_dir),
+            track_all: overrides.track_all.or(self.track_all),
+            configurations: overrides.configurations.or(self.configurations),
+            stability_samples: overrides.stability_samples.or(self.stability_samples),
+            stability_runs: overrides.stability_runs.or(self.stability_runs),
+            stability_seed: overrides.stability_seed.or(self.stability_seed),
+            collect_failed: overrides.collect_failed.or(self.collect_failed),
+            block_union: overrides.block_union.or(self.block_union),
+            screen_exec: overrides.screen_exec.or(self.screen_exec),
+            screen_timeout_ms: overrides.screen_timeout_ms.or(self.screen_timeout_ms),
+            backend: overrides.backend.or(self.backend),
+            traced_objects: overrides.traced_objects.or(self.traced_objects),
+        }
+    }
+}
+
+impl EvaluateFile {
+    fn merge(self, overrides: EvaluateFile) -> EvaluateFile {
+        EvaluateFile {
+            id: overrides.id.or(self.id),
+        }
+    }
+}
+
+fn normalize_configurations(configurations: &[Configuration]) -> ResultT<Vec<Configuration>> {
+    if configurations.is_empty() {
+        return Ok(vec![Configuration::default()]);
+    }
+
+    let mut names: Vec<&String> = configurations.iter().map(|c| &c.name).collect();
+    names.sort();
+    names.dedup();
+    if names.len() != configurations.len() {
+        return Err(Box::from("Configuration names have to be unique"));
+    }
+
+    Ok(configurations
+        .iter()
+        .enumerate()
+        .map(|(i, c)| Configuration {
+            id: i as u64 + 1,
+            ..c.clone()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN_FILE: &str = r#"
+repo = "/repo"
+jobs = 4
+exec = "/repo/build/bin/cvc5 {}"
+result_db = "/reports/old.sqlite"
+pin_cpus = true
+
+[coverage]
+benchmarks = "/benchmarks/**/*.smt2"
+use_prefixes = true
+collect_failed = true
+configurations = ["default=", "incremental=--incremental"]
+"#;
+
+    #[test]
+    fn merge_prefers_overrides() {
+        let base = RunFile::parse(RUN_FILE).unwrap();
+        let merged = base.merge(RunFile {
+            jobs: Some(8),
+            result_db: Some("/reports/new.sqlite".into()),
+            pin_cpus: Some(false),
+            coverage: Some(CoverageFile {
+                collect_failed: Some(false),
+                track_all: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        assert_eq!(merged.jobs, Some(8));
+        assert_eq!(merged.result_db, Some("/reports/new.sqlite".into()));
+        assert_eq!(merged.pin_cpus, Some(false));
+        // Values not overridden are kept
+        assert_eq!(merged.repo, Some("/repo".into()));
+        assert_eq!(merged.exec.as_deref(), Some("/repo/build/bin/cvc5 {}"));
+
+        let coverage = merged.coverage.unwrap();
+        assert_eq!(coverage.collect_failed, Some(false));
+        assert_eq!(coverage.track_all, Some(true));
+        assert_eq!(coverage.use_prefixes, Some(true));
+        assert_eq!(
+            coverage.benchmarks.as_deref(),
+            Some("/benchmarks/**/*.smt2")
+        );
+        assert_eq!(coverage.configurations.map(|c| c.len()), Some(2));
+    }
+
+    #[test]
+    fn merge_without_overrides_keeps_file() {
+        let base = RunFile::parse(RUN_FILE).unwrap();
+        assert_eq!(base.clone().merge(RunFile::default()), base);
+    }
+
+    #[test]
+    fn merge_subcommand_discards_other_section() {
+        let base = RunFile::parse(RUN_FILE).unwrap();
+        let merged = base.merge(RunFile {
+            evaluate: Some(EvaluateFile {
+                id: Some("nightly".to_string()),
+            }),
+            ..Default::default()
+        });
+
+        assert_eq!(merged.coverage, None);
+        assert_eq!(merged.evaluate.unwrap().id.as_deref(), Some("nightly"));
+    }
+
+    #[test]
+    fn replayed_run_file_needs_new_db() {
+        let config = RunFile::parse(RUN_FILE)
+            .unwrap()
+            .into_run_config(|| Ok("/tmp/coverage_reports/run".into()))
+            .unwrap();
+        let replay = RunFile::parse(&config.to_run_file().to_toml().unwrap()).unwrap();
+
+        assert_eq!(replay.result_db, None);
+        assert_eq!(replay.coverage.as_ref().unwrap().tmp_dir, None);
+        assert_eq!(replay.pin_cpus, Some(true));
+
+        let replayed = replay
+            .merge(RunFile {
+                result_db: Some("/reports/replay.sqlite".into()),
+                ..Default::default()
+            })
+            .into_run_config(|| Ok("/tmp/coverage_reports/replay".into()))
+            .unwrap();
+        assert_eq!(replayed.result_db, PathBuf::from("/reports/replay.sqlite"));
+        assert_eq!(
+            replayed.coverage().unwrap().tmp_dir,
+            PathBuf::from("/tmp/coverage_reports/replay")
+        );
+        assert_eq!(replayed.to_run_file(), config.to_run_file());
+    }
+
+    #[test]
+    fn retry_policy_limits_attempts_and_outcomes() {
+        let policy = RetryPolicy {
+            retries: 2,
+            retry_on: vec![RetryOutcome::Signal],
+        };
+        // A crash is retried until the retries are used up
+        assert!(policy.should_retry(1, -1, Some(libc::SIGSEGV)));
+        assert!(policy.should_retry(2, -1, Some(libc::SIGSEGV)));
+        assert!(!policy.should_retry(3, -1, Some(libc::SIGSEGV)));
+        // Errors are only retried if asked for, successful runs never
+        assert!(!policy.should_retry(1, 1, None));
+        assert!(!policy.should_retry(1, 0, None));
+
+        let policy = RetryPolicy {
+            retries: 1,
+            retry_on: vec![RetryOutcome::Error],
+        };
+        assert!(policy.should_retry(1, 1, None));
+        assert!(!policy.should_retry(1, 0, None));
+        assert!(!policy.should_retry(1, -1, Some(libc::SIGABRT)));
+        assert!(!policy.should_retry(2, 1, None));
+
+        // Nothing is retried by default
+        assert!(!RetryPolicy::default().should_retry(1, -1, Some(libc::SIGSEGV)));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::runner::index::{ConditionId, FunctionId, IndexData, InstantiationId, LineId, SourceId};
+
+use rusqlite::{params, Connection};
+
+/// DB row ids of the entries of a `CoverageIndex`, rows are created on first use
+#[derive(Default)]
+pub(super) struct DbIds {
+    sources: Vec<Option<u64>>,
+    functions: Vec<Option<u64>>,
+    instantiations: Vec<Option<u64>>,
+    lines: Vec<Option<u64>>,
+    conditions: Vec<Option<u64>>,
+}
+
+fn cached(ids: &[Option<u64>], id: u32) -> Option<u64> {
+    ids.get(id as usize).copied().flatten()
+}
+
+fn store(ids: &mut Vec<Option<u64>>, id: u32, row_id: u64) -> u64 {
+    if ids.len() <= id as usize {
+        ids.resize(id as usize + 1, None);
+    }
+    ids[id as usize] = Some(row_id);
+    row_id
+}
+
+impl DbIds {
+    /// Row id of a function, if its row was already created
+    pub(super) fn existing_function(&self, id: FunctionId) -> Option<u64> {
+        cached(&self.functions, id)
+    }
+
+    pub(super) fn existing_instantiation(&self, id: InstantiationId) -> Option<u64> {
+        cached(&self.instantiations, id)
+    }
+
+    pub(super) fn source(
+        &mut self,
+        conn: &Connection,
+        index: &IndexData,
+        id: SourceId,
+    ) -> rusqlite::Result<u64> {
+        if let Some(row_id) = cached(&self.sources, id) {
+            return Ok(row_id);
+        }
+        let path = index.source(id);
+        conn.prepare_cached(
+            "INSERT INTO \"sources\" ( path ) VALUES ( ?1 ) ON CONFLICT DO NOTHING",
+        )?
+        .execute(params![path])?;
+        let row_id = conn
+            .prepare_cached("SELECT id FROM \"sources\" WHERE path = ?1")?
+            .query_row(params![path], |row| row.get(0))?;
+        Ok(store(&mut self.sources, id, row_id))
+    }
+
+    /// Row id of a function, a new row starts with a usage count of 0
+    pub(super) fn function(
+        &mut self,
+        conn: &Connection,
+        index: &IndexData,
+        id: FunctionId,
+    ) -> rusqlite::Result<u64> {
+        if let Some(row_id) = cached(&self.functions, id) {
+            return Ok(row_id);
+        }
+        let func = index.function(id);
+        let sid = self.source(conn, index, func.source)?;
+        // NOTE: As the function name also contains the parameter types,
+        // overloading kind of breaks the names and they should be used with care
+        conn.prepare_cached(
+            "INSERT INTO \"functions\" (
+                source_id,
+                name,
+                start_line,
+                start_col,
+                end_line,
+                end_col,
+                benchmark_usage_count
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0)
+            ON CONFLICT (source_id, start_line, start_col) DO NOTHING",
+        )?
+        .execute(params![
+            sid,
+            func.name,
+            func.start.line,
+            func.start.col,
+            func.end.line,
+            func.end.col
+        ])?;
+        let row_id = conn
+            .prepare_cached(
+                "SELECT id FROM \"functions\"
+                    WHERE source_id = ?1 AND start_line = ?2 AND start_col = ?3",
+            )?
+            .query_row(params![sid, func.start.line, func.start.col], |row| {
+                row.get(0)
+            })?;
+        Ok(store(&mut self.functions, id, row_id))
+    }
+
+    /// Row id of an instantiation, linked to the function row of its shared source range
+    pub(super) fn instantiation(
+        &mut self,
+        conn: &Connection,
+        index: &IndexData,
+        id: InstantiationId,
+    ) -> rusqlite::Result<u64> {
+        if let Some(row_id) = cached(&self.instantiations, id) {
+            return Ok(row_id);
+        }
+        let inst = index.instantiation(id);
+        let fid = self.function(conn, index, inst.function)?;
+        conn.prepare_cached(
+            "INSERT INTO \"function_instantiations\" (
+                function_id,
+                name,
+                demangled_name,
+                benchmark_usage_count
+            ) VALUES (?1, ?2, ?3, 0)
+            ON CONFLICT (function_id, name) DO NOTHING",
+        )?
+        .execute(params![fid, inst.name, inst.demangled_name])?;
+        let row_id = conn
+            .prepare_cached(
+                "SELECT id FROM \"function_instantiations\" WHERE function_id = ?1 AND name = ?2",
+            )?
+            .query_row(params![fid, inst.name], |row| row.get(0))?;
+        Ok(store(&mut self.instantiations, id, row_id))
+    }
+
+    pub(super) fn line(
+        &mut self,
+        conn: &Connection,
+        index: &IndexData,
+        id: LineId,
+    ) -> rusqlite::Result<u64> {
+        if let Some(row_id) = cached(&self.lines, id) {
+            return Ok(row_id);
+        }
+        let line = index.line(id);
+        let sid = self.source(conn, index, line.source)?;
+        let fid = match line.function {
+            Some(function) => Some(self.function(conn, index, function)?),
+            None => None,
+        };
+        conn.prepare_cached(
+            "INSERT INTO \"lines\" (
+                source_id,
+                line_no,
+                function_id,
+                benchmark_usage_count
+            ) VALUES (?1, ?2, ?3, 0)
+            ON CONFLICT (source_id, line_no) DO NOTHING",
+        )?
+        .execute(params![sid, line.line_no, fid])?;
+        let row_id = conn
+            .prepare_cached("SELECT id FROM \"lines\" WHERE source_id = ?1 AND line_no = ?2")?
+            .query_row(params![sid, line.line_no], |row| row.get(0))?;
+        Ok(store(&mut self.lines, id, row_id))
+    }
+
+    pub(super) fn condition(
+        &mut self,
+        conn: &Connection,
+        index: &IndexData,
+        id: ConditionId,
+    ) -> rusqlite::Result<u64> {
+        if let Some(row_id) = cached(&self.conditions, id) {
+            return Ok(row_id);
+        }
+        let cond = index.condition(id);
+        let sid = self.source(conn, index, cond.source)?;
+        let fid = match cond.function {
+            Some(function) => Some(self.function(conn, index, function)?),
+            None => None,
+        };
+        conn.prepare_cached(
+            "INSERT INTO \"conditions\" (
+                source_id,
+                line_no,
+                decision,
+                condition,
+                function_id,
+                true_benchmark_count,
+                false_benchmark_count
+            ) VALUES (?1, ?2, ?3, ?4, ?5, 0, 0)
+            ON CONFLICT (source_id, line_no, decision, condition) DO NOTHING",
+        )?
+        .execute(params![
+            sid,
+            cond.line_no,
+            cond.decision,
+            cond.condition,
+            fid
+        ])?;
+        let row_id = conn
+            .prepare_cached(
+                "SELECT id FROM \"conditions\"
+                    WHERE source_id = ?1 AND line_no = ?2 AND decision = ?3 AND condition = ?4",
+            )?
+            .query_row(
+                params![sid, cond.line_no, cond.decision, cond.condition],
+                |row| row.get(0),
+            )?;
+        Ok(store(&mut self.conditions, id, row_id))
+    }
+}
@@ -1,14 +1,12 @@
-use crate::args::{
-    Commands, EXEC_PLACEHOLDER, RESULT_TABLE_NAME, TRACK_BRANCHES, TRACK_FUNCS, TRACK_LINES,
-};
-use crate::{ResultT, ARGS};
+use crate::config::{CoverageConfig, RunConfig};
+use crate::error::{Error, RunnerResult};
+use crate::plan::benchmark_files;
 
-use glob::glob;
 use rusqlite::{params, Connection, Transaction};
 use sha2::{Digest, Sha256};
 use std::fs;
 
-pub(super) fn prepare(conn: &Connection) -> ResultT<()> {
+pub(super) fn prepare(conn: &Connection) -> RunnerResult<()> {
     // Disable disk sync after every transaction
     conn.execute("PRAGMA synchronous = OFF", [])?;
     // Increase cache size to 10MB
@@ -25,49 +23,93 @@ pub(super) fn prepare(conn: &Connection) -> ResultT<()> {
     Ok(())
 }
 
-pub(super) fn create_tables(conn: &Connection) -> ResultT<()> {
+pub(super) fn create_tables(conn: &Connection, config: &RunConfig) -> RunnerResult<()> {
     // Stores the arguments and other run parameters
     let config_table = "CREATE TABLE IF NOT EXISTS \"config\" (
                 key TEXT NOT NULL PRIMARY KEY,
                 value TEXT NOT NULL
             )";
-    conn.execute(&config_table, [])
-        .expect("Issue during config table creation");
+    conn.execute(config_table, [])?;
 
     // Stores the benchmark metadata
     let benchmarks_table = "CREATE TABLE IF NOT EXISTS \"benchmarks\" (
                 id INTEGER PRIMARY KEY,
                 prefix TEXT,
-                path TEXT NOT NULL
+                path TEXT NOT NULL,
+                archive TEXT,
+                member TEXT
             )";
-    conn.execute(&benchmarks_table, [])
-        .expect("Issue during benchmarks table creation");
+    conn.execute(benchmarks_table, [])?;
+
+    // Stores the named option sets every benchmark is run with
+    let configurations_table = "CREATE TABLE IF NOT EXISTS \"configurations\" (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                args TEXT NOT NULL
+            )";
+    conn.execute(configurations_table, [])?;
 
     // Store information about source files
     let source_table = "CREATE TABLE IF NOT EXISTS \"sources\" (
                 id INTEGER PRIMARY KEY,
                 path INTEGER NOT NULL UNIQUE
             )";
-    conn.execute(&source_table, [])
-        .expect("Issue during sources table creation");
+    conn.execute(source_table, [])?;
 
     // Stores the output of benchmark runs and other metadata
     let results_table = format!(
         "CREATE TABLE IF NOT EXISTS \"{}\" (
                 id INTEGER PRIMARY KEY,
                 bench_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
                 time_ms INTEGER NOT NULL,
                 exit_code INTEGER NOT NULL,
                 stdout TEXT NOT NULL,
-                stderr TEXT NOT NULL
+                stderr TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 1,
+                flaky INTEGER NOT NULL DEFAULT 0,
+                error TEXT,
+                timed_out INTEGER NOT NULL DEFAULT 0,
+                failed_coverage INTEGER NOT NULL DEFAULT 0,
+                cpu INTEGER
             )",
-        RESULT_TABLE_NAME.as_str()
+        config.result_table()
     );
-    conn.execute(&results_table, [])
-        .expect("Issue during result_benchmarks table creation");
+    conn.execute(&results_table, [])?;
+
+    // Stores every attempt of the benchmark runs of all result tables
+    let attempts_table = "CREATE TABLE IF NOT EXISTS \"benchmark_attempts\" (
+                id INTEGER PRIMARY KEY,
+                result_table TEXT NOT NULL,
+                bench_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                attempt INTEGER NOT NULL,
+                time_ms INTEGER NOT NULL,
+                exit_code INTEGER NOT NULL,
+                signal INTEGER,
+                stderr TEXT NOT NULL
+            )";
+    conn.execute(attempts_table, [])?;
+
+    if config.coverage().is_some_and(|c| c.screening.is_some()) {
+        // Outcome of every benchmark run of the pre-screening, only passed ones are measured
+        let screening_table = "CREATE TABLE IF NOT EXISTS \"screening\" (
+                bench_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                time_ms INTEGER NOT NULL,
+                exit_code INTEGER NOT NULL,
+                signal INTEGER,
+                timed_out INTEGER NOT NULL,
+                error TEXT,
+                passed INTEGER NOT NULL,
+                UNIQUE(bench_id, config_id)
+            )";
+        conn.execute(screening_table, [])?;
+    }
 
-    if TRACK_FUNCS.clone() {
-        // Store information about functions and their usage
+    if config.track_funcs() {
+        // Store information about functions and their usage. The usage counts sum the runs
+        // of all configurations, so they exceed the number of benchmarks if there are several.
         let func_table = "CREATE TABLE IF NOT EXISTS \"functions\" (
                 id INTEGER PRIMARY KEY,
                 source_id INTEGER NOT NULL,
@@ -77,37 +119,121 @@ pub(super) fn create_tables(conn: &Connection) -> ResultT<()> {
                 end_line INTEGER NOT NULL,
                 end_col INTEGER NOT NULL,
                 benchmark_usage_count INTEGER NOT NULL,
+                failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                blocks INTEGER NOT NULL DEFAULT 0,
+                max_blocks_executed INTEGER NOT NULL DEFAULT 0,
+                union_blocks_executed INTEGER,
+                lines INTEGER NOT NULL DEFAULT 0,
+                lines_executed INTEGER NOT NULL DEFAULT 0,
                 UNIQUE(source_id, start_line, start_col)
             )";
         //      UNIQUE(source_id, name)
         //  )";
-        conn.execute(&func_table, [])
-            .expect("Issue during functions table creation");
+        conn.execute(func_table, [])?;
 
-        let funcvec_table = "CREATE TABLE \"function_bitvecs\" ( 
-                source_id INTEGER NOT NULL, 
-                function_id INTEGER NOT NULL, 
+        // Template instantiations and constructor/destructor variants, sharing the source
+        // range of their function
+        let instantiation_table = "CREATE TABLE IF NOT EXISTS \"function_instantiations\" (
+                id INTEGER PRIMARY KEY,
+                function_id INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                demangled_name TEXT NOT NULL,
+                benchmark_usage_count INTEGER NOT NULL,
+                failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                blocks INTEGER NOT NULL DEFAULT 0,
+                max_blocks_executed INTEGER NOT NULL DEFAULT 0,
+                union_blocks_executed INTEGER,
+                UNIQUE(function_id, name)
+            )";
+        conn.execute(instantiation_table, [])?;
+
+        // Usage counts of the functions used under a configuration, by that configuration
+        let usage_table = "CREATE TABLE IF NOT EXISTS \"function_usage\" (
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                UNIQUE(function_id, config_id)
+            )";
+        conn.execute(usage_table, [])?;
+
+        // One usage bitvector (indexed by bench_id - 1) per function and configuration
+        let funcvec_table = "CREATE TABLE IF NOT EXISTS \"function_bitvecs\" (
+                source_id INTEGER NOT NULL,
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
                 data BLOB NOT NULL,
-                UNIQUE(source_id, function_id)
+                UNIQUE(source_id, function_id, config_id)
+            )";
+        conn.execute(funcvec_table, [])?;
+
+        if config.coverage().is_some_and(|c| c.stability.is_some()) {
+            // Functions used by some, but not all, runs of a sampled benchmark
+            let nondet_table = "CREATE TABLE IF NOT EXISTS \"nondeterministic_functions\" (
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                bench_id INTEGER NOT NULL,
+                runs INTEGER NOT NULL,
+                used_runs INTEGER NOT NULL,
+                UNIQUE(function_id, config_id, bench_id)
             )";
-        conn.execute(&funcvec_table, [])
-            .expect("Issue during functions bitvecs table creation");
+            conn.execute(nondet_table, [])?;
+
+            // Share of the sampled benchmarks using a function that use it in every run
+            let stability_table = "CREATE TABLE IF NOT EXISTS \"function_stability\" (
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                sampled_benchmarks INTEGER NOT NULL,
+                stable_benchmarks INTEGER NOT NULL,
+                score REAL NOT NULL,
+                UNIQUE(function_id, config_id)
+            )";
+            conn.execute(stability_table, [])?;
+        }
     }
 
-    if TRACK_LINES.clone() {
+    if config.track_lines() {
         // Store information about lines and their usage
         let line_table = "CREATE TABLE IF NOT EXISTS \"lines\" (
                 id INTEGER PRIMARY KEY,
                 source_id INTEGER NOT NULL,
                 line_no INTEGER NOT NULL,
+                function_id INTEGER,
                 benchmark_usage_count INTEGER NOT NULL,
+                failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
                 UNIQUE(source_id, line_no)
             )";
-        conn.execute(&line_table, [])
-            .expect("Issue during lines table creation");
+        conn.execute(line_table, [])?;
+
+        // One usage bitvector (indexed by bench_id - 1) per line and configuration
+        let linevec_table = "CREATE TABLE IF NOT EXISTS \"line_bitvecs\" (
+                source_id INTEGER NOT NULL,
+                line_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                UNIQUE(source_id, line_id, config_id)
+            )";
+        conn.execute(linevec_table, [])?;
     }
 
-    if TRACK_BRANCHES.clone() {
+    if config.track_conditions() {
+        // Conditions of every decision, with the number of successful runs they were seen
+        // (in the MC/DC sense) as true respectively false in
+        let condition_table = "CREATE TABLE IF NOT EXISTS \"conditions\" (
+                id INTEGER PRIMARY KEY,
+                source_id INTEGER NOT NULL,
+                line_no INTEGER NOT NULL,
+                decision INTEGER NOT NULL,
+                condition INTEGER NOT NULL,
+                function_id INTEGER,
+                true_benchmark_count INTEGER NOT NULL,
+                false_benchmark_count INTEGER NOT NULL,
+                UNIQUE(source_id, line_no, decision, condition)
+            )";
+        conn.execute(condition_table, [])?;
+    }
+
+    if config.track_branches() {
         // Store information about branches
         let branch_table = "CREATE TABLE IF NOT EXISTS \"branches\" (
                 id INTEGER PRIMARY KEY,
@@ -116,68 +242,65 @@ pub(super) fn create_tables(conn: &Connection) -> ResultT<()> {
                 benchmark_usage_count INTEGER NOT NULL,
                 UNIQUE(source_id, branch_no)
             )";
-        conn.execute(&branch_table, [])
-            .expect("Issue during branches table creation");
+        conn.execute(branch_table, [])?;
     }
 
     Ok(())
 }
 
-pub(super) fn populate_config(tx: Transaction) -> ResultT<()> {
-    let (individual_prefixes, coverage_kinds) = match &ARGS.command {
-        Commands::Coverage {
-            individual_prefixes,
-            coverage_kinds,
-            ..
-        } => (*individual_prefixes, coverage_kinds),
-        _ => unreachable!("Illegal populate_config call"),
-    };
-
+pub(super) fn populate_config(
+    tx: Transaction,
+    config: &RunConfig,
+    coverage: &CoverageConfig,
+) -> RunnerResult<()> {
     let c_insert = "INSERT INTO \"config\" (key, value) VALUES (?1, ?2)";
     tx.execute(
-        &c_insert,
-        params!["individual_gcov_prefixes", individual_prefixes],
+        c_insert,
+        params!["individual_gcov_prefixes", coverage.individual_prefixes],
+    )?;
+    tx.execute(
+        c_insert,
+        params!["worker_gcov_prefixes", coverage.worker_prefixes],
+    )?;
+
+    tx.execute(
+        c_insert,
+        params!["coverage_backend", coverage.backend.to_string()],
     )?;
 
-    for (i, c) in coverage_kinds.iter().enumerate() {
+    for (i, c) in coverage.coverage_kinds.iter().enumerate() {
         let k = format!("coverage_kind_{}", i);
-        tx.execute(&c_insert, params![k, c.to_string()])?;
+        tx.execute(c_insert, params![k, c.to_string()])?;
     }
 
-    tx.execute(&c_insert, params!["job_size", ARGS.job_size])?;
+    tx.execute(c_insert, params!["job_size", config.job_size])?;
 
-    tx.execute(&c_insert, params!["exec", ARGS.exec])?;
+    tx.execute(c_insert, params!["exec", config.exec])?;
 
     tx.execute(
-        &c_insert,
-        params!["parsed_exec", format!("{:?}", EXEC_PLACEHOLDER)],
+        c_insert,
+        params!["parsed_exec", format!("{:?}", config.exec_template())],
     )?;
 
-    let benchmark_pattern = match &ARGS.command {
-        Commands::Coverage { benchmarks, .. } => benchmarks,
-        _ => unreachable!("Expected a benchmark directory for initialization"),
-    };
-    tx.execute(&c_insert, params!["benchmark_pattern", benchmark_pattern])?;
+    tx.execute(c_insert, params!["benchmark_pattern", coverage.benchmarks])?;
 
-    let repo_path = ARGS.repo_dir.display().to_string();
-    tx.execute(&c_insert, params!["repo_path", repo_path.as_str()])?;
+    let repo_path = config.repo_dir.display().to_string();
+    tx.execute(c_insert, params!["repo_path", repo_path.as_str()])?;
 
     let get_commit_cmd = std::process::Command::new("git")
-        .args(&["-C", repo_path.as_str(), "rev-parse", "HEAD"])
-        .output()
-        .expect("Failed to fetch git repository HEAD commit hash");
+        .args(["-C", repo_path.as_str(), "rev-parse", "HEAD"])
+        .output()?;
     let commit_hash = String::from_utf8_lossy(&get_commit_cmd.stdout);
-    tx.execute(&c_insert, params!["git_commit_hash", commit_hash])?;
+    tx.execute(c_insert, params!["git_commit_hash", commit_hash])?;
 
     let get_upstreamurl_cmd = std::process::Command::new("git")
-        .args(&["-C", repo_path.as_str(), "remote", "get-url", "origin"])
-        .output()
-        .expect("Failed to fetch git repository upstream URL");
+        .args(["-C", repo_path.as_str(), "remote", "get-url", "origin"])
+        .output()?;
     let upstream_url = String::from_utf8_lossy(&get_upstreamurl_cmd.stdout);
-    tx.execute(&c_insert, params!["git_upstream_url", upstream_url])?;
+    tx.execute(c_insert, params!["git_upstream_url", upstream_url])?;
 
     let get_branch_cmd = std::process::Command::new("git")
-        .args(&[
+        .args([
             "-C",
             repo_path.as_str(),
             "rev-parse",
@@ -185,62 +308,95 @@ pub(super) fn populate_config(tx: Transaction) -> ResultT<()> {
             "--symbolic-full-name",
             "@{u}",
         ])
-        .output()
-        .expect("Failed to fetch git repository branch name");
+        .output()?;
     let branch = String::from_utf8_lossy(&get_branch_cmd.stdout);
-    tx.execute(&c_insert, params!["git_branch", branch])?;
+    tx.execute(c_insert, params!["git_branch", branch])?;
 
     tx.commit()?;
 
     Ok(())
 }
 
-pub(super) fn populate_benchmarks(tx: Transaction) -> ResultT<()> {
+/// Stores the fully resolved run configuration as TOML, so the run can be replayed via
+/// `--config`. Evaluation runs store theirs next to the coverage one, keyed by result table.
+pub(super) fn populate_run_config(tx: Transaction, config: &RunConfig) -> RunnerResult<()> {
+    let key = if config.is_coverage() {
+        "run_config".to_string()
+    } else {
+        format!("run_config_{}", config.result_table())
+    };
+    tx.execute(
+        "INSERT OR REPLACE INTO \"config\" (key, value) VALUES (?1, ?2)",
+        params![
+            key,
+            config
+                .to_run_file()
+                .to_toml()
+                .map_err(|e| Error::Config(e.to_string()))?
+        ],
+    )?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+pub(super) fn populate_configurations(
+    tx: Transaction,
+    coverage: &CoverageConfig,
+) -> RunnerResult<()> {
+    {
+        let mut stmt =
+            tx.prepare("INSERT INTO \"configurations\" (id, name, args) VALUES (?1, ?2, ?3)")?;
+        for c in coverage.configurations.iter() {
+            let args: Vec<&str> = c.args.iter().map(String::as_str).collect();
+            stmt.execute(params![c.id, c.name, shellwords::join(&args)])?;
+        }
+    }
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+pub(super) fn populate_benchmarks(tx: Transaction, coverage: &CoverageConfig) -> RunnerResult<()> {
     // TODO: Readd sampling support
-    if let Commands::Coverage {
-        individual_prefixes,
-        tmp_dir,
-        ..
-    } = &ARGS.command
     {
-        let mut stmt = tx.prepare("INSERT INTO \"benchmarks\" (path, prefix) VALUES (?1, ?2)")?;
-
-        let prefix_base = tmp_dir.as_ref().unwrap();
-        fs::create_dir_all(&prefix_base)
-            .expect("Could not create temporary base folder for prefix files");
-
-        let pattern = match &ARGS.command {
-            Commands::Coverage { benchmarks, .. } => benchmarks,
-            _ => unreachable!("Expected a benchmark directory"),
-        };
-
-        for entry in glob(&pattern).expect("Failed to read glob pattern") {
-            if let Ok(file) = entry {
-                let dfile = file.canonicalize().unwrap().display().to_string();
-                let prefix = if *individual_prefixes {
-                    let mut hasher = Sha256::new();
-                    hasher.update(file.to_string_lossy().as_bytes());
-                    let hash = format!("{:x}", hasher.finalize());
-
-                    let prefix = prefix_base.join(hash);
-                    if !prefix.exists() {
-                        fs::create_dir(&prefix).expect("Could not create prefix dir");
-                    }
-
-                    let prefix = prefix.canonicalize().unwrap().display().to_string();
-                    prefix
-                } else {
-                    "".to_string()
-                };
-
-                // TODO: Instead of storing the full path only store the difference
-                // due to file size reasons
-
-                stmt.execute(params![dfile, prefix])?;
-            }
+        let mut stmt = tx.prepare(
+            "INSERT INTO \"benchmarks\" (path, prefix, archive, member) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+
+        let prefix_base = &coverage.tmp_dir;
+        fs::create_dir_all(prefix_base)?;
+
+        for file in benchmark_files(coverage).map_err(|e| Error::Config(e.to_string()))? {
+            let dfile = file.path.display().to_string();
+            // Workers bring their own prefix
+            let prefix = if coverage.individual_prefixes && !coverage.worker_prefixes {
+                let mut hasher = Sha256::new();
+                hasher.update(dfile.as_bytes());
+                let hash = format!("{:x}", hasher.finalize());
+
+                let prefix = prefix_base.join(hash);
+                if !prefix.exists() {
+                    fs::create_dir(&prefix)?;
+                }
+
+                let prefix = prefix.canonicalize()?.display().to_string();
+                prefix
+            } else {
+                "".to_string()
+            };
+
+            // TODO: Instead of storing the full path only store the difference
+            // due to file size reasons
+
+            stmt.execute(params![
+                dfile,
+                prefix,
+                file.archive.map(|a| a.display().to_string()),
+                file.member
+            ])?;
         }
-    } else {
-        unreachable!("Illegal populate_benchmarks call")
     }
 
     tx.commit()?;
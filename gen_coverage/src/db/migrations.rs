@@ -0,0 +1,357 @@
+//! Versioning of the report DB layout. The version is stored as `schema_version` in the
+//! config table, reports written before it was introduced count as version 0.
+//!
+//! Every change of the layout created by `init::create_tables` that readers depend on bumps
+//! [`SCHEMA_VERSION`] and appends a migration upgrading reports of the previous version in
+//! place. The readers (`code_remover`, the optimizer) refuse reports of any other version.
+
+use super::has_table;
+use crate::error::{Error, RunnerResult};
+
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+/// Version of the layout created by this gen_coverage
+pub const SCHEMA_VERSION: u64 = 1;
+
+struct Migration {
+    description: &'static str,
+    apply: fn(&Transaction) -> RunnerResult<()>,
+}
+
+/// `MIGRATIONS[i]` upgrades a report of version `i` to version `i + 1`
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [Migration {
+    description: "add the columns and tables of reports written before schema versioning",
+    apply: unversioned_to_v1,
+}];
+
+/// Schema version of the report, 0 if it predates versioning
+pub fn version(conn: &Connection) -> RunnerResult<u64> {
+    if !has_table(conn, "config")? {
+        return Ok(0);
+    }
+    let version: Option<String> = conn
+        .query_row(
+            "SELECT value FROM \"config\" WHERE key = 'schema_version'",
+            params![],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match version {
+        Some(v) => v
+            .parse()
+            .map_err(|_| Error::Schema(format!("invalid schema_version '{}'", v))),
+        None => Ok(0),
+    }
+}
+
+/// Fails if the report was written by a newer gen_coverage, whose layout is unknown
+pub fn ensure_supported(conn: &Connection) -> RunnerResult<u64> {
+    let version = version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(Error::Schema(format!(
+            "schema version {} is newer than the supported version {}, update gen_coverage",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// Upgrades the report in place to [`SCHEMA_VERSION`], each migration in a transaction of
+/// its own. Returns the version the report had before.
+pub fn migrate(conn: &mut Connection) -> RunnerResult<u64> {
+    let initial = ensure_supported(conn)?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(initial as usize) {
+        info!(
+            "Migrating report DB to schema version {}: {}",
+            from + 1,
+            migration.description
+        );
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx)?;
+        set_version(&tx, from as u64 + 1)?;
+        tx.commit()?;
+    }
+    Ok(initial)
+}
+
+/// Marks a freshly created report as having the current layout
+pub(super) fn init_version(conn: &Connection) -> RunnerResult<()> {
+    set_version(conn, SCHEMA_VERSION)
+}
+
+fn set_version(conn: &Connection, version: u64) -> RunnerResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO \"config\" (key, value) VALUES ('schema_version', ?1)",
+        params![version.to_string()],
+    )?;
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> RunnerResult<bool> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )?)
+}
+
+/// Adds the column to the table, if the table exists and lacks it
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> RunnerResult<()> {
+    if has_table(conn, table)? && !has_column(conn, table, column)? {
+        conn.execute(
+            &format!(
+                "ALTER TABLE \"{}\" ADD COLUMN {} {}",
+                table, column, definition
+            ),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Unversioned reports were written by any gen_coverage since the initial one, so every
+/// addition since then is applied if missing. Tables that only exist for some coverage
+/// kinds or options are left out, readers check for them.
+fn unversioned_to_v1(tx: &Transaction) -> RunnerResult<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS \"config\" (
+                key TEXT NOT NULL PRIMARY KEY,
+                value TEXT NOT NULL
+            )",
+        [],
+    )?;
+
+    add_column(tx, "benchmarks", "archive", "TEXT")?;
+    add_column(tx, "benchmarks", "member", "TEXT")?;
+
+    // All runs were made with the plain exec template
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS \"configurations\" (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                args TEXT NOT NULL
+            )",
+        [],
+    )?;
+    tx.execute(
+        "INSERT INTO \"configurations\" (id, name, args)
+            SELECT 1, 'default', '' WHERE NOT EXISTS (SELECT * FROM \"configurations\")",
+        [],
+    )?;
+
+    // The coverage and every evaluation have a result table of their own
+    let result_tables = {
+        let mut stmt = tx.prepare(
+            "SELECT m.name FROM sqlite_master AS m WHERE m.type = 'table'
+                AND EXISTS (SELECT * FROM pragma_table_info(m.name) AS c WHERE c.name = 'bench_id')
+                AND EXISTS (SELECT * FROM pragma_table_info(m.name) AS c WHERE c.name = 'stdout')",
+        )?;
+        let rows = stmt.query_map(params![], |row| row.get(0))?;
+        rows.collect::<Result<Vec<String>, _>>()?
+    };
+    for table in result_tables {
+        add_column(tx, &table, "config_id", "INTEGER NOT NULL DEFAULT 1")?;
+        add_column(tx, &table, "attempts", "INTEGER NOT NULL DEFAULT 1")?;
+        add_column(tx, &table, "flaky", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(tx, &table, "error", "TEXT")?;
+        add_column(tx, &table, "timed_out", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(tx, &table, "failed_coverage", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(tx, &table, "cpu", "INTEGER")?;
+    }
+
+    add_column(
+        tx,
+        "functions",
+        "failed_benchmark_usage_count",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column(tx, "functions", "blocks", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(
+        tx,
+        "functions",
+        "max_blocks_executed",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column(tx, "functions", "union_blocks_executed", "INTEGER")?;
+    add_column(tx, "functions", "lines", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(
+        tx,
+        "functions",
+        "lines_executed",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+
+    // The runs of the default configuration are all the usage counts hold
+    if has_table(tx, "functions")? && !has_table(tx, "function_usage")? {
+        tx.execute_batch(
+            "CREATE TABLE \"function_usage\" (
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                failed_benchmark_usage_count INTEGER NOT NULL DEFAULT 0,
+                UNIQUE(function_id, config_id)
+            );
+            INSERT INTO \"function_usage\" (function_id, config_id, benchmark_usage_count, failed_benchmark_usage_count)
+                SELECT id, 1, benchmark_usage_count, failed_benchmark_usage_count FROM \"functions\"
+                WHERE benchmark_usage_count > 0 OR failed_benchmark_usage_count > 0;",
+        )?;
+    }
+
+    add_column(tx, "lines", "function_id", "INTEGER")?;
+    add_column(
+        tx,
+        "lines",
+        "failed_benchmark_usage_count",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+
+    // The bitvectors are keyed by configuration, which requires a new unique constraint
+    if has_table(tx, "function_bitvecs")? && !has_column(tx, "function_bitvecs", "config_id")? {
+        tx.execute_batch(
+            "ALTER TABLE \"function_bitvecs\" RENAME TO \"function_bitvecs_unversioned\";
+            CREATE TABLE \"function_bitvecs\" (
+                source_id INTEGER NOT NULL,
+                function_id INTEGER NOT NULL,
+                config_id INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                UNIQUE(source_id, function_id, config_id)
+            );
+            INSERT INTO \"function_bitvecs\" (source_id, function_id, config_id, data)
+                SELECT source_id, function_id, 1, data FROM \"function_bitvecs_unversioned\";
+            DROP TABLE \"function_bitvecs_unversioned\";",
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Layout of the reports written before schema versioning, with tracked functions and
+    /// lines
+    const UNVERSIONED: &str = "
+        CREATE TABLE \"config\" (key TEXT NOT NULL PRIMARY KEY, value TEXT NOT NULL);
+        CREATE TABLE \"benchmarks\" (id INTEGER PRIMARY KEY, prefix TEXT, path TEXT NOT NULL);
+        CREATE TABLE \"sources\" (id INTEGER PRIMARY KEY, path INTEGER NOT NULL UNIQUE);
+        CREATE TABLE \"result_benchmarks\" (
+            id INTEGER PRIMARY KEY,
+            bench_id INTEGER NOT NULL,
+            time_ms INTEGER NOT NULL,
+            exit_code INTEGER NOT NULL,
+            stdout TEXT NOT NULL,
+            stderr TEXT NOT NULL
+        );
+        CREATE TABLE \"functions\" (
+            id INTEGER PRIMARY KEY,
+            source_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            start_line INTEGER NOT NULL,
+            start_col INTEGER NOT NULL,
+            end_line INTEGER NOT NULL,
+            end_col INTEGER NOT NULL,
+            benchmark_usage_count INTEGER NOT NULL,
+            UNIQUE(source_id, start_line, start_col)
+        );
+        CREATE TABLE \"function_bitvecs\" (
+            source_id INTEGER NOT NULL,
+            function_id INTEGER NOT NULL,
+            data BLOB NOT NULL,
+            UNIQUE(source_id, function_id)
+        );
+        CREATE TABLE \"lines\" (
+            id INTEGER PRIMARY KEY,
+            source_id INTEGER NOT NULL,
+            line_no INTEGER NOT NULL,
+            benchmark_usage_count INTEGER NOT NULL,
+            UNIQUE(source_id, line_no)
+        );
+        INSERT INTO \"benchmarks\" VALUES (1, NULL, '/benchmarks/a.smt2'), (2, NULL, '/benchmarks/b.smt2');
+        INSERT INTO \"sources\" VALUES (1, '/repo/src/solver.c');
+        INSERT INTO \"result_benchmarks\" VALUES (1, 1, 10, 0, 'sat', ''), (2, 2, 20, 1, '', 'error');
+        INSERT INTO \"functions\" VALUES (1, 1, 'main', 1, 1, 10, 1, 2), (2, 1, 'helper', 12, 1, 14, 1, 1);
+        INSERT INTO \"function_bitvecs\" VALUES (1, 1, X'C0'), (1, 2, X'80');
+        INSERT INTO \"lines\" VALUES (1, 1, 2, 2);";
+
+    #[test]
+    fn unversioned_to_v1_migrates_baseline_report() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(UNVERSIONED).unwrap();
+        assert_eq!(version(&conn).unwrap(), 0);
+
+        assert_eq!(migrate(&mut conn).unwrap(), 0);
+        assert_eq!(version(&conn).unwrap(), SCHEMA_VERSION);
+
+        for (table, column) in [
+            ("benchmarks", "archive"),
+            ("benchmarks", "member"),
+            ("result_benchmarks", "config_id"),
+            ("result_benchmarks", "failed_coverage"),
+            ("result_benchmarks", "cpu"),
+            ("functions", "failed_benchmark_usage_count"),
+            ("functions", "union_blocks_executed"),
+            ("functions", "lines_executed"),
+            ("lines", "function_id"),
+            ("lines", "failed_benchmark_usage_count"),
+        ] {
+            assert!(
+                has_column(&conn, table, column).unwrap(),
+                "{}.{}",
+                table,
+                column
+            );
+        }
+        // Tables that were not tracked are not created
+        assert!(!has_table(&conn, "branches").unwrap());
+
+        // All existing runs belong to the default configuration
+        let configurations: Vec<(u64, String)> = conn
+            .prepare("SELECT id, name FROM \"configurations\"")
+            .unwrap()
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(configurations, vec![(1, "default".to_string())]);
+        let run_configs: Vec<u64> = conn
+            .prepare("SELECT config_id FROM \"result_benchmarks\" ORDER BY id")
+            .unwrap()
+            .query_map(params![], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(run_configs, vec![1, 1]);
+
+        // So are the usage counts
+        let usage: Vec<(u64, u64, u64)> = conn
+            .prepare("SELECT function_id, config_id, benchmark_usage_count FROM \"function_usage\" ORDER BY function_id")
+            .unwrap()
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(usage, vec![(1, 1, 2), (2, 1, 1)]);
+
+        // The bitvectors are kept under the default configuration
+        let bitvecs: Vec<(u64, u64, Vec<u8>)> = conn
+            .prepare("SELECT function_id, config_id, data FROM \"function_bitvecs\" ORDER BY function_id")
+            .unwrap()
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(bitvecs, vec![(1, 1, vec![0xC0]), (2, 1, vec![0x80])]);
+        assert!(!has_table(&conn, "function_bitvecs_unversioned").unwrap());
+        // A bitvector of another configuration fits the new unique constraint
+        conn.execute(
+            "INSERT INTO \"function_bitvecs\" VALUES (1, 1, 2, X'40')",
+            params![],
+        )
+        .unwrap();
+
+        // Migrating the current layout again changes nothing
+        assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+    }
+}
@@ -1,39 +1,51 @@
+mod ids;
 mod init;
-use crate::args::{
-    Commands, RESULT_TABLE_NAME, TRACK_BRANCHES, TRACK_FUNCS, TRACK_LINES, TRACK_UNUSED,
-};
-use crate::runner::{GcovBitvec, GcovRes};
-use crate::types::{Benchmark, BenchmarkRun};
-use crate::{ResultT, ARGS};
-
-use itertools::Itertools;
+pub mod migrations;
+use crate::config::RunConfig;
+use crate::error::{Error, RunnerResult};
+use crate::runner::gcov::{GcovCounts, GcovRes};
+use crate::runner::index::{ConditionId, CoverageIndex, FunctionId, InstantiationId, LineId};
+use crate::runner::stability::StabilityResults;
+use crate::runner::GcovBitvec;
+use crate::types::{Benchmark, BenchmarkRun, Configuration};
+
 use log::info;
 use rusqlite::{params, Connection, OpenFlags};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 const MEMORY_CONN_URI: &str = ":memory:";
-const INSERT_BATCH_SIZE: usize = 400;
+
+/// Whether the report DB has the table, which depends on its version and the tracked
+/// coverage kinds
+pub fn has_table(conn: &Connection, table: &str) -> RunnerResult<bool> {
+    Ok(conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![table],
+        |row| row.get(0),
+    )?)
+}
 
 pub struct DbWriter {
     conn: Connection,
+    config: Arc<RunConfig>,
+    ids: ids::DbIds,
 }
 
 impl DbWriter {
-    pub fn new() -> ResultT<Self> {
-        let is_coverage = match ARGS.command {
-            Commands::Coverage { .. } => true,
-            _ => false,
-        };
-        assert!(
-            !is_coverage || !ARGS.result_db.exists(),
-            "DB file already exists... Coverage command will create a new DB!"
-        );
+    pub fn new(config: Arc<RunConfig>) -> RunnerResult<Self> {
+        let is_coverage = config.is_coverage();
+        if is_coverage && config.result_db.exists() {
+            return Err(Error::Config(
+                "DB file already exists... Coverage command will create a new DB!".to_string(),
+            ));
+        }
         let mut conn = Connection::open_with_flags(
             if is_coverage {
                 MEMORY_CONN_URI.to_string()
             } else {
-                ARGS.result_db.canonicalize()?.display().to_string()
+                config.result_db.canonicalize()?.display().to_string()
             },
             if is_coverage {
                 OpenFlags::SQLITE_OPEN_URI
@@ -49,44 +61,109 @@ impl DbWriter {
         )?;
 
         info!("Configuring database...");
-        init::prepare(&conn).expect("Issue during table preparation");
+        init::prepare(&conn)?;
+        if !is_coverage {
+            // Evaluations add their results to an existing report, which may be older
+            let version = migrations::migrate(&mut conn)?;
+            if version < migrations::SCHEMA_VERSION {
+                info!(
+                    "Upgraded report DB from schema version {} to {}",
+                    version,
+                    migrations::SCHEMA_VERSION
+                );
+            }
+        }
         info!("Creating tables...");
-        init::create_tables(&conn).expect("Issue during table creation");
+        init::create_tables(&conn, &config)?;
+        if is_coverage {
+            migrations::init_version(&conn)?;
+        }
 
         // Only populate benchmark and config tables when running initial coverage reports
-        if is_coverage {
+        if let Some(coverage) = config.coverage() {
             info!("Populating config table...");
-            init::populate_config(conn.transaction()?)
-                .expect("Issue during config table population");
+            init::populate_config(conn.transaction()?, &config, coverage)?;
+            info!("Populating configurations table...");
+            init::populate_configurations(conn.transaction()?, coverage)?;
             info!("Populating benchmarks table...");
-            init::populate_benchmarks(conn.transaction()?)
-                .expect("Issue during benchmark table population");
+            init::populate_benchmarks(conn.transaction()?, coverage)?;
         }
+        init::populate_run_config(conn.transaction()?, &config)?;
 
-        Ok(DbWriter { conn })
+        Ok(DbWriter {
+            conn,
+            config,
+            ids: ids::DbIds::default(),
+        })
     }
 
-    pub fn write_to_disk(&self) -> ResultT<()> {
-        let query = format!("VACUUM INTO '{}'", ARGS.result_db.display());
+    pub fn write_to_disk(&self) -> RunnerResult<()> {
+        let query = format!("VACUUM INTO '{}'", self.config.result_db.display());
         self.conn.execute(&query, params![])?;
         Ok(())
     }
 
-    pub fn get_all_benchmarks(&mut self) -> ResultT<Vec<Benchmark>> {
+    /// Size of the in memory DB of a coverage run, which still has to be written to disk
+    pub fn memory_size(&self) -> RunnerResult<u64> {
+        if !self.config.is_coverage() {
+            return Ok(0);
+        }
+        let pages: u64 = self
+            .conn
+            .query_row("PRAGMA page_count", params![], |row| row.get(0))?;
+        let page_size: u64 = self
+            .conn
+            .query_row("PRAGMA page_size", params![], |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+
+    /// Records why the run was aborted, the report only holds the results collected until
+    /// then. Evaluations are keyed by their result table.
+    pub fn mark_incomplete(&self, reason: &Error) -> RunnerResult<()> {
+        let key = if self.config.is_coverage() {
+            "incomplete".to_string()
+        } else {
+            format!("incomplete_{}", self.config.result_table())
+        };
+        self.conn.execute(
+            "INSERT OR REPLACE INTO \"config\" (key, value) VALUES (?1, ?2)",
+            params![key, reason.to_string()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_all_benchmarks(&mut self) -> RunnerResult<Vec<Benchmark>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, path, prefix FROM \"benchmarks\"")?;
+            .prepare("SELECT id, path, prefix, archive, member FROM \"benchmarks\"")?;
+        let mut screened_out: HashMap<u64, Vec<u64>> = HashMap::new();
+        // Evaluations run all benchmarks, regardless of the screening of the coverage run
+        if self.config.is_coverage() && has_table(&self.conn, "screening")? {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT bench_id, config_id FROM \"screening\" WHERE passed = 0")?;
+            let rows = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            for row in rows {
+                let (bench_id, config_id) = row?;
+                screened_out.entry(bench_id).or_default().push(config_id);
+            }
+        }
         let rows = stmt.query_map(params![], |row| {
             let pref: String = row.get(2)?;
             let path: String = row.get(1)?;
+            let archive: Option<String> = row.get(3)?;
+            let id = row.get(0)?;
             Ok(Benchmark {
-                id: row.get(0)?,
+                id,
                 path: PathBuf::from(path),
-                prefix: if pref.len() > 0 {
+                prefix: if !pref.is_empty() {
                     Some(PathBuf::from(pref))
                 } else {
                     None
                 },
+                archive: archive.map(PathBuf::from),
+                member: row.get(4)?,
+                screened_out: screened_out.remove(&id).unwrap_or_default(),
             })
         })?;
         let mut result = vec![];
@@ -97,107 +174,180 @@ impl DbWriter {
         Ok(result)
     }
 
-    pub fn add_run_result(&mut self, run_result: BenchmarkRun) -> ResultT<()> {
-        let query = format!(
-            "INSERT INTO \"{}\" (
-                bench_id,
-                time_ms,
-                exit_code,
-                stdout,
-                stderr
-            ) VALUES (?1, ?2, ?3, ?4, ?5)",
-            RESULT_TABLE_NAME.as_str()
-        );
-        let mut stmt_insert_runresult = self
-            .conn
-            .prepare_cached(query.as_str())
-            .expect("Issue during benchmark status update query preparation");
-        stmt_insert_runresult
-            .execute(params![
-                run_result.bench_id,
-                run_result.time_ms,
-                run_result.exit_code,
-                run_result.stdout,
-                run_result.stderr,
-            ])
-            .expect("Issue during run result insertion!");
-        Ok(())
-    }
-
-    pub fn add_gcov_bitvecs(&mut self, run_result: GcovBitvec) -> ResultT<()> {
+    /// Records the outcomes of the pre-screening runs
+    pub fn add_screening_results(&mut self, runs: &[BenchmarkRun]) -> RunnerResult<()> {
         let tx = self.conn.transaction()?;
-        // 1. Ensure all sources exist in DB & retrieve their ids
         {
-            for chunk in &run_result.iter().chunks(INSERT_BATCH_SIZE) {
-                let mut batch_query = String::new();
-                for (file, _) in chunk {
-                    batch_query.push_str(&format!(
-                        "INSERT INTO \"sources\" ( path ) VALUES ( '{}' ) ON CONFLICT DO NOTHING;",
-                        file
-                    ));
-                }
-                tx.execute_batch(&batch_query)?;
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO \"screening\" (
+                    bench_id,
+                    config_id,
+                    time_ms,
+                    exit_code,
+                    signal,
+                    timed_out,
+                    error,
+                    passed
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for run in runs {
+                stmt.execute(params![
+                    run.bench_id,
+                    run.config_id,
+                    run.time_ms,
+                    run.exit_code,
+                    run.signal,
+                    run.timed_out,
+                    run.error,
+                    run.exit_code == 0 && !run.timed_out,
+                ])?;
             }
         }
+        tx.commit()?;
+        Ok(())
+    }
 
-        let mut srcid_file_map: HashMap<Box<String>, u64>;
+    pub fn get_all_configurations(&mut self) -> RunnerResult<Vec<Configuration>> {
+        let mut result = vec![];
         {
-            let mut stmt = tx.prepare_cached("SELECT id, path FROM \"sources\"")?;
+            let mut stmt = self
+                .conn
+                .prepare("SELECT id, name, args FROM \"configurations\" ORDER BY id")?;
             let rows = stmt.query_map(params![], |row| {
-                let id: u64 = row.get(0)?;
-                let file: String = row.get(1)?;
-                Ok((file, id))
+                let args: String = row.get(2)?;
+                Ok((row.get(0)?, row.get(1)?, args))
             })?;
-            srcid_file_map = HashMap::with_capacity(rows.size_hint().0);
             for row in rows {
-                let (file, id) = row?;
-                srcid_file_map.insert(Box::from(file), id);
+                let (id, name, args) = row?;
+                result.push(Configuration {
+                    id,
+                    name,
+                    args: shellwords::split(&args).map_err(|e| Error::Config(e.to_string()))?,
+                });
             }
         }
 
-        // 2. Track usage data of all (used) functions
-        if TRACK_FUNCS.clone() {
-            let mut fids: HashMap<(u64, (u32, u32)), u64>;
-            {
-                let mut stmt = tx.prepare_cached(
-                    "SELECT id, source_id, start_line, start_col FROM \"functions\"",
-                )?;
-                let rows = stmt.query_map(params![], |row| {
-                    let id: u64 = row.get(0)?;
-                    let sid: u64 = row.get(1)?;
-                    let start_line: u32 = row.get(2)?;
-                    let start_col: u32 = row.get(3)?;
-                    Ok((sid, (start_line, start_col), id))
-                })?;
-                fids = HashMap::with_capacity(rows.size_hint().0);
-                for row in rows {
-                    let (sid, pos, id) = row?;
-                    fids.insert((sid, pos), id);
-                }
-            }
+        Ok(result)
+    }
 
-            for (file, (funcs, _, _)) in &run_result {
-                let sid = srcid_file_map.get(file).unwrap();
-                for (fkey, fvec) in funcs {
-                    let fid_key = (*sid, *fkey);
-                    let fid = fids.get(&fid_key).unwrap();
+    pub fn add_run_result(&mut self, run_result: BenchmarkRun) -> RunnerResult<()> {
+        let query = format!(
+            "INSERT INTO \"{}\" (
+                bench_id,
+                config_id,
+                time_ms,
+                exit_code,
+                stdout,
+                stderr,
+                attempts,
+                flaky,
+                error,
+                timed_out,
+                failed_coverage,
+                cpu
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            self.config.result_table()
+        );
+        let mut stmt_insert_runresult = self.conn.prepare_cached(query.as_str())?;
+        stmt_insert_runresult.execute(params![
+            run_result.bench_id,
+            run_result.config_id,
+            run_result.time_ms,
+            run_result.exit_code,
+            run_result.stdout,
+            run_result.stderr,
+            run_result.attempt,
+            run_result.flaky,
+            run_result.error,
+            run_result.timed_out,
+            run_result.failed_coverage,
+            run_result.cpu,
+        ])?;
+        Ok(())
+    }
+
+    /// Records every attempt of a benchmark run, i.e. the retried ones and the final one
+    pub fn add_run_attempts(
+        &mut self,
+        retried: &[BenchmarkRun],
+        final_run: &BenchmarkRun,
+    ) -> RunnerResult<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO \"benchmark_attempts\" (
+                result_table,
+                bench_id,
+                config_id,
+                attempt,
+                time_ms,
+                exit_code,
+                signal,
+                stderr
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for run in retried.iter().chain([final_run]) {
+            stmt.execute(params![
+                self.config.result_table(),
+                run.bench_id,
+                run.config_id,
+                run.attempt,
+                run.time_ms,
+                run.exit_code,
+                run.signal,
+                run.stderr,
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn add_gcov_bitvecs(
+        &mut self,
+        index: &CoverageIndex,
+        config_results: HashMap<u64, GcovBitvec>,
+    ) -> RunnerResult<()> {
+        let index = index.read();
+        let tx = self.conn.transaction()?;
+
+        // 1. Track usage data of all (used) functions
+        if self.config.track_funcs() {
+            for (config_id, run_result) in &config_results {
+                for (id, fvec) in run_result.functions.iter().enumerate() {
+                    if fvec.is_empty() {
+                        continue;
+                    }
+                    let id = id as FunctionId;
+                    let sid = self.ids.source(&tx, &index, index.function(id).source)?;
+                    let fid = self.ids.function(&tx, &index, id)?;
                     let bytes: &[u8] = fvec.as_raw_slice();
 
-                    tx.execute(
-                        "INSERT INTO \"function_bitvecs\" ( source_id, function_id, data ) VALUES (?1, ?2, ?3);",
-                        params![ sid, fid, bytes ]
-                    )?;
+                    tx.prepare_cached(
+                        "INSERT INTO \"function_bitvecs\" ( source_id, function_id, config_id, data ) VALUES (?1, ?2, ?3, ?4);",
+                    )?
+                    .execute(params![sid, fid, config_id, bytes])?;
                 }
             }
         }
 
         // 2. Track usage data of all (used) lines
-        if TRACK_LINES.clone() {
-            // TODO: Add support for branch tracking
-            unimplemented!("Bitvecs for lines not yet supported")
+        if self.config.track_lines() {
+            for (config_id, run_result) in &config_results {
+                for (id, lvec) in run_result.lines.iter().enumerate() {
+                    if lvec.is_empty() {
+                        continue;
+                    }
+                    let id = id as LineId;
+                    let sid = self.ids.source(&tx, &index, index.line(id).source)?;
+                    let lid = self.ids.line(&tx, &index, id)?;
+                    let bytes: &[u8] = lvec.as_raw_slice();
+
+                    tx.prepare_cached(
+                        "INSERT INTO \"line_bitvecs\" ( source_id, line_id, config_id, data ) VALUES (?1, ?2, ?3, ?4);",
+                    )?
+                    .execute(params![sid, lid, config_id, bytes])?;
+                }
+            }
         }
 
-        if TRACK_BRANCHES.clone() {
+        if self.config.track_branches() {
             // TODO: Add support for branch tracking
             unimplemented!("Branch tracking not yet supported")
         }
@@ -206,110 +356,172 @@ impl DbWriter {
         Ok(())
     }
 
-    pub fn add_gcov_measurement(&mut self, run_result: GcovRes) -> ResultT<()> {
+    /// Stores the functions with nondeterministic usage and the stability score of every
+    /// function used by a sampled benchmark
+    pub fn add_stability_results(
+        &mut self,
+        index: &CoverageIndex,
+        results: StabilityResults,
+    ) -> RunnerResult<()> {
+        let index = index.read();
         let tx = self.conn.transaction()?;
-        // 1. Ensure all sources exist in DB & retrieve their ids
         {
-            for chunk in &run_result.iter().chunks(INSERT_BATCH_SIZE) {
-                let mut batch_query = String::new();
-                for (file, _) in chunk {
-                    batch_query.push_str(&format!(
-                        "INSERT INTO \"sources\" ( path ) VALUES ( '{}' ) ON CONFLICT DO NOTHING;",
-                        file
-                    ));
-                }
-                tx.execute_batch(&batch_query)?;
+            // Functions only used by repeated runs get a row with a usage count of 0
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO \"nondeterministic_functions\" (
+                    function_id,
+                    config_id,
+                    bench_id,
+                    runs,
+                    used_runs
+                ) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for f in results.nondeterministic.iter() {
+                stmt.execute(params![
+                    self.ids.function(&tx, &index, f.function)?,
+                    f.config_id,
+                    f.bench_id,
+                    f.runs,
+                    f.used_runs
+                ])?;
             }
-        }
 
-        let mut srcid_file_map: HashMap<Box<String>, u64>;
-        {
-            let mut stmt = tx.prepare_cached("SELECT id, path FROM \"sources\"")?;
-            let rows = stmt.query_map(params![], |row| {
-                let id: u64 = row.get(0)?;
-                let file: String = row.get(1)?;
-                Ok((file, id))
-            })?;
-            srcid_file_map = HashMap::with_capacity(rows.size_hint().0);
-            for row in rows {
-                let (file, id) = row?;
-                srcid_file_map.insert(Box::from(file), id);
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO \"function_stability\" (
+                    function_id,
+                    config_id,
+                    sampled_benchmarks,
+                    stable_benchmarks,
+                    score
+                ) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for f in results.stability.iter() {
+                stmt.execute(params![
+                    self.ids.function(&tx, &index, f.function)?,
+                    f.config_id,
+                    f.sampled_benchmarks,
+                    f.stable_benchmarks,
+                    f.score()
+                ])?;
             }
         }
+        tx.commit()?;
+
+        Ok(())
+    }
 
-        // 2. Track usage data of all (used & if wanted unused) functions
-        let track_unused = TRACK_UNUSED.clone();
-        if TRACK_FUNCS.clone() {
-            for (file, (funcs, _, _)) in &run_result {
-                let sid = srcid_file_map.get(file).unwrap();
-                for chunk in &funcs
-                    .values()
-                    .filter(|f| track_unused || f.borrow().usage > 0)
-                    .chunks(INSERT_BATCH_SIZE)
-                {
-                    let mut batch_query = String::new();
-                    for func in chunk {
-                        // NOTE: As the function name also contains the parameter types,
-                        // overloading kind of breaks the names and they should be used with care
-                        // ON CONFLICT (source_id, name) DO UPDATE
-                        let func = func.borrow();
-                        batch_query.push_str(&format!(
-                            "INSERT INTO \"functions\" (
-                            source_id,
-                            name,
-                            start_line,
-                            start_col,
-                            end_line,
-                            end_col,
-                            benchmark_usage_count
-                        ) VALUES ('{}', '{}', {}, {}, {}, {}, {}) 
-                        ON CONFLICT (source_id, start_line, start_col) DO UPDATE 
-                        SET benchmark_usage_count = benchmark_usage_count + excluded.benchmark_usage_count;",
-                            sid.to_string(),
-                            func.name.to_string(),
-                            func.start.line.to_string(),
-                            func.start.col.to_string(),
-                            func.end.line.to_string(),
-                            func.end.col.to_string(),
-                            func.usage
-                        ));
+    /// Adds the usage counts of a batch of benchmarks run under the configuration to the
+    /// functions, instantiations and lines tables, and to the per configuration function
+    /// usage. Counts of failed runs go to the separate `failed_benchmark_usage_count`.
+    pub fn add_gcov_measurement(
+        &mut self,
+        index: &CoverageIndex,
+        config_id: u64,
+        counts: GcovCounts,
+        failed: bool,
+    ) -> RunnerResult<()> {
+        let index = index.read();
+        let column = if failed {
+            "failed_benchmark_usage_count"
+        } else {
+            "benchmark_usage_count"
+        };
+        let tx = self.conn.transaction()?;
+        let track_unused = self.config.track_unused();
+        let usage = |counts: &[u32], id: usize| counts.get(id).copied().unwrap_or(0);
+
+        // 1. Track usage data of all (used & if wanted unused) functions
+        if self.config.track_funcs() {
+            let mut update = tx.prepare_cached(&format!(
+                "UPDATE \"functions\" SET {0} = {0} + ?2 WHERE id = ?1",
+                column
+            ))?;
+            let mut update_config = tx.prepare_cached(&format!(
+                "INSERT INTO \"function_usage\" (function_id, config_id, {0}) VALUES (?1, ?2, ?3)
+                ON CONFLICT (function_id, config_id) DO UPDATE SET {0} = {0} + excluded.{0}",
+                column
+            ))?;
+            // Failed runs might have been stopped before reaching most blocks
+            let mut update_blocks = (!failed).then_some(tx.prepare_cached(
+                "UPDATE \"functions\" SET max_blocks_executed = MAX(max_blocks_executed, ?2) WHERE id = ?1",
+            )?);
+            for id in 0..index.function_count() {
+                let count = usage(&counts.functions, id);
+                if count > 0 || track_unused {
+                    let fid = self.ids.function(&tx, &index, id as FunctionId)?;
+                    if count > 0 {
+                        update.execute(params![fid, count])?;
+                        update_config.execute(params![fid, config_id, count])?;
+                    }
+                    let blocks = usage(&counts.function_blocks, id);
+                    if let Some(update_blocks) = update_blocks.as_mut().filter(|_| blocks > 0) {
+                        update_blocks.execute(params![fid, blocks])?;
+                    }
+                }
+            }
+
+            // Instantiations are linked to the function row of their shared source range
+            let mut update = tx.prepare_cached(&format!(
+                "UPDATE \"function_instantiations\" SET {0} = {0} + ?2 WHERE id = ?1",
+                column
+            ))?;
+            let mut update_blocks = (!failed).then_some(tx.prepare_cached(
+                "UPDATE \"function_instantiations\" SET max_blocks_executed = MAX(max_blocks_executed, ?2) WHERE id = ?1",
+            )?);
+            for id in 0..index.instantiation_count() {
+                let count = usage(&counts.instantiations, id);
+                if count > 0 || track_unused {
+                    let iid = self.ids.instantiation(&tx, &index, id as InstantiationId)?;
+                    if count > 0 {
+                        update.execute(params![iid, count])?;
+                    }
+                    let blocks = usage(&counts.instantiation_blocks, id);
+                    if let Some(update_blocks) = update_blocks.as_mut().filter(|_| blocks > 0) {
+                        update_blocks.execute(params![iid, blocks])?;
                     }
-                    tx.execute_batch(&batch_query)?;
                 }
             }
         }
 
         // 2. Track usage data of all (used) lines
-        if TRACK_LINES.clone() {
-            for (file, (_, lines, _)) in &run_result {
-                let sid = srcid_file_map.get(file).unwrap();
-                for chunk in &lines
-                    .values()
-                    .filter(|l| track_unused || l.borrow().usage > 0)
-                    .chunks(INSERT_BATCH_SIZE)
-                {
-                    let mut batch_query = String::new();
-                    for line in chunk {
-                        let line = line.borrow();
-                        batch_query.push_str(&format!(
-                            "INSERT INTO \"lines\" (
-                            source_id,
-                            line_no,
-                            benchmark_usage_count
-                        ) VALUES ({}, {}, {})
-                        ON CONFLICT (source_id, line_no) DO UPDATE 
-                        SET benchmark_usage_count = benchmark_usage_count + excluded.benchmark_usage_count;",
-                            *sid,
-                            line.line_no,
-                            line.usage
-                        ));
+        if self.config.track_lines() {
+            let mut update = tx.prepare_cached(&format!(
+                "UPDATE \"lines\" SET {0} = {0} + ?2 WHERE id = ?1",
+                column
+            ))?;
+            for id in 0..index.line_count() {
+                let count = usage(&counts.lines, id);
+                if count > 0 || track_unused {
+                    let lid = self.ids.line(&tx, &index, id as LineId)?;
+                    if count > 0 {
+                        update.execute(params![lid, count])?;
+                    }
+                }
+            }
+        }
+
+        // 3. Track the outcomes of all (evaluated) conditions, only of successful runs as
+        // failed ones might have been stopped before a decision got to vary
+        if self.config.track_conditions() && !failed {
+            let mut update = tx.prepare_cached(
+                "UPDATE \"conditions\" SET
+                    true_benchmark_count = true_benchmark_count + ?2,
+                    false_benchmark_count = false_benchmark_count + ?3
+                WHERE id = ?1",
+            )?;
+            for id in 0..index.condition_count() {
+                let true_count = usage(&counts.conditions, 2 * id);
+                let false_count = usage(&counts.conditions, 2 * id + 1);
+                if true_count > 0 || false_count > 0 || track_unused {
+                    let cid = self.ids.condition(&tx, &index, id as ConditionId)?;
+                    if true_count > 0 || false_count > 0 {
+                        update.execute(params![cid, true_count, false_count])?;
                     }
-                    tx.execute_batch(&batch_query)?;
                 }
             }
         }
 
-        if TRACK_BRANCHES.clone() {
+        if self.config.track_branches() {
             // TODO: Add support for branch tracking
             unimplemented!("Branch tracking not yet supported")
         }
@@ -317,4 +529,76 @@ impl DbWriter {
 
         Ok(())
     }
+
+    /// Stores the number of blocks and lines of every stored function, together with the
+    /// number of them executed by any successful run. Executed blocks are only known if the
+    /// block union was tracked, lines if lines are tracked as well.
+    pub fn add_function_totals(
+        &mut self,
+        index: &CoverageIndex,
+        union: Option<GcovRes>,
+    ) -> RunnerResult<()> {
+        if !self.config.track_funcs() {
+            return Ok(());
+        }
+        let index = index.read();
+        let union_blocks = |id: InstantiationId| {
+            union
+                .as_ref()
+                .map(|u| u.blocks.get(id as usize).copied().unwrap_or(0))
+        };
+        let blocks = index.sum_per_function(|id| index.instantiation(id).blocks);
+        let union_function_blocks = union
+            .as_ref()
+            .map(|_| index.sum_per_function(|id| union_blocks(id).unwrap()));
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut update = tx.prepare_cached(
+                "UPDATE \"functions\" SET blocks = ?2, union_blocks_executed = ?3 WHERE id = ?1",
+            )?;
+            for id in 0..index.function_count() {
+                if let Some(fid) = self.ids.existing_function(id as FunctionId) {
+                    let union = union_function_blocks.as_ref().map(|u| u[id]);
+                    update.execute(params![fid, blocks[id], union])?;
+                }
+            }
+            let mut update = tx.prepare_cached(
+                "UPDATE \"function_instantiations\" SET blocks = ?2, union_blocks_executed = ?3 WHERE id = ?1",
+            )?;
+            for id in 0..index.instantiation_count() {
+                let id = id as InstantiationId;
+                if let Some(iid) = self.ids.existing_instantiation(id) {
+                    update.execute(params![
+                        iid,
+                        index.instantiation(id).blocks,
+                        union_blocks(id)
+                    ])?;
+                }
+            }
+
+            if self.config.track_lines() {
+                let mut lines = vec![0; index.function_count()];
+                for id in 0..index.line_count() {
+                    if let Some(function) = index.line(id as LineId).function {
+                        lines[function as usize] += 1;
+                    }
+                }
+                let mut update = tx.prepare_cached(
+                    "UPDATE \"functions\" SET lines = ?2, lines_executed = (
+                        SELECT COUNT(*) FROM \"lines\"
+                        WHERE function_id = ?1 AND benchmark_usage_count > 0
+                    ) WHERE id = ?1",
+                )?;
+                for (id, lines) in lines.into_iter().enumerate() {
+                    if let Some(fid) = self.ids.existing_function(id as FunctionId) {
+                        update.execute(params![fid, lines])?;
+                    }
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Exit code recorded for benchmark runs that failed for a reason other than the benchmark
+/// itself, the `error` column of the result table holds the reason
+pub const ERROR_EXIT_CODE: i32 = -1;
+
+pub type RunnerResult<T> = Result<T, Error>;
+
+/// Errors of the runner and DB writer
+#[derive(Debug)]
+pub enum Error {
+    /// The executable could not be started for a benchmark
+    Spawn {
+        benchmark: PathBuf,
+        source: io::Error,
+    },
+    /// A compressed benchmark could not be decompressed
+    Unpack {
+        benchmark: PathBuf,
+        source: io::Error,
+    },
+    /// gcov could not be run on, or its output not be parsed for, the data of a benchmark
+    Gcov { benchmark: PathBuf, message: String },
+    /// Reading from or writing to the report DB failed
+    Db(rusqlite::Error),
+    /// A filesystem operation not tied to a single benchmark failed
+    Io(io::Error),
+    /// The configuration of the run is not usable, e.g. an invalid benchmark pattern
+    Config(String),
+    /// The layout of an existing report DB is not supported, e.g. it was written by a newer
+    /// gen_coverage
+    Schema(String),
+    /// A worker or the DB writer terminated unexpectedly
+    Disconnected,
+    /// The free space on a filesystem used by the run fell below the configured minimum
+    DiskSpace {
+        path: PathBuf,
+        free_mb: u64,
+        /// `None` for filesystems without an inode limit
+        free_inodes: Option<u64>,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spawn { benchmark, source } => write!(
+                f,
+                "Could not run executable on benchmark {}: {}",
+                benchmark.display(),
+                source
+            ),
+            Error::Unpack { benchmark, source } => write!(
+                f,
+                "Could not decompress benchmark {}: {}",
+                benchmark.display(),
+                source
+            ),
+            Error::Gcov { benchmark, message } => write!(
+                f,
+                "Could not collect coverage of benchmark {}: {}",
+                benchmark.display(),
+                message
+            ),
+            Error::Db(e) => write!(f, "Report DB error: {}", e),
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Config(e) => write!(f, "Invalid configuration: {}", e),
+            Error::Schema(e) => write!(f, "Unsupported report DB: {}", e),
+            Error::Disconnected => write!(f, "Worker disconnected unexpectedly"),
+            Error::DiskSpace {
+                path,
+                free_mb,
+                free_inodes,
+            } => {
+                write!(
+                    f,
+                    "Running out of disk space on {} ({} MB",
+                    path.display(),
+                    free_mb
+                )?;
+                if let Some(free_inodes) = free_inodes {
+                    write!(f, ", {} inodes", free_inodes)?;
+                }
+                write!(f, " free)")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Spawn { source, .. } | Error::Unpack { source, .. } => Some(source),
+            Error::Db(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Db(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,57 @@
+//! Runs benchmarks against a (coverage instrumented) executable and stores the execution
+//! results and gcov usage data in a SQLite report DB.
+//!
+//! The `gen_coverage` binary is a thin CLI wrapper around this crate, other tooling can
+//! drive a run directly by creating a [`RunConfig`] and passing it to a [`Runner`]:
+//!
+//! ```no_run
+//! use gen_coverage::config::{EvaluateConfig, RunMode};
+//! use gen_coverage::runner::jobs;
+//! use gen_coverage::{Callbacks, RunConfig, Runner};
+//! use std::sync::Arc;
+//!
+//! let config = RunConfig::new(
+//!     "../cvc5-repo".into(),
+//!     4,
+//!     "../cvc5-repo/build/bin/cvc5 --tlimit 5000 {}".to_string(),
+//!     "reports/report.sqlite".into(),
+//!     RunMode::Evaluate(EvaluateConfig::default()),
+//! )
+//! .unwrap();
+//! let callbacks = Callbacks {
+//!     on_run: Some(Box::new(|job, run| {
+//!         println!("{}: {}", job.benchmark.path.display(), run.exit_code)
+//!     })),
+//!     ..Default::default()
+//! };
+//!
+//! let config = Arc::new(config);
+//! let mut runner = Runner::with_callbacks(config.clone(), callbacks).unwrap();
+//! runner.wait_on_db_ready().unwrap();
+//! let (benchmarks, configurations) = runner.wait_for_all_benchmarks().unwrap();
+//! let jobs = jobs(&config, &benchmarks, &configurations);
+//! let total = jobs.len();
+//! for job in jobs {
+//!     runner.enqueue(job).unwrap();
+//! }
+//! runner.enqueue_worker_stop();
+//!
+//! let mut done = 0;
+//! while done < total {
+//!     done += runner.wait_for_next_bench_done().unwrap() as usize;
+//! }
+//! runner.join();
+//! ```
+pub mod archive;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod plan;
+pub mod runner;
+pub mod trace;
+pub mod types;
+
+pub use config::RunConfig;
+pub use db::DbWriter;
+pub use error::Error;
+pub use runner::{Callbacks, Runner};
@@ -1,25 +1,36 @@
 mod args;
-mod db;
 mod multiwriter;
-mod runner;
-mod types;
-use crate::args::ARGS;
-use crate::types::ResultT;
+use crate::args::CliArgs;
 use std::cmp::min;
 
+use clap::Parser;
 use dur::Duration as DurDuration;
+use gen_coverage::db::migrations;
+use gen_coverage::plan::RunPlan;
+use gen_coverage::runner::{self, Runner};
+use gen_coverage::trace;
+use gen_coverage::{Error, RunConfig};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use indicatif_log_bridge::LogWrapper;
-pub use log::{error, info, warn};
+use log::{info, warn};
 use multiwriter::MultiWriter;
+use rusqlite::{Connection, OpenFlags};
 use std::fs::{remove_dir_all, File};
+use std::io;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
-use types::Benchmark;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    info!("Starting benchmark suite");
+    let args = CliArgs::parse();
+    let dry_run = args.dry_run();
+    let migrate = args.is_migrate();
+    let trace_out = args.trace_out.clone();
+    let run_file = args.into_run_file()?;
+    let log_file = run_file
+        .log_file
+        .clone()
+        .unwrap_or_else(|| "./output.log".into());
 
     let start = Instant::now();
     let running = Arc::new(AtomicBool::new(true));
@@ -34,7 +45,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let log_target = Box::new(MultiWriter::new(
         std::io::stdout(),
-        File::create(&ARGS.log_file).expect("Can't create file"),
+        File::create(&log_file).expect("Can't create file"),
     ));
 
     // Logger Setup
@@ -49,14 +60,68 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     LogWrapper::new(multi.clone(), logger).try_init()?;
     log::set_max_level(level);
 
+    if migrate {
+        let result_db = run_file.result_db.ok_or("Missing result DB")?;
+        let mut conn = Connection::open_with_flags(&result_db, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        let version = migrations::migrate(&mut conn).map_err(|e| e.to_string())?;
+        if version < migrations::SCHEMA_VERSION {
+            println!(
+                "Upgraded {} from schema version {} to {}",
+                result_db.display(),
+                version,
+                migrations::SCHEMA_VERSION
+            );
+        } else {
+            println!(
+                "{} already has schema version {}",
+                result_db.display(),
+                version
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(dry_run) = dry_run {
+        // Don't create a temporary directory for a run that never happens
+        let config = run_file.into_run_config(|| Ok("/tmp/coverage_reports/<new>".into()))?;
+        let plan = RunPlan::resolve(&config, dry_run.timings.as_deref())?;
+        print!("{}", plan);
+        if let Some(plan_out) = dry_run.plan_out {
+            serde_json::to_writer_pretty(File::create(plan_out)?, &plan)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(trace_out) = &trace_out {
+        trace::init(trace_out)?;
+    }
+
+    info!("Starting benchmark suite");
+    let config = Arc::new(run_file.into_run_config(args::default_tmp_dir)?);
+
     // Runner Setup
     info!("Creating runners and waiting on db to be initialized");
-    let mut runner = runner::Runner::new();
-    runner.wait_on_db_ready();
-    let benchmarks: Vec<Benchmark> = runner.wait_for_all_benchmarks();
+    let mut runner = match Runner::new(config.clone()) {
+        Ok(runner) => runner,
+        Err(e) => return fail(&config, e),
+    };
+    let (benchmarks, configurations) = match runner
+        .wait_on_db_ready()
+        .and_then(|_| runner.wait_for_all_benchmarks())
+    {
+        Ok(res) => res,
+        Err(e) => return abort(&mut runner, &config, e),
+    };
+    info!(
+        "Running {} benchmarks under {} configuration(s)",
+        benchmarks.len(),
+        configurations.len()
+    );
 
-    // Fancy overall progress bar
-    let total_count = benchmarks.len();
+    // Fancy overall progress bar, every benchmark and configuration pair (plus the repeated
+    // runs of the nondeterminism detection) is one unit of work
+    let jobs = runner::jobs(&config, &benchmarks, &configurations);
+    let total_count = jobs.len();
     let done_pb = multi.add(ProgressBar::new(total_count as u64));
     done_pb.set_style(
         ProgressStyle::default_bar()
@@ -68,8 +133,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     info!("Enqueuing all benchmarks");
-    for b in benchmarks {
-        runner.enqueue(b);
+    for job in jobs {
+        if let Err(e) = runner.enqueue(job) {
+            return abort(&mut runner, &config, e);
+        }
     }
     // Ensure that workers terminate after all things have been processed
     runner.enqueue_worker_stop();
@@ -87,12 +154,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         };
         done_pb.set_message(eta_msg.clone());
         done_pb.set_position(done_count as u64);
-        if done_count % min(ARGS.job_size, 100) == 0 {
+        if done_count % min(config.job_size, 100) == 0 {
             info!(" Processed {}/{} Benchmark Files", done_count, total_count);
             info!(" {}", eta_msg);
         }
 
-        done_count += runner.wait_for_next_bench_done() as usize;
+        done_count += match runner.wait_for_next_bench_done() {
+            Ok(done) => done as usize,
+            Err(e) => {
+                done_pb.abandon_with_message("Aborted");
+                return abort(&mut runner, &config, e);
+            }
+        };
         // Early return in case of Ctrl+C or in case we already completed all tasks
         if !running.load(Ordering::SeqCst) || done_count == total_count {
             break;
@@ -105,15 +178,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Wait for runners to work of the queue
     runner.join();
+    trace::finish()?;
 
-    if let args::Commands::Coverage { tmp_dir, .. } = &ARGS.command {
-        info!("Deleting the tmp_dir");
-        // Remove the tmp directory
-        remove_dir_all(tmp_dir.clone().unwrap())?;
-    }
+    info!("Deleting the tmp_dir");
+    remove_tmp_dir(&config)?;
 
     let duration = start.elapsed();
     info!("Total time taken: {} milliseconds", duration.as_millis());
 
     Ok(())
 }
+
+/// The DB writer already stored the results collected so far, stop the remaining workers and
+/// free the temporary data before giving up
+fn abort(
+    runner: &mut Runner,
+    config: &RunConfig,
+    e: Error,
+) -> Result<(), Box<dyn std::error::Error>> {
+    runner.enqueue_worker_stop();
+    runner.join();
+    fail(config, e)
+}
+
+/// Frees the temporary data of a run that could not complete
+fn fail(config: &RunConfig, e: Error) -> Result<(), Box<dyn std::error::Error>> {
+    remove_tmp_dir(config).unwrap_or_else(|e| warn!("Could not delete the tmp_dir: {}", e));
+    trace::finish()?;
+    Err(Box::new(e))
+}
+
+/// Evaluate runs only get a scratch directory once a compressed benchmark is unpacked
+fn remove_tmp_dir(config: &RunConfig) -> io::Result<()> {
+    match remove_dir_all(config.run_tmp_dir()) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
@@ -7,7 +7,7 @@ pub struct MultiWriter<W1, W2> {
 
 impl<W1, W2> MultiWriter<W1, W2> {
     pub fn new(w1: W1, w2: W2) -> MultiWriter<W1, W2> {
-        return MultiWriter { w1, w2 };
+        MultiWriter { w1, w2 }
     }
 }
 
@@ -21,7 +21,7 @@ impl<W1: Write, W2: Write> Write for MultiWriter<W1, W2> {
                 if n1 == n2 {
                     Ok(n1)
                 } else {
-                    Err(io::Error::new(io::ErrorKind::Other, "Write sizes differ"))
+                    Err(io::Error::other("Write sizes differ"))
                 }
             }
             (Err(e), _) | (_, Err(e)) => Err(e),
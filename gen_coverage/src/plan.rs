@@ -0,0 +1,408 @@
+use crate::archive::{self, BenchmarkFile};
+use crate::config::{CoverageConfig, RunConfig, RunMode};
+use crate::db::{has_table, migrations};
+use crate::types::{Configuration, ResultT};
+
+use glob::glob;
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Everything a run would do, resolved without running a benchmark or creating the DB
+#[derive(Debug, Serialize)]
+pub struct RunPlan {
+    pub mode: String,
+    pub result_db: PathBuf,
+    pub result_table: String,
+    pub job_size: usize,
+    pub exec_template: Vec<String>,
+    /// Exec template of the pre-screening, the run count includes runs it may screen out
+    pub screening_template: Option<Vec<String>>,
+    pub gcov_version: Option<String>,
+    pub configurations: Vec<Configuration>,
+    pub benchmark_count: usize,
+    /// Number of benchmarks per top-level directory below the benchmark base directory
+    pub benchmarks_per_dir: BTreeMap<String, usize>,
+    pub run_count: usize,
+    pub prefix_usage: Option<PrefixUsage>,
+    pub runtime: Option<RuntimeEstimate>,
+}
+
+/// Expected disk usage of the individual GCOV prefix directories
+#[derive(Debug, Serialize)]
+pub struct PrefixUsage {
+    /// Size of the gcda files a single run produces, measured on the gcda files currently
+    /// present in the build directory
+    pub bytes_per_run: u64,
+    pub gcda_files: usize,
+    /// Prefixes are removed once processed, so at most one per worker exists at a time
+    pub peak_bytes: u64,
+}
+
+/// Runtime estimate based on the timings of prior runs of the same benchmarks
+#[derive(Debug, Serialize)]
+pub struct RuntimeEstimate {
+    pub timings_db: PathBuf,
+    pub timings_table: String,
+    /// Number of benchmarks with a prior timing, the others are assumed to take the mean time
+    pub known_benchmarks: usize,
+    pub mean_ms: u64,
+    pub total_ms: u64,
+    /// Wall clock time with all workers busy
+    pub wall_ms: u64,
+}
+
+impl RunPlan {
+    /// Resolves the plan of the given run. Prior timings are read from `timings_db`, falling
+    /// back to the result DB of the run if it already exists.
+    pub fn resolve(config: &RunConfig, timings_db: Option<&Path>) -> ResultT<Self> {
+        let (mode, benchmarks, configurations, gcov_version, prefix_usage) = match &config.mode {
+            RunMode::Coverage(coverage) => {
+                let benchmarks = benchmark_files(coverage)?
+                    .into_iter()
+                    .map(|b| b.path)
+                    .collect();
+                let prefix_usage = if coverage.individual_prefixes {
+                    Some(estimate_prefix_usage(config)?)
+                } else {
+                    None
+                };
+                (
+                    "coverage",
+                    benchmarks,
+                    coverage.configurations.clone(),
+                    Some(gcov_version()?),
+                    prefix_usage,
+                )
+            }
+            RunMode::Evaluate(_) => {
+                if !config.result_db.exists() {
+                    return Err(Box::from(format!(
+                        "Result DB '{}' does not exist, evaluations append to an existing report DB",
+                        config.result_db.display()
+                    )));
+                }
+                let conn = open_read_only(&config.result_db)?;
+                (
+                    "evaluate",
+                    db_benchmarks(&conn)?,
+                    db_configurations(&conn)?,
+                    None,
+                    None,
+                )
+            }
+        };
+
+        let base_dir = match config.coverage() {
+            Some(coverage) => pattern_base(&coverage.benchmarks),
+            None => common_base(&benchmarks),
+        };
+        let mut benchmarks_per_dir = BTreeMap::new();
+        for benchmark in benchmarks.iter() {
+            *benchmarks_per_dir
+                .entry(top_level_dir(&base_dir, benchmark))
+                .or_insert(0) += 1;
+        }
+
+        let timings_db = match timings_db {
+            Some(db) => Some(db.to_path_buf()),
+            None if config.result_db.exists() => Some(config.result_db.clone()),
+            None => None,
+        };
+        let runtime = match timings_db {
+            Some(db) => estimate_runtime(&db, &benchmarks, configurations.len(), config.job_size)?,
+            None => None,
+        };
+
+        // Sampled benchmarks of the nondeterminism detection are run multiple times
+        let repeated_runs = match config.coverage().and_then(|c| c.stability.as_ref()) {
+            Some(s) => s.samples.min(benchmarks.len()) * (s.runs as usize - 1),
+            None => 0,
+        };
+
+        Ok(RunPlan {
+            mode: mode.to_string(),
+            result_db: config.result_db.clone(),
+            result_table: config.result_table().to_string(),
+            job_size: config.job_size,
+            exec_template: config.exec_template().to_vec(),
+            screening_template: config
+                .screening_config()
+                .map(|s| s.exec_template().to_vec()),
+            gcov_version,
+            run_count: (benchmarks.len() + repeated_runs) * configurations.len(),
+            benchmark_count: benchmarks.len(),
+            benchmarks_per_dir,
+            configurations,
+            prefix_usage,
+            runtime,
+        })
+    }
+}
+
+/// All benchmarks matched by the benchmark pattern of a coverage run, compressed files and
+/// archives are expanded to the benchmarks they contain
+pub fn benchmark_files(coverage: &CoverageConfig) -> ResultT<Vec<BenchmarkFile>> {
+    let mut benchmarks = vec![];
+    for file in glob(&coverage.benchmarks)?.flatten() {
+        benchmarks.extend(archive::expand(&file)?);
+    }
+    Ok(benchmarks)
+}
+
+fn gcov_version() -> ResultT<String> {
+    let output = Command::new("gcov")
+        .arg("--version")
+        .output()
+        .map_err(|e| format!("Could not run gcov: {}", e))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+fn estimate_prefix_usage(config: &RunConfig) -> ResultT<PrefixUsage> {
+    let pattern = format!("{}/**/*.gcda", config.repo_dir.join("build").display());
+    let mut gcda_files = 0;
+    let mut bytes_per_run = 0;
+    for gcda_file in glob(&pattern)?.flatten() {
+        gcda_files += 1;
+        bytes_per_run += fs::metadata(gcda_file)?.len();
+    }
+
+    Ok(PrefixUsage {
+        bytes_per_run,
+        gcda_files,
+        peak_bytes: bytes_per_run * config.job_size as u64,
+    })
+}
+
+fn open_read_only(db: &Path) -> ResultT<Connection> {
+    // Opened as immutable, a read only connection would otherwise leave WAL files behind
+    let conn = Connection::open_with_flags(
+        format!("file:{}?immutable=1", db.canonicalize()?.display()),
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+    )?;
+    // Older reports are read as they are, an evaluation upgrades them when it is run
+    migrations::ensure_supported(&conn)?;
+    Ok(conn)
+}
+
+fn db_benchmarks(conn: &Connection) -> ResultT<Vec<PathBuf>> {
+    let mut stmt = conn.prepare("SELECT path FROM \"benchmarks\" ORDER BY id")?;
+    let rows = stmt.query_map(params![], |row| row.get::<_, String>(0))?;
+    Ok(rows
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn db_configurations(conn: &Connection) -> ResultT<Vec<Configuration>> {
+    if !has_table(conn, "configurations")? {
+        return Ok(vec![Configuration::default()]);
+    }
+    let mut stmt = conn.prepare("SELECT id, name, args FROM \"configurations\" ORDER BY id")?;
+    let rows = stmt.query_map(params![], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+    })?;
+    let mut configurations = vec![];
+    for row in rows {
+        let (id, name, args) = row?;
+        configurations.push(Configuration {
+            id,
+            name,
+            args: shellwords::split(&args)?,
+        });
+    }
+    if configurations.is_empty() {
+        configurations.push(Configuration::default());
+    }
+    Ok(configurations)
+}
+
+fn estimate_runtime(
+    db: &Path,
+    benchmarks: &[PathBuf],
+    configuration_count: usize,
+    job_size: usize,
+) -> ResultT<Option<RuntimeEstimate>> {
+    let conn = open_read_only(db)?;
+
+    // Prefer the coverage timings, otherwise use the most recent evaluation
+    let timings_table = if has_table(&conn, "result_benchmarks")? {
+        "result_benchmarks".to_string()
+    } else {
+        let latest: Option<String> = conn
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'table'
+                    AND name LIKE 'evaluation_benchmarks_%' ORDER BY name DESC LIMIT 1",
+                params![],
+                |row| row.get(0),
+            )
+            .ok();
+        match latest {
+            Some(table) => table,
+            None => return Ok(None),
+        }
+    };
+
+    let query = format!(
+        "SELECT b.path, AVG(r.time_ms) FROM \"{}\" r
+            JOIN \"benchmarks\" b ON b.id = r.bench_id GROUP BY b.path",
+        timings_table
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+    })?;
+    let mut timings = HashMap::new();
+    for row in rows {
+        let (path, time_ms) = row?;
+        timings.insert(PathBuf::from(path), time_ms);
+    }
+    if timings.is_empty() {
+        return Ok(None);
+    }
+
+    let known: Vec<f64> = benchmarks
+        .iter()
+        .filter_map(|b| {
+            let path = b.canonicalize().unwrap_or_else(|_| b.clone());
+            timings.get(&path).copied()
+        })
+        .collect();
+    let mean_ms = if known.is_empty() {
+        timings.values().sum::<f64>() / timings.len() as f64
+    } else {
+        known.iter().sum::<f64>() / known.len() as f64
+    };
+    let unknown = benchmarks.len() - known.len();
+    let total_ms =
+        (known.iter().sum::<f64>() + unknown as f64 * mean_ms) * configuration_count as f64;
+
+    Ok(Some(RuntimeEstimate {
+        timings_db: db.to_path_buf(),
+        timings_table,
+        known_benchmarks: known.len(),
+        mean_ms: mean_ms as u64,
+        total_ms: total_ms as u64,
+        wall_ms: (total_ms / job_size as f64) as u64,
+    }))
+}
+
+/// Directory part of a glob pattern before the first wildcard
+fn pattern_base(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[']))
+        .collect()
+}
+
+fn common_base(paths: &[PathBuf]) -> PathBuf {
+    let mut base = match paths.first().and_then(|p| p.parent()) {
+        Some(p) => p.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+    for path in paths.iter() {
+        while !path.starts_with(&base) {
+            if !base.pop() {
+                return base;
+            }
+        }
+    }
+    base
+}
+
+fn top_level_dir(base: &Path, benchmark: &Path) -> String {
+    let relative = benchmark.strip_prefix(base).unwrap_or(benchmark);
+    let mut components = relative.components();
+    match (components.next(), components.next()) {
+        (Some(dir), Some(_)) => dir.as_os_str().to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return format!("{:.1} {}", value, unit);
+        }
+        value /= 1024.0;
+    }
+    format!("{:.1} TiB", value)
+}
+
+fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!(
+        "{}d {:02}h {:02}m {:02}s",
+        secs / 86400,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+impl fmt::Display for RunPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Mode:           {}", self.mode)?;
+        writeln!(f, "Result DB:      {}", self.result_db.display())?;
+        writeln!(f, "Result table:   {}", self.result_table)?;
+        writeln!(f, "Jobs:           {}", self.job_size)?;
+        writeln!(f, "Exec template:  {:?}", self.exec_template)?;
+        if let Some(screening_template) = &self.screening_template {
+            writeln!(f, "Screening:      {:?}", screening_template)?;
+        }
+        if let Some(gcov_version) = &self.gcov_version {
+            writeln!(f, "gcov:           {}", gcov_version)?;
+        }
+        writeln!(f, "Configurations:")?;
+        for c in self.configurations.iter() {
+            writeln!(f, "  {}", c)?;
+        }
+        writeln!(
+            f,
+            "Benchmarks:     {} ({} runs)",
+            self.benchmark_count, self.run_count
+        )?;
+        for (dir, count) in self.benchmarks_per_dir.iter() {
+            writeln!(f, "  {:<40} {}", dir, count)?;
+        }
+        match &self.prefix_usage {
+            Some(p) if p.gcda_files == 0 => writeln!(
+                f,
+                "Prefix usage:   unknown, no gcda files found in the build directory"
+            )?,
+            Some(p) => writeln!(
+                f,
+                "Prefix usage:   {} per run ({} gcda files), {} peak",
+                format_bytes(p.bytes_per_run),
+                p.gcda_files,
+                format_bytes(p.peak_bytes)
+            )?,
+            None => {}
+        }
+        match &self.runtime {
+            Some(r) => writeln!(
+                f,
+                "Runtime:        {} wall clock, {} total (mean {} ms, {}/{} benchmarks timed in {}:{})",
+                format_duration(r.wall_ms),
+                format_duration(r.total_ms),
+                r.mean_ms,
+                r.known_benchmarks,
+                self.benchmark_count,
+                r.timings_db.display(),
+                r.timings_table
+            )?,
+            None => writeln!(f, "Runtime:        unknown, no prior timings available")?,
+        }
+        Ok(())
+    }
+}
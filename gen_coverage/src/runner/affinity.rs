@@ -0,0 +1,111 @@
+//! Pinning of the workers to dedicated cores, so the timings of parallel runs are not skewed
+//! by migrations between cores or by hyperthreads competing for the same core
+
+use crate::config::RunConfig;
+use crate::error::{Error, RunnerResult};
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+/// The CPUs the workers are pinned to, `None` unless `pin_cpus` is set
+pub(super) fn pinned_cpus(config: &RunConfig) -> RunnerResult<Option<Vec<usize>>> {
+    config
+        .pin_cpus
+        .then(|| worker_cpus(config.job_size))
+        .transpose()
+        .map_err(|e| Error::Config(format!("Could not pin the workers to CPUs: {}", e)))
+}
+
+/// One CPU per worker, each on a physical core of its own, taken from the CPUs the process
+/// may run on. The other hyperthreads of these cores are left idle.
+fn worker_cpus(workers: usize) -> io::Result<Vec<usize>> {
+    distinct_cores(allowed_cpus()?, workers, |cpu| {
+        fs::read_to_string(format!(
+            "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+            cpu
+        ))
+        .unwrap_or_else(|_| cpu.to_string())
+    })
+}
+
+/// The first `workers` of the CPUs that do not share a core, given the siblings list of each
+fn distinct_cores(
+    cpus: impl IntoIterator<Item = usize>,
+    workers: usize,
+    siblings: impl Fn(usize) -> String,
+) -> io::Result<Vec<usize>> {
+    let mut cores = HashSet::new();
+    let mut distinct = vec![];
+    for cpu in cpus {
+        // All hyperthreads of a core share the same siblings list
+        if cores.insert(siblings(cpu).trim().to_string()) {
+            distinct.push(cpu);
+        }
+    }
+    if distinct.len() < workers {
+        return Err(io::Error::other(format!(
+            "Only {} physical cores are available to pin {} workers to",
+            distinct.len(),
+            workers
+        )));
+    }
+    distinct.truncate(workers);
+    Ok(distinct)
+}
+
+/// Pins the calling thread, and every process it spawns from now on, to the CPU
+pub(super) fn pin(cpu: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let set = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        set
+    };
+    Ok((0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two hyperthreads per core, numbered like Linux does: cpu N and N + 4 share a core
+    fn siblings(cpu: usize) -> String {
+        format!("{},{}\n", cpu % 4, cpu % 4 + 4)
+    }
+
+    #[test]
+    fn one_cpu_per_core() {
+        assert_eq!(distinct_cores(0..8, 4, siblings).unwrap(), vec![0, 1, 2, 3]);
+        assert_eq!(distinct_cores(0..8, 2, siblings).unwrap(), vec![0, 1]);
+        // Only the allowed CPUs count, whichever hyperthread of a core comes first wins
+        assert_eq!(
+            distinct_cores([1, 5, 6, 3, 7], 3, siblings).unwrap(),
+            vec![1, 6, 3]
+        );
+    }
+
+    #[test]
+    fn too_few_cores() {
+        let err = distinct_cores(0..8, 5, siblings).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Only 4 physical cores are available to pin 5 workers to"
+        );
+        assert!(distinct_cores([2, 6], 2, siblings).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Keeps an eye on the free space of the filesystems a run writes to. Workers are throttled
+//! while it runs low, and the DB writer stops the run before it runs out.
+
+use crate::config::{DiskPolicy, RunConfig};
+use crate::error::Error;
+
+use log::{error, info, warn};
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Interval in which the free space is measured again, throttled workers and the DB writer
+/// share the last measurement in between
+pub(super) const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Level {
+    Ok = 0,
+    /// Below twice the minimum, only a single worker keeps running
+    Low = 1,
+    /// Below the minimum, the run has to stop
+    Exhausted = 2,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct FreeSpace {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// `None` for filesystems without an inode limit
+    pub inodes: Option<u64>,
+}
+
+impl FreeSpace {
+    pub fn into_error(self) -> Error {
+        Error::DiskSpace {
+            path: self.path,
+            free_mb: self.bytes / MB,
+            free_inodes: self.inodes,
+        }
+    }
+}
+
+/// Level of the fullest filesystem at the time of the measurement
+struct Check {
+    at: Instant,
+    level: Level,
+    free: Option<FreeSpace>,
+}
+
+pub(super) struct DiskGuard {
+    policy: DiskPolicy,
+    /// Directories of the temporary data and the report DB
+    paths: Vec<PathBuf>,
+    /// Size of the report DB, which still has to be written to the filesystem of `paths[0]`
+    pending_bytes: AtomicU64,
+    /// Last reported level, to only log changes
+    level: AtomicU8,
+    last_check: Mutex<Option<Check>>,
+    stopped: AtomicBool,
+}
+
+impl DiskGuard {
+    pub fn new(config: &RunConfig) -> Self {
+        let mut paths = vec![match config.result_db.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }];
+        match config.coverage() {
+            Some(coverage) => paths.push(coverage.tmp_dir.clone()),
+            None => paths.push(config.scratch_dir()),
+        }
+        DiskGuard {
+            policy: config.disk.clone(),
+            paths,
+            pending_bytes: AtomicU64::new(0),
+            level: AtomicU8::new(Level::Ok as u8),
+            last_check: Mutex::new(None),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Updates the size of the report DB that is only written at the end of the run
+    pub fn set_pending_bytes(&self, bytes: u64) {
+        self.pending_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Makes waiting workers give up once the DB writer does not take results anymore
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the last measurement is outdated, so the next check measures again
+    pub fn check_due(&self) -> bool {
+        self.last_check
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|check| check.at.elapsed() >= CHECK_INTERVAL)
+    }
+
+    /// The filesystem below its minimum free space, if any
+    pub fn exhausted(&self) -> Option<FreeSpace> {
+        match self.check() {
+            (Level::Exhausted, free) => free,
+            _ => None,
+        }
+    }
+
+    /// Blocks a worker while the free space is low, only worker 0 keeps running then so the
+    /// run still progresses. Returns false once the run was stopped.
+    pub fn wait(&self, worker: usize) -> bool {
+        loop {
+            if self.stopped.load(Ordering::SeqCst) {
+                return false;
+            }
+            match self.check().0 {
+                Level::Ok => return true,
+                Level::Low if worker == 0 => return true,
+                _ => thread::sleep(CHECK_INTERVAL),
+            }
+        }
+    }
+
+    fn check(&self) -> (Level, Option<FreeSpace>) {
+        let mut last_check = self.last_check.lock().unwrap();
+        if let Some(check) = last_check
+            .as_ref()
+            .filter(|check| check.at.elapsed() < CHECK_INTERVAL)
+        {
+            return (check.level, check.free.clone());
+        }
+        let (level, free) = self.measure();
+        *last_check = Some(Check {
+            at: Instant::now(),
+            level,
+            free: free.clone(),
+        });
+        (level, free)
+    }
+
+    fn measure(&self) -> (Level, Option<FreeSpace>) {
+        let mut worst = (Level::Ok, None);
+        for (i, path) in self.paths.iter().enumerate() {
+            let free = match free_space(path) {
+                Ok(free) => free,
+                Err(e) => {
+                    error!("Could not get the free space of {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let pending = if i == 0 {
+                self.pending_bytes.load(Ordering::Relaxed)
+            } else {
+                0
+            };
+            let level = self.level_of(&free, pending);
+            if level > worst.0 {
+                worst = (level, Some(free));
+            }
+        }
+
+        let previous = self.level.swap(worst.0 as u8, Ordering::Relaxed);
+        if previous != worst.0 as u8 {
+            match &worst {
+                (Level::Ok, _) => info!("Enough free disk space again, resuming all workers"),
+                (Level::Low, Some(free)) => warn!(
+                    "Low disk space on {} ({} MB free), throttling workers",
+                    free.path.display(),
+                    free.bytes / MB
+                ),
+                _ => {}
+            }
+        }
+        worst
+    }
+
+    fn level_of(&self, free: &FreeSpace, pending_bytes: u64) -> Level {
+        let below = |factor: u64| {
+            let min_bytes = self.policy.min_free_mb * factor * MB;
+            let min_inodes = self.policy.min_free_inodes * factor;
+            (min_bytes > 0 && free.bytes < min_bytes + pending_bytes)
+                || free.inodes.is_some_and(|inodes| inodes < min_inodes)
+        };
+        if below(1) {
+            Level::Exhausted
+        } else if below(2) {
+            Level::Low
+        } else {
+            Level::Ok
+        }
+    }
+}
+
+/// Free space of the filesystem of `path`, or of its closest existing ancestor
+fn free_space(path: &Path) -> io::Result<FreeSpace> {
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("/"));
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(FreeSpace {
+        path: path.to_path_buf(),
+        bytes: stat.f_bavail as u64 * stat.f_frsize as u64,
+        inodes: (stat.f_files > 0).then_some(stat.f_favail as u64),
+    })
+}
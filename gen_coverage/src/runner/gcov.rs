@@ -1,185 +1,449 @@
-use crate::args::{Commands, ARGS, TRACK_BRANCHES, TRACK_FUNCS, TRACK_LINES};
-use crate::types::{
-    Benchmark, FilePosition, GcovBranchResult, GcovFuncResult, GcovLineResult, ResultT,
-};
+use super::index::{CoverageIndex, FunctionId, IndexData, IndexKey, InstantiationId};
+use crate::config::RunConfig;
+use crate::error::{Error, RunnerResult};
+use crate::trace;
+use crate::types::{Job, ResultT};
 
 use bitvec::prelude::*;
 use glob::glob;
-use log::error;
-use serde::de::{self, Deserializer, MapAccess, Visitor};
+use log::{debug, error};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
-use serde_json;
 
-use std::borrow::BorrowMut;
-use std::cell::RefCell;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
-use std::fs::{remove_dir_all, remove_file};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename};
 use std::io::{BufRead, BufReader};
 use std::os::unix::fs::symlink;
-use std::process::{exit, Command};
-
-// Maps from SrcFileName -> Line/Function/Branch Identifier -> Result
-pub type GcovRes = HashMap<
-    Box<String>,
-    (
-        HashMap<(u32, u32), RefCell<GcovFuncResult>>,
-        HashMap<u32, RefCell<GcovLineResult>>,
-        HashMap<u32, RefCell<GcovBranchResult>>,
-    ),
->;
-
-pub type GcovBitvec = HashMap<
-    Box<String>,
-    (
-        HashMap<(u32, u32), BitVec<u8, Msb0>>,
-        HashMap<u32, BitVec<u8, Msb0>>,
-        HashMap<u32, BitVec<u8, Msb0>>,
-    ),
->;
-
-pub(super) fn res_to_bitvec(
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Flat vector indexed by the ids of a `CoverageIndex`
+pub type HitVec = BitVec<u64, Lsb0>;
+
+/// Usage of a single benchmark, a set bit marks an executed function, instantiation or line
+#[derive(Debug, Clone, Default)]
+pub struct GcovRes {
+    pub functions: HitVec,
+    pub instantiations: HitVec,
+    pub lines: HitVec,
+    /// Two bits per condition, set if it was seen (in the MC/DC sense) as true respectively
+    /// false
+    pub conditions: HitVec,
+    /// Number of executed blocks per instantiation
+    pub blocks: Vec<u32>,
+}
+
+impl GcovRes {
+    /// Nothing was executed, e.g. as no gcda data was written
+    pub fn is_empty(&self) -> bool {
+        self.functions.not_any() && self.lines.not_any() && self.conditions.not_any()
+    }
+
+    /// Union with another result of the same benchmark, e.g. of a different gcda file
+    pub fn merge(&mut self, other: &GcovRes) {
+        or_assign(&mut self.functions, &other.functions);
+        or_assign(&mut self.instantiations, &other.instantiations);
+        or_assign(&mut self.lines, &other.lines);
+        or_assign(&mut self.conditions, &other.conditions);
+        // Functions defined in headers show up in the gcda file of every translation unit
+        // including them, but only the copy kept by the linker is executed
+        max_assign(&mut self.blocks, &other.blocks);
+    }
+}
+
+/// Marks an id as used, the vector grows as ids may be interned after it was created
+pub(super) fn hit(hits: &mut HitVec, id: u32) {
+    let id = id as usize;
+    if hits.len() <= id {
+        hits.resize(id + 1, false);
+    }
+    hits.set(id, true);
+}
+
+fn or_assign(hits: &mut HitVec, other: &HitVec) {
+    if hits.len() < other.len() {
+        hits.resize(other.len(), false);
+    }
+    // Bits past the end of a vector are never set, so whole words can be combined
+    for (word, other) in hits.as_raw_mut_slice().iter_mut().zip(other.as_raw_slice()) {
+        *word |= *other;
+    }
+}
+
+/// Keeps the larger value of every entry
+fn max_assign(values: &mut Vec<u32>, other: &[u32]) {
+    if values.len() < other.len() {
+        values.resize(other.len(), 0);
+    }
+    for (value, other) in values.iter_mut().zip(other) {
+        *value = (*value).max(*other);
+    }
+}
+
+/// Number of benchmarks that used every function, instantiation and line
+#[derive(Debug, Clone, Default)]
+pub struct GcovCounts {
+    pub functions: Vec<u32>,
+    pub instantiations: Vec<u32>,
+    pub lines: Vec<u32>,
+    /// Two counts per condition, of the benchmarks it was true respectively false in
+    pub conditions: Vec<u32>,
+    /// Most blocks of every function executed by a single benchmark
+    pub function_blocks: Vec<u32>,
+    /// Same for every instantiation
+    pub instantiation_blocks: Vec<u32>,
+}
+
+impl GcovCounts {
+    pub fn add(&mut self, index: &IndexData, result: &GcovRes) {
+        add_hits(&mut self.functions, &result.functions);
+        add_hits(&mut self.instantiations, &result.instantiations);
+        add_hits(&mut self.lines, &result.lines);
+        add_hits(&mut self.conditions, &result.conditions);
+        // Only the functions executed by this benchmark are touched, the blocks of their
+        // instantiations add up
+        let mut executed: Vec<(FunctionId, u32)> = result
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, &blocks)| blocks > 0)
+            .map(|(id, &blocks)| (index.instantiation(id as InstantiationId).function, blocks))
+            .collect();
+        executed.sort_unstable_by_key(|(function, _)| *function);
+        for run in executed.chunk_by(|a, b| a.0 == b.0) {
+            let function = run[0].0 as usize;
+            if self.function_blocks.len() <= function {
+                self.function_blocks.resize(function + 1, 0);
+            }
+            let blocks = run.iter().map(|(_, blocks)| blocks).sum();
+            self.function_blocks[function] = self.function_blocks[function].max(blocks);
+        }
+        max_assign(&mut self.instantiation_blocks, &result.blocks);
+    }
+}
+
+fn add_hits(counts: &mut Vec<u32>, hits: &HitVec) {
+    if counts.len() < hits.len() {
+        counts.resize(hits.len(), 0);
+    }
+    for id in hits.iter_ones() {
+        counts[id] += 1;
+    }
+}
+
+/// Indicator matrix of a configuration, one bitvector over all benchmarks per used id
+#[derive(Debug, Clone, Default)]
+pub struct GcovBitvec {
+    pub functions: Vec<BitVec<u8, Msb0>>,
+    pub lines: Vec<BitVec<u8, Msb0>>,
+}
+
+pub fn res_to_bitvec(
     gcov_bitvec: &mut GcovBitvec,
     no_benchmarks: usize,
     benchmark_id: usize,
     result: &GcovRes,
 ) {
-    for (key, value) in result {
-        gcov_bitvec
-            .borrow_mut()
-            .entry(key.clone())
-            .and_modify(|pvalue| {
-                for (k, v) in &value.0 {
-                    if v.borrow().usage > 0 {
-                        pvalue
-                            .0
-                            .entry(*k)
-                            .and_modify(|e| {
-                                e.set(benchmark_id - 1, true);
-                            })
-                            .or_insert(bitvec![u8, Msb0; 0; no_benchmarks]);
-                    }
-                }
+    for (bitvecs, hits) in [
+        (&mut gcov_bitvec.functions, &result.functions),
+        (&mut gcov_bitvec.lines, &result.lines),
+    ] {
+        if bitvecs.len() < hits.len() {
+            bitvecs.resize_with(hits.len(), BitVec::new);
+        }
+        for id in hits.iter_ones() {
+            let bitvec = &mut bitvecs[id];
+            if bitvec.is_empty() {
+                *bitvec = bitvec![u8, Msb0; 0; no_benchmarks];
+            }
+            bitvec.set(benchmark_id - 1, true);
+        }
+    }
+}
 
-                for (k, v) in &value.1 {
-                    if v.borrow().usage > 0 {
-                        pvalue
-                            .1
-                            .entry(*k)
-                            .and_modify(|e| {
-                                e.set(benchmark_id - 1, true);
-                            })
-                            .or_insert(bitvec![u8, Msb0; 0; no_benchmarks]);
-                    }
-                }
+/// Settings that determine which parts of the gcov JSON output end up in a `GcovRes`
+#[derive(Debug, Clone)]
+pub struct GcovOptions {
+    pub track_funcs: bool,
+    pub track_lines: bool,
+    pub track_branches: bool,
+    pub track_conditions: bool,
+    /// Don't filter out system includes and files from the build directory
+    pub no_ignore_libs: bool,
+    /// Build directory of the measured executable, as given and canonicalized, its files are
+    /// filtered out
+    pub build_dirs: Vec<String>,
+}
 
-                if TRACK_BRANCHES.clone() {
-                    unreachable!();
-                    // for (k, v) in value.2 {
-                    //     pvalue
-                    //         .2
-                    //         .entry(k)
-                    //         .and_modify(|_e| {
-                    //             if ARGS.mode == CoverageMode::Full {
-                    //             }
-                    //         })
-                    //         .or_insert(v);
-                    // }
-                }
-            })
-            .or_insert((HashMap::new(), HashMap::new(), HashMap::new()));
+impl GcovOptions {
+    pub fn from_config(config: &RunConfig) -> Self {
+        let build_dir = config.repo_dir.join("build/");
+        GcovOptions {
+            track_funcs: config.track_funcs(),
+            track_lines: config.track_lines(),
+            track_branches: config.track_branches(),
+            track_conditions: config.track_conditions(),
+            no_ignore_libs: config.coverage().is_some_and(|c| c.no_ignore_libs),
+            build_dirs: [Some(build_dir.clone()), build_dir.canonicalize().ok()]
+                .into_iter()
+                .flatten()
+                .map(|dir| dir.display().to_string())
+                .collect(),
+        }
     }
 }
 
 const CHUNK_SIZE: usize = 20;
 
-pub(super) fn process(benchmark: &Benchmark) -> GcovRes {
-    let individual_prefixes = match &ARGS.command {
-        Commands::Coverage {
-            individual_prefixes,
-            ..
-        } => *individual_prefixes,
-        _ => unreachable!("Unreachable argument combination"),
-    };
+/// Runs gcov on all gcda files produced by the given job and returns the merged result
+pub fn process(job: &Job, config: &RunConfig, index: &CoverageIndex) -> RunnerResult<GcovRes> {
+    let individual_prefixes = config.coverage().is_some_and(|c| c.individual_prefixes);
+    let options = GcovOptions::from_config(config);
 
-    let prefix_dir = match benchmark.prefix.clone() {
-        None => ARGS.repo_dir.clone().join("build/"),
+    let prefix_dir = match job.prefix() {
+        None => config.repo_dir.clone().join("build/"),
         Some(p) => p,
     }
     .display()
     .to_string();
-    let pattern = format!("{}/**/*.gcda", prefix_dir);
 
-    let mut files = vec![];
-    for f in glob(&pattern).expect("Failed to read glob pattern") {
-        if let Ok(gcda_file) = f {
-            files.push(gcda_file)
+    let result = collect(
+        &job.benchmark.path,
+        &prefix_dir,
+        individual_prefixes,
+        job.worker_prefix.is_some(),
+        &options,
+        index,
+    );
+
+    if individual_prefixes {
+        reset_prefix(job);
+    }
+
+    result
+}
+
+/// Removes the gcda data of a job. Prefixes of workers are reused, so only their gcda files
+/// are removed and the gcno symlinks are kept in place.
+pub fn reset_prefix(job: &Job) {
+    let prefix = match job.prefix() {
+        Some(prefix) => prefix,
+        None => return,
+    };
+    if job.worker_prefix.is_none() {
+        remove_dir_all(prefix).unwrap_or_else(|e| debug!("Could not delete prefix dir: {:?}", e));
+        return;
+    }
+    let pattern = format!("{}/**/*.gcda", prefix.display());
+    for gcda_file in glob(&pattern).into_iter().flatten().flatten() {
+        remove_file(gcda_file).unwrap_or_else(|e| error!("Could not remove gcda file: {:?}", e));
+    }
+}
+
+/// Directory the gcda data of the successful runs is merged into, one per worker
+fn union_dir(config: &RunConfig) -> Option<PathBuf> {
+    config
+        .coverage()
+        .filter(|c| c.block_union && c.individual_prefixes)
+        .map(|c| c.tmp_dir.join("block_union"))
+}
+
+fn gcov_tool(benchmark: &Path, args: &[&std::ffi::OsStr]) -> RunnerResult<()> {
+    let gcov_error = |message: String| Error::Gcov {
+        benchmark: benchmark.to_path_buf(),
+        message,
+    };
+    let output = Command::new("gcov-tool")
+        .args(args)
+        .output()
+        .map_err(|e| gcov_error(format!("Could not run gcov-tool: {}", e)))?;
+    if !output.status.success() {
+        return Err(gcov_error(format!(
+            "gcov-tool failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Merges the gcda data of a successful run into the block union of the worker, has to
+/// happen before `process` removes it
+pub fn accumulate_union(job: &Job, config: &RunConfig, worker: usize) -> RunnerResult<()> {
+    let (dir, prefix) = match (union_dir(config), job.prefix()) {
+        (Some(dir), Some(prefix)) if prefix.exists() => (dir, prefix),
+        _ => return Ok(()),
+    };
+    let _span = trace::span("block union merge");
+    let target = dir.join(worker.to_string());
+    if target.exists() {
+        gcov_tool(
+            &job.benchmark.path,
+            &[
+                "merge".as_ref(),
+                "-o".as_ref(),
+                target.as_os_str(),
+                target.as_os_str(),
+                prefix.as_os_str(),
+            ],
+        )
+    } else {
+        // Rewrite next to the union dir and move it in afterwards, so a failed rewrite
+        // leaves no partial target behind
+        let partial = dir.with_file_name(format!("block_union_{}.partial", worker));
+        gcov_tool(
+            &job.benchmark.path,
+            &[
+                "rewrite".as_ref(),
+                "-o".as_ref(),
+                partial.as_os_str(),
+                prefix.as_os_str(),
+            ],
+        )
+        .inspect_err(|_| remove_dir_all(&partial).unwrap_or(()))?;
+        create_dir_all(&dir)?;
+        rename(&partial, &target)?;
+        Ok(())
+    }
+}
+
+/// Blocks executed by any successful run, `None` if the block union is not tracked
+pub fn block_union(config: &RunConfig, index: &CoverageIndex) -> RunnerResult<Option<GcovRes>> {
+    if !config.coverage().is_some_and(|c| c.block_union) {
+        return Ok(None);
+    }
+    let options = GcovOptions::from_config(config);
+    let label = Path::new("<block union>");
+    let dir = match union_dir(config) {
+        Some(dir) => dir,
+        None => {
+            // All runs write to the same gcda files, which therefore already hold the union
+            let build_dir = config.repo_dir.join("build/").display().to_string();
+            return collect(label, &build_dir, false, false, &options, index).map(Some);
         }
+    };
+    if !dir.exists() {
+        debug!("No successful run to compute the block union of");
+        return Ok(Some(GcovRes::default()));
     }
 
+    let mut workers = read_dir(&dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    workers.sort();
+    let Some((target, others)) = workers.split_first() else {
+        debug!("No successful run to compute the block union of");
+        remove_dir_all(&dir).unwrap_or_else(|e| error!("Could not clean up block union: {:?}", e));
+        return Ok(Some(GcovRes::default()));
+    };
+    let result = others
+        .iter()
+        .try_for_each(|other| {
+            gcov_tool(
+                label,
+                &[
+                    "merge".as_ref(),
+                    "-o".as_ref(),
+                    target.as_os_str(),
+                    target.as_os_str(),
+                    other.as_os_str(),
+                ],
+            )
+        })
+        .and_then(|_| {
+            let target = target.display().to_string();
+            collect(label, &target, true, false, &options, index)
+        });
+    remove_dir_all(&dir).unwrap_or_else(|e| error!("Could not clean up block union: {:?}", e));
+    result.map(Some)
+}
+
+/// Runs gcov on all gcda files below the prefix directory. The gcno files are symlinked
+/// next to them if an individual prefix is used, `keep_symlinks` leaves them in place for
+/// the next run using the same prefix.
+fn collect(
+    benchmark: &Path,
+    prefix_dir: &str,
+    individual_prefixes: bool,
+    keep_symlinks: bool,
+    options: &GcovOptions,
+    index: &CoverageIndex,
+) -> RunnerResult<GcovRes> {
+    let gcov_error = |message: String| Error::Gcov {
+        benchmark: benchmark.to_path_buf(),
+        message,
+    };
+
+    let pattern = format!("{}/**/*.gcda", prefix_dir);
+    let files: Vec<PathBuf> = glob(&pattern)
+        .map_err(|e| gcov_error(format!("Invalid gcda pattern: {}", e)))?
+        .flatten()
+        .collect();
+
     let mut ires: Option<GcovRes> = None;
     for gcda_chunk in files.chunks(CHUNK_SIZE) {
         let mut gcno_symlinks = vec![];
         for gcda_file in gcda_chunk {
             if individual_prefixes {
-                let gcno_file_dst = gcda_file.to_str().unwrap();
+                let gcno_file_dst = gcda_file.to_string_lossy();
                 let gcno_file_dst = format!("{}.gcno", &gcno_file_dst[..gcno_file_dst.len() - 5]);
-                let gcno_file_src = gcno_file_dst.strip_prefix(&prefix_dir).unwrap();
-                symlink(&gcno_file_src, &gcno_file_dst).unwrap_or(());
-                gcno_symlinks.push(gcno_file_dst);
+                let gcno_file_src = gcno_file_dst.strip_prefix(prefix_dir).unwrap_or_default();
+                symlink(gcno_file_src, &gcno_file_dst).unwrap_or(());
+                if !keep_symlinks {
+                    gcno_symlinks.push(gcno_file_dst);
+                }
             }
         }
 
-        let chunk_args: Vec<&str> = gcda_chunk.iter().map(|p| p.to_str().unwrap()).collect();
+        let chunk_args: Vec<&std::ffi::OsStr> = gcda_chunk.iter().map(|p| p.as_os_str()).collect();
         let args = ["--json-format", "--stdout"]; // gcda_file.to_str().unwrap()];
+        let span = trace::span("gcov chunk").arg("gcda_files", gcda_chunk.len());
         let output = Command::new("gcov")
-            .args(&args)
+            .args(args)
             .args(&chunk_args)
             .output()
-            .expect("Could not capture output of gcov...");
-        let stderr = String::from_utf8(output.stderr).unwrap();
+            .map_err(|e| gcov_error(format!("Could not run gcov: {}", e)))?;
+        drop(span);
         if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
             error!(
                 "Gcov failed with error!\n GCDA Files: {:?} \n ERROR: {:?}",
                 &gcda_chunk, stderr
             );
-            continue;
+            return Err(gcov_error(format!(
+                "gcov failed ({}): {}",
+                output.status,
+                stderr.trim()
+            )));
         }
 
         // let mut deserializer = JsonDeserializer::from_slice(output.stdout.as_slice());
         let reader = BufReader::new(output.stdout.as_slice());
 
+        let span = trace::span("JSON parse").arg("bytes", output.stdout.len());
         for line_result in reader.lines() {
-            match line_result {
-                Ok(line) => {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
-                    }
-                    match serde_json::from_str(&line) {
-                        Ok(gcov_json) => {
-                            let new_res = interpret_gcov(&gcov_json)
-                                .expect("Error while interpreting gcov output");
-                            match ires.borrow_mut() {
-                                Some(r) => {
-                                    merge_gcov(r, new_res, MergeKind::MAX);
-                                }
-                                None => {
-                                    ires = Some(new_res);
-                                }
-                            };
-                        }
-                        Err(e) => {
-                            error!("{}\n\nCould not parse GCOV json:\n{}", line, e);
-                            exit(1);
-                        }
-                    }
+            let line = line_result
+                .map_err(|e| gcov_error(format!("Could not read gcov output: {}", e)))?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse_gcov_json(line, options, index) {
+                Ok(new_res) => {
+                    match ires.as_mut() {
+                        Some(r) => r.merge(&new_res),
+                        None => ires = Some(new_res),
+                    };
                 }
-                Err(_) => {
-                    error!("Could not parse GCOV output line")
+                Err(e) => {
+                    debug!("{}\n\nCould not parse GCOV json", line);
+                    return Err(gcov_error(format!("Could not parse gcov json: {}", e)));
                 }
             }
         }
+        drop(span);
 
         // Delete the gcda file gcno file if it was symlinked
         if individual_prefixes {
@@ -194,180 +458,266 @@ pub(super) fn process(benchmark: &Benchmark) -> GcovRes {
         }
     }
 
-    if individual_prefixes {
-        remove_dir_all(prefix_dir)
-            .unwrap_or_else(|e| error!("Could not clean up prefix folder: {:?}", e));
-    }
-
-    ires.unwrap_or_else(|| {
+    Ok(ires.unwrap_or_else(|| {
         error!("No result created...");
-        HashMap::new()
-    })
+        GcovRes::default()
+    }))
 }
 
-#[derive(PartialEq)]
-pub enum MergeKind {
-    SUM,
-    MAX,
-}
-
-pub fn merge_gcov(res0: &mut GcovRes, res1: GcovRes, kind: MergeKind) {
-    for (key, value) in res1 {
-        res0.borrow_mut()
-            .entry(key)
-            .and_modify(|pvalue| {
-                for (k, v) in &value.0 {
-                    pvalue
-                        .0
-                        .entry(*k)
-                        .and_modify(|e| {
-                            let v_usage = v.borrow().usage;
-                            let e = e.get_mut();
-                            e.usage = if kind == MergeKind::MAX {
-                                e.usage.max(v_usage)
-                            } else {
-                                e.usage + v_usage
-                            };
-                        })
-                        .or_insert(RefCell::clone(v));
-                }
+/// Parses a single line of `gcov --json-format --stdout` output
+pub fn parse_gcov_json(
+    line: &str,
+    options: &GcovOptions,
+    index: &CoverageIndex,
+) -> ResultT<GcovRes> {
+    let mut deserializer = serde_json::Deserializer::from_str(line);
+    let json = GcovJsonSeed(options).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    interpret_gcov(&json, options, index)
+}
 
-                for (k, v) in &value.1 {
-                    pvalue
-                        .1
-                        .entry(*k)
-                        .and_modify(|e| {
-                            let v_usage = v.borrow().usage;
-                            let e = e.get_mut();
-                            let new_val = if kind == MergeKind::MAX {
-                                e.usage.max(v_usage)
-                            } else {
-                                e.usage + v_usage
-                            };
-                            e.usage = new_val;
-                        })
-                        .or_insert(RefCell::clone(v));
-                }
+fn interpret_gcov(
+    json: &GcovJson,
+    options: &GcovOptions,
+    index: &CoverageIndex,
+) -> ResultT<GcovRes> {
+    // Ignore include files and build dir files, as we can not optimize over them anyways
+    let files: Vec<&FileElement> = json
+        .files
+        .iter()
+        .filter(|file| {
+            options.no_ignore_libs
+                || !(file.file.starts_with("/usr/include")
+                    || options
+                        .build_dirs
+                        .iter()
+                        .any(|dir| file.file.starts_with(dir.as_str())))
+        })
+        .collect();
 
-                if TRACK_BRANCHES.clone() {
-                    unreachable!();
-                    // for (k, v) in value.2 {
-                    //     pvalue
-                    //         .2
-                    //         .entry(k)
-                    //         .and_modify(|_e| {
-                    //             if ARGS.mode == CoverageMode::Full {
-                    //             }
-                    //         })
-                    //         .or_insert(v);
-                    // }
-                }
-            })
-            .or_insert(value);
+    if options.track_branches {
+        // TODO: Add support for branch tracking
+        unimplemented!("Branch tracking not yet supported")
     }
-}
 
-fn interpret_gcov(json: &GcovJson) -> ResultT<GcovRes> {
-    let mut result: GcovRes = HashMap::new();
-    let no_ignore_libs = match &ARGS.command {
-        Commands::Coverage { no_ignore_libs, .. } => *no_ignore_libs,
-        _ => unreachable!("Unreachable argument combination"),
+    // Almost all entries are known after the first few benchmarks, so the write lock is
+    // only taken if something new shows up
+    let known = {
+        let data = index.read();
+        collect_hits(&files, options, |key| data.get(key))
     };
-
-    for file in &json.files {
-        // Ignore include files and build dir files, as we can not optimize over them anyways
-        let build_dir = &ARGS.repo_dir.join("build/");
-        if !no_ignore_libs
-            && (file.file.starts_with("/usr/include")
-                || file.file.starts_with(&build_dir.display().to_string())
-                || file
-                    .file
-                    .starts_with(&build_dir.canonicalize()?.display().to_string()))
-        {
-            continue;
+    Ok(match known {
+        Some(result) => result,
+        None => {
+            let mut data = index.write();
+            collect_hits(&files, options, |key| Some(data.intern(key))).unwrap()
         }
+    })
+}
+
+/// Usage of the given files, `None` as soon as an id can not be resolved
+fn collect_hits(
+    files: &[&FileElement],
+    options: &GcovOptions,
+    mut id: impl FnMut(&IndexKey) -> Option<u32>,
+) -> Option<GcovRes> {
+    let mut result = GcovRes::default();
+    for file in files {
+        let source = id(&IndexKey::Source(&file.file))?;
+        // Lines name the (mangled) instantiation they belong to
+        let mut owners: HashMap<&str, FunctionId> = HashMap::new();
 
-        // Filter out libraries unless specified otherwise
-        let mut funcs: HashMap<(u32, u32), RefCell<GcovFuncResult>> = HashMap::new();
         if let Some(fs) = &file.functions {
             for function in fs {
-                let usage = (function.execution_count as u32 > 0) as u32;
-                let name = function.demangled_name.clone();
-                funcs.insert(
-                    (function.start_line, function.start_column),
-                    RefCell::from(GcovFuncResult {
-                        name,
-                        start: FilePosition {
-                            line: function.start_line,
-                            col: function.start_column,
-                        },
-                        end: FilePosition {
-                            line: function.end_line,
-                            col: function.end_column,
-                        },
-                        usage,
-                    }),
-                );
+                // All instantiations of a template share the source range of their body
+                let function_id = id(&IndexKey::Function {
+                    source,
+                    start: (function.start_line, function.start_column),
+                    end: (function.end_line, function.end_column),
+                    name: &function.demangled_name,
+                })?;
+                let instantiation_id = id(&IndexKey::Instantiation {
+                    function: function_id,
+                    name: &function.name,
+                    demangled_name: &function.demangled_name,
+                    blocks: function.blocks,
+                })?;
+                owners.insert(&function.name, function_id);
+                if function.blocks_executed > 0 {
+                    let blocks = &mut result.blocks;
+                    if blocks.len() <= instantiation_id as usize {
+                        blocks.resize(instantiation_id as usize + 1, 0);
+                    }
+                    blocks[instantiation_id as usize] = function.blocks_executed;
+                }
+                if function.execution_count as u32 > 0 {
+                    hit(&mut result.functions, function_id);
+                    hit(&mut result.instantiations, instantiation_id);
+                }
             }
         }
 
-        let mut lines: HashMap<u32, RefCell<GcovLineResult>> = HashMap::new();
         if let Some(ls) = &file.lines {
             for line in ls {
-                let usage = (line.count as u32 > 0) as u32;
-                lines.insert(
-                    line.line_number,
-                    RefCell::from(GcovLineResult {
+                let function = line
+                    .function_name
+                    .as_deref()
+                    .and_then(|name| owners.get(name).copied());
+                if options.track_lines {
+                    let line_id = id(&IndexKey::Line {
+                        source,
                         line_no: line.line_number,
-                        usage,
-                    }),
-                );
-            }
-        }
+                        function,
+                    })?;
+                    if line.count as u32 > 0 {
+                        hit(&mut result.lines, line_id);
+                    }
+                }
 
-        let branches: HashMap<u32, RefCell<GcovBranchResult>> = HashMap::new();
-        if TRACK_BRANCHES.clone() {
-            // TODO: Add support for branch tracking
-            unimplemented!("Branch tracking not yet supported")
+                let decisions = match &line.conditions {
+                    Some(decisions) if options.track_conditions => decisions,
+                    _ => continue,
+                };
+                for (decision, element) in decisions.iter().enumerate() {
+                    // Every condition has a true and a false outcome
+                    for condition in 0..element.count / 2 {
+                        let condition_id = id(&IndexKey::Condition {
+                            source,
+                            line_no: line.line_number,
+                            decision: decision as u32,
+                            condition,
+                            function,
+                        })?;
+                        if !element.not_covered_true.contains(&condition) {
+                            hit(&mut result.conditions, 2 * condition_id);
+                        }
+                        if !element.not_covered_false.contains(&condition) {
+                            hit(&mut result.conditions, 2 * condition_id + 1);
+                        }
+                    }
+                }
+            }
         }
-
-        result.insert(Box::from(file.file.clone()), (funcs, lines, branches));
     }
-
-    Ok(result)
+    Some(result)
 }
 
-#[derive(Debug, Deserialize)]
-struct GcovJson {
+#[derive(Debug)]
+struct GcovJson<'a> {
     // current_working_directory: String,
     // data_file: String,
     // format_version: String,
     // gcc_version: String,
-    files: Vec<FileElement>,
+    files: Vec<FileElement<'a>>,
 }
 
+/// Deserializes a `GcovJson`, skipping over all parts the options do not track
+struct GcovJsonSeed<'a>(&'a GcovOptions);
+
+impl<'de> DeserializeSeed<'de> for GcovJsonSeed<'_> {
+    type Value = GcovJson<'de>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<GcovJson<'de>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for GcovJsonSeed<'_> {
+    type Value = GcovJson<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map representing GcovJson")
+    }
+
+    fn visit_map<V>(self, mut map: V) -> Result<GcovJson<'de>, V::Error>
+    where
+        V: MapAccess<'de>,
+    {
+        let mut files = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "files" => {
+                    if files.is_some() {
+                        return Err(de::Error::duplicate_field("files"));
+                    }
+                    files = Some(map.next_value_seed(FileElementsSeed(self.0))?);
+                }
+                _ => {
+                    // Skip unknown fields
+                    let _ = map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        Ok(GcovJson {
+            files: files.ok_or_else(|| de::Error::missing_field("files"))?,
+        })
+    }
+}
+
+struct FileElementsSeed<'a>(&'a GcovOptions);
+
+impl<'de> DeserializeSeed<'de> for FileElementsSeed<'_> {
+    type Value = Vec<FileElement<'de>>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Vec<FileElement<'de>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FileElementsSeed<'_> {
+    type Value = Vec<FileElement<'de>>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of FileElements")
+    }
+
+    fn visit_seq<V>(self, mut seq: V) -> Result<Vec<FileElement<'de>>, V::Error>
+    where
+        V: SeqAccess<'de>,
+    {
+        let mut files = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(file) = seq.next_element_seed(FileElementSeed(self.0))? {
+            files.push(file);
+        }
+        Ok(files)
+    }
+}
+
+/// Names are borrowed from the gcov output where possible, there are a lot of them
 #[derive(Debug, Deserialize)]
-struct FunctionElement {
-    // blocks: u32,
-    // blocks_executed: u32,
-    demangled_name: String,
+struct FunctionElement<'a> {
+    blocks: u32,
+    blocks_executed: u32,
+    #[serde(borrow)]
+    demangled_name: Cow<'a, str>,
     end_column: u32,
     end_line: u32,
     execution_count: f64,
-    // name: String,
+    #[serde(borrow)]
+    name: Cow<'a, str>,
     start_column: u32,
     start_line: u32,
 }
 
 #[derive(Debug, Deserialize)]
-struct LineElement {
+struct LineElement<'a> {
     line_number: u32,
     count: f64,
-    // function_name: Option<String>, //TODO: Also incorporate this information into the DB
+    /// Mangled name of the instantiation containing the line
+    #[serde(borrow)]
+    function_name: Option<Cow<'a, str>>,
+    /// Only reported for executables built with `-fcondition-coverage`
+    conditions: Option<Vec<ConditionElement>>,
     // unexecuted_block: bool,
     // branches: Option<Vec<BranchElement>>,
     // calls: Option<Vec<CallElement>>,
-    // conditions: Option<Vec<ConditionElement>>,
 }
 
 #[allow(dead_code)]
@@ -388,40 +738,42 @@ struct CallElement {
     source_block_id: u32,
 }
 
-#[allow(dead_code)]
+/// A decision, `count` is the number of condition outcomes (two per condition)
 #[derive(Debug, Deserialize)]
 struct ConditionElement {
     count: u32,
-    covered: u32,
+    // covered: u32,
     not_covered_true: Vec<u32>,
     not_covered_false: Vec<u32>,
 }
 
 #[derive(Debug)]
-struct FileElement {
+struct FileElement<'a> {
     file: String,
-    functions: Option<Vec<FunctionElement>>,
-    lines: Option<Vec<LineElement>>,
+    functions: Option<Vec<FunctionElement<'a>>>,
+    lines: Option<Vec<LineElement<'a>>>,
 }
-impl<'de> Deserialize<'de> for FileElement {
-    fn deserialize<D>(deserializer: D) -> Result<FileElement, D::Error>
+struct FileElementSeed<'a>(&'a GcovOptions);
+
+impl<'de> DeserializeSeed<'de> for FileElementSeed<'_> {
+    type Value = FileElement<'de>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<FileElement<'de>, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_map(FileElementVisitor)
+        deserializer.deserialize_map(self)
     }
 }
 
-struct FileElementVisitor;
-
-impl<'de> Visitor<'de> for FileElementVisitor {
-    type Value = FileElement;
+impl<'de> Visitor<'de> for FileElementSeed<'_> {
+    type Value = FileElement<'de>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         formatter.write_str("a map representing FileElement")
     }
 
-    fn visit_map<V>(self, mut map: V) -> Result<FileElement, V::Error>
+    fn visit_map<V>(self, mut map: V) -> Result<FileElement<'de>, V::Error>
     where
         V: MapAccess<'de>,
     {
@@ -441,7 +793,7 @@ impl<'de> Visitor<'de> for FileElementVisitor {
                     if functions.is_some() {
                         return Err(de::Error::duplicate_field("functions"));
                     }
-                    if TRACK_FUNCS.clone() {
+                    if self.0.track_funcs {
                         functions = Some(map.next_value()?);
                     } else {
                         let _ = map.next_value::<de::IgnoredAny>()?;
@@ -451,7 +803,7 @@ impl<'de> Visitor<'de> for FileElementVisitor {
                     if lines.is_some() {
                         return Err(de::Error::duplicate_field("lines"));
                     }
-                    if TRACK_LINES.clone() {
+                    if self.0.track_lines || self.0.track_conditions {
                         lines = Some(map.next_value()?);
                     } else {
                         let _ = map.next_value::<de::IgnoredAny>()?;
@@ -475,3 +827,211 @@ impl<'de> Visitor<'de> for FileElementVisitor {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// gcov output of two benchmarks, the first one with two translation units including
+    /// the same header
+    const BENCHMARKS: [&[&str]; 2] = [
+        &[
+            include_str!("../../tests/fixtures/gcov/a_solver.json"),
+            include_str!("../../tests/fixtures/gcov/a_other.json"),
+        ],
+        &[include_str!("../../tests/fixtures/gcov/b_solver.json")],
+    ];
+
+    fn options() -> GcovOptions {
+        GcovOptions {
+            track_funcs: true,
+            track_lines: true,
+            track_branches: false,
+            track_conditions: false,
+            no_ignore_libs: false,
+            build_dirs: vec!["/repo/build/".to_string()],
+        }
+    }
+
+    /// Usage counts keyed by source and function start, instantiation name and line number
+    #[derive(Debug, Default, PartialEq)]
+    struct Usage {
+        functions: HashMap<(String, (u32, u32)), u32>,
+        instantiations: HashMap<(String, (u32, u32), String), u32>,
+        lines: HashMap<(String, u32), u32>,
+    }
+
+    impl Usage {
+        fn merge(&mut self, other: Usage, merge: fn(u32, u32) -> u32) {
+            for (k, v) in other.functions {
+                let e = self.functions.entry(k).or_insert(0);
+                *e = merge(*e, v);
+            }
+            for (k, v) in other.instantiations {
+                let e = self.instantiations.entry(k).or_insert(0);
+                *e = merge(*e, v);
+            }
+            for (k, v) in other.lines {
+                let e = self.lines.entry(k).or_insert(0);
+                *e = merge(*e, v);
+            }
+        }
+    }
+
+    /// Usage as computed before the interning, with a nested map per source file: the gcov
+    /// outputs of a benchmark merged with MAX, the benchmarks with SUM
+    fn reference_usage() -> Usage {
+        let options = options();
+        let mut total = Usage::default();
+        for outputs in BENCHMARKS {
+            let mut benchmark = Usage::default();
+            for output in outputs {
+                let json: serde_json::Value = serde_json::from_str(output).unwrap();
+                let mut usage = Usage::default();
+                for file in json["files"].as_array().unwrap() {
+                    let path = file["file"].as_str().unwrap().to_string();
+                    if path.starts_with("/usr/include")
+                        || options.build_dirs.iter().any(|d| path.starts_with(d))
+                    {
+                        continue;
+                    }
+                    for f in file["functions"].as_array().unwrap() {
+                        let start = (
+                            f["start_line"].as_u64().unwrap() as u32,
+                            f["start_column"].as_u64().unwrap() as u32,
+                        );
+                        let used = (f["execution_count"].as_f64().unwrap() as u32 > 0) as u32;
+                        let e = usage.functions.entry((path.clone(), start)).or_insert(used);
+                        *e = (*e).max(used);
+                        let name = f["name"].as_str().unwrap().to_string();
+                        usage
+                            .instantiations
+                            .insert((path.clone(), start, name), used);
+                    }
+                    for l in file["lines"].as_array().unwrap() {
+                        let line_no = l["line_number"].as_u64().unwrap() as u32;
+                        let used = (l["count"].as_f64().unwrap() as u32 > 0) as u32;
+                        usage.lines.insert((path.clone(), line_no), used);
+                    }
+                }
+                benchmark.merge(usage, u32::max);
+            }
+            total.merge(benchmark, |a, b| a + b);
+        }
+        total
+    }
+
+    /// Interns all outputs into `index`, returning the usage counts over all benchmarks
+    fn interned_counts(index: &CoverageIndex) -> GcovCounts {
+        let options = options();
+        let mut counts = GcovCounts::default();
+        for outputs in BENCHMARKS {
+            let mut result = GcovRes::default();
+            for output in outputs {
+                result.merge(&parse_gcov_json(output.trim(), &options, index).unwrap());
+            }
+            counts.add(&index.read(), &result);
+        }
+        counts
+    }
+
+    #[test]
+    fn interned_merge_matches_reference() {
+        let index = CoverageIndex::default();
+        let counts = interned_counts(&index);
+        let data = index.read();
+        let count = |counts: &[u32], id: usize| counts.get(id).copied().unwrap_or(0);
+
+        let mut usage = Usage::default();
+        for id in 0..data.function_count() {
+            let f = data.function(id as FunctionId);
+            let key = (
+                data.source(f.source).to_string(),
+                (f.start.line, f.start.col),
+            );
+            usage.functions.insert(key, count(&counts.functions, id));
+        }
+        for id in 0..data.instantiation_count() {
+            let i = data.instantiation(id as InstantiationId);
+            let f = data.function(i.function);
+            let key = (
+                data.source(f.source).to_string(),
+                (f.start.line, f.start.col),
+                i.name.clone(),
+            );
+            usage
+                .instantiations
+                .insert(key, count(&counts.instantiations, id));
+        }
+        for id in 0..data.line_count() {
+            let l = data.line(id as u32);
+            let key = (data.source(l.source).to_string(), l.line_no);
+            usage.lines.insert(key, count(&counts.lines, id));
+        }
+
+        assert_eq!(usage, reference_usage());
+    }
+
+    #[test]
+    fn interned_merge_counts() {
+        let index = CoverageIndex::default();
+        let counts = interned_counts(&index);
+        let data = index.read();
+        let function = |path: &str, line: u32| {
+            (0..data.function_count())
+                .find(|&id| {
+                    let f = data.function(id as FunctionId);
+                    data.source(f.source) == path && f.start.line == line
+                })
+                .unwrap()
+        };
+
+        // Ignored libraries and build dir files are not interned
+        assert_eq!(data.source_count(), 2);
+        assert_eq!(counts.functions[function("/repo/src/solver.c", 1)], 2);
+        assert_eq!(counts.functions[function("/repo/src/solver.c", 12)], 1);
+        assert_eq!(counts.functions[function("/repo/src/solver.c", 22)], 0);
+        // Both instantiations share the function, each one is used in one unit of benchmark a
+        let max = function("/repo/src/util.h", 3);
+        assert_eq!(counts.functions[max], 1);
+        assert_eq!(data.instantiation_count(), 5);
+        assert_eq!(counts.instantiations.iter().sum::<u32>(), 5);
+        // Blocks of the two instantiations executed by benchmark a add up
+        assert_eq!(counts.function_blocks[max], 4);
+    }
+
+    #[test]
+    fn block_union_without_merged_runs() {
+        use crate::config::RunFile;
+
+        let tmp_dir =
+            std::env::temp_dir().join(format!("gen_coverage_union_{}", std::process::id()));
+        let config = RunFile::parse(&format!(
+            r#"
+repo = "/repo"
+exec = "/repo/build/solver {{}}"
+result_db = "/reports/union.sqlite"
+
+[coverage]
+benchmarks = "/benchmarks/*.smt2"
+use_prefixes = true
+block_union = true
+tmp_dir = "{}"
+"#,
+            tmp_dir.display()
+        ))
+        .unwrap()
+        .into_run_config(|| unreachable!())
+        .unwrap();
+        // The union dir of a run whose merges all failed before one was written
+        let dir = union_dir(&config).unwrap();
+        create_dir_all(&dir).unwrap();
+
+        let union = block_union(&config, &CoverageIndex::default())
+            .unwrap()
+            .unwrap();
+        assert!(union.is_empty());
+        assert!(!dir.exists());
+        remove_dir_all(&tmp_dir).unwrap();
+    }
+}
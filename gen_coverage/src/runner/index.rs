@@ -0,0 +1,281 @@
+use crate::types::FilePosition;
+
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Ids handed out by the `CoverageIndex`, they are dense and start at 0 so results can be
+/// stored in flat vectors indexed by them
+pub type SourceId = u32;
+pub type FunctionId = u32;
+pub type InstantiationId = u32;
+pub type LineId = u32;
+pub type ConditionId = u32;
+
+#[derive(Debug, Clone)]
+pub struct FunctionInfo {
+    pub source: SourceId,
+    /// Demangled name of the first instantiation seen
+    pub name: String,
+    pub start: FilePosition,
+    pub end: FilePosition,
+}
+
+/// A template instantiation or constructor/destructor variant of a function
+#[derive(Debug, Clone)]
+pub struct InstantiationInfo {
+    pub function: FunctionId,
+    /// Mangled name
+    pub name: String,
+    pub demangled_name: String,
+    /// Number of basic blocks, as reported by gcov
+    pub blocks: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct LineInfo {
+    pub source: SourceId,
+    pub line_no: u32,
+    /// Function the line belongs to, as reported by gcov. Only known if functions are tracked.
+    pub function: Option<FunctionId>,
+}
+
+/// A single condition of a decision (boolean expression), identified by its position
+#[derive(Debug, Clone)]
+pub struct ConditionInfo {
+    pub source: SourceId,
+    pub line_no: u32,
+    /// Index of the decision among the decisions of its line
+    pub decision: u32,
+    /// Index of the condition within its decision
+    pub condition: u32,
+    /// Function the decision belongs to, only known if functions are tracked
+    pub function: Option<FunctionId>,
+}
+
+/// Entry of the index, as it is found in the gcov output
+pub enum IndexKey<'a> {
+    Source(&'a str),
+    Function {
+        source: SourceId,
+        start: (u32, u32),
+        end: (u32, u32),
+        name: &'a str,
+    },
+    Instantiation {
+        function: FunctionId,
+        name: &'a str,
+        demangled_name: &'a str,
+        blocks: u32,
+    },
+    Line {
+        source: SourceId,
+        line_no: u32,
+        function: Option<FunctionId>,
+    },
+    Condition {
+        source: SourceId,
+        line_no: u32,
+        decision: u32,
+        condition: u32,
+        function: Option<FunctionId>,
+    },
+}
+
+/// Sources, functions, instantiations, lines and conditions seen in the gcov output of any benchmark,
+/// shared by all workers of a run so every one of them uses the same ids
+#[derive(Debug, Default)]
+pub struct CoverageIndex {
+    data: RwLock<IndexData>,
+}
+
+impl CoverageIndex {
+    pub fn read(&self) -> RwLockReadGuard<'_, IndexData> {
+        self.data.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, IndexData> {
+        self.data.write().unwrap()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IndexData {
+    sources: Vec<String>,
+    source_ids: HashMap<String, SourceId>,
+    functions: Vec<FunctionInfo>,
+    function_ids: HashMap<(SourceId, (u32, u32)), FunctionId>,
+    instantiations: Vec<InstantiationInfo>,
+    instantiation_ids: HashMap<FunctionId, HashMap<String, InstantiationId>>,
+    lines: Vec<LineInfo>,
+    line_ids: HashMap<(SourceId, u32), LineId>,
+    conditions: Vec<ConditionInfo>,
+    condition_ids: HashMap<(SourceId, u32, u32, u32), ConditionId>,
+}
+
+impl IndexData {
+    pub fn source(&self, id: SourceId) -> &str {
+        &self.sources[id as usize]
+    }
+
+    pub fn function(&self, id: FunctionId) -> &FunctionInfo {
+        &self.functions[id as usize]
+    }
+
+    pub fn instantiation(&self, id: InstantiationId) -> &InstantiationInfo {
+        &self.instantiations[id as usize]
+    }
+
+    pub fn line(&self, id: LineId) -> &LineInfo {
+        &self.lines[id as usize]
+    }
+
+    pub fn condition(&self, id: ConditionId) -> &ConditionInfo {
+        &self.conditions[id as usize]
+    }
+
+    pub fn source_count(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn instantiation_count(&self) -> usize {
+        self.instantiations.len()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn condition_count(&self) -> usize {
+        self.conditions.len()
+    }
+
+    /// Sums up values given per instantiation for every function, e.g. numbers of blocks
+    pub fn sum_per_function(&self, per_instantiation: impl Fn(InstantiationId) -> u32) -> Vec<u32> {
+        let mut sums = vec![0; self.functions.len()];
+        for (id, inst) in self.instantiations.iter().enumerate() {
+            sums[inst.function as usize] += per_instantiation(id as InstantiationId);
+        }
+        sums
+    }
+
+    /// Id of an entry, if it was interned before
+    pub fn get(&self, key: &IndexKey) -> Option<u32> {
+        match key {
+            IndexKey::Source(path) => self.source_ids.get(*path).copied(),
+            IndexKey::Function { source, start, .. } => {
+                self.function_ids.get(&(*source, *start)).copied()
+            }
+            IndexKey::Instantiation { function, name, .. } => self
+                .instantiation_ids
+                .get(function)
+                .and_then(|ids| ids.get(*name))
+                .copied(),
+            IndexKey::Line {
+                source, line_no, ..
+            } => self.line_ids.get(&(*source, *line_no)).copied(),
+            IndexKey::Condition {
+                source,
+                line_no,
+                decision,
+                condition,
+                ..
+            } => self
+                .condition_ids
+                .get(&(*source, *line_no, *decision, *condition))
+                .copied(),
+        }
+    }
+
+    /// Id of an entry, it is added to the index if not yet known
+    pub fn intern(&mut self, key: &IndexKey) -> u32 {
+        if let Some(id) = self.get(key) {
+            return id;
+        }
+        match *key {
+            IndexKey::Source(path) => {
+                let id = self.sources.len() as SourceId;
+                self.sources.push(path.to_string());
+                self.source_ids.insert(path.to_string(), id);
+                id
+            }
+            IndexKey::Function {
+                source,
+                start,
+                end,
+                name,
+            } => {
+                let id = self.functions.len() as FunctionId;
+                self.functions.push(FunctionInfo {
+                    source,
+                    name: name.to_string(),
+                    start: FilePosition {
+                        line: start.0,
+                        col: start.1,
+                    },
+                    end: FilePosition {
+                        line: end.0,
+                        col: end.1,
+                    },
+                });
+                self.function_ids.insert((source, start), id);
+                id
+            }
+            IndexKey::Instantiation {
+                function,
+                name,
+                demangled_name,
+                blocks,
+            } => {
+                let id = self.instantiations.len() as InstantiationId;
+                self.instantiations.push(InstantiationInfo {
+                    function,
+                    name: name.to_string(),
+                    demangled_name: demangled_name.to_string(),
+                    blocks,
+                });
+                self.instantiation_ids
+                    .entry(function)
+                    .or_default()
+                    .insert(name.to_string(), id);
+                id
+            }
+            IndexKey::Line {
+                source,
+                line_no,
+                function,
+            } => {
+                let id = self.lines.len() as LineId;
+                self.lines.push(LineInfo {
+                    source,
+                    line_no,
+                    function,
+                });
+                self.line_ids.insert((source, line_no), id);
+                id
+            }
+            IndexKey::Condition {
+                source,
+                line_no,
+                decision,
+                condition,
+                function,
+            } => {
+                let id = self.conditions.len() as ConditionId;
+                self.conditions.push(ConditionInfo {
+                    source,
+                    line_no,
+                    decision,
+                    condition,
+                    function,
+                });
+                self.condition_ids
+                    .insert((source, line_no, decision, condition), id);
+                id
+            }
+        }
+    }
+}
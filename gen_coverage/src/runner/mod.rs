@@ -1,29 +1,125 @@
-mod gcov;
+mod affinity;
+mod disk;
+pub mod gcov;
+pub mod index;
+#[cfg(target_arch = "x86_64")]
+mod ptrace;
+/// The ptrace backend reads the registers of x86_64, the run config rejects it elsewhere
+#[cfg(not(target_arch = "x86_64"))]
+mod ptrace {
+    use super::gcov::GcovRes;
+    use super::symbols::TracedObject;
+    use crate::config::TimeoutPolicy;
+    use std::io;
+    use std::process::{Command, Output};
+    use std::sync::Arc;
+
+    pub(super) enum FunctionTracer {}
+
+    impl FunctionTracer {
+        pub fn new(_objects: Arc<Vec<TracedObject>>) -> Self {
+            unreachable!("The ptrace backend is only supported on x86_64")
+        }
+
+        pub fn result(&self) -> GcovRes {
+            match *self {}
+        }
+
+        pub fn run(
+            &mut self,
+            _cmd: &mut Command,
+            _timeout: Option<&TimeoutPolicy>,
+        ) -> io::Result<(Output, bool)> {
+            match *self {}
+        }
+    }
+}
 mod run;
+mod screening;
+pub mod stability;
+mod symbols;
 mod worker;
 pub use gcov::GcovBitvec;
 pub use gcov::GcovRes;
+pub use index::CoverageIndex;
 use log::{error, warn};
 
-use crate::types::{Benchmark, BenchmarkRun};
-use crate::ARGS;
+use crate::config::{CoverageBackend, RunConfig};
+use crate::error::{Error, RunnerResult};
+use crate::types::{Benchmark, BenchmarkRun, Configuration, Job};
 
 use crossbeam::channel;
+use itertools::iproduct;
 use std::collections::HashSet;
-use std::process::exit;
+use std::sync::Arc;
+
+/// Called by the DB writer thread for every finished job, before its result is stored
+pub type RunCallback = Box<dyn FnMut(&Job, &BenchmarkRun) + Send>;
+/// Called by the DB writer thread for every job that produced a gcov result, its ids refer
+/// to the given index
+pub type GcovCallback = Box<dyn FnMut(&Job, &CoverageIndex, &GcovRes) + Send>;
+
+/// Hooks to observe the results of a run, in addition to them being written to the DB
+#[derive(Default)]
+pub struct Callbacks {
+    pub on_run: Option<RunCallback>,
+    pub on_gcov: Option<GcovCallback>,
+}
 
 enum RunnerQueueMessage {
-    Start(Benchmark),
+    Start(Box<Job>),
     Stop,
 }
 
-type ProcessingQueueMessage = (u64, BenchmarkRun, Option<GcovRes>);
+/// A finished job with all its run attempts, the last attempt being the final result
+type ProcessingQueueMessage = (Job, Vec<BenchmarkRun>, Option<GcovRes>);
 
 enum ProcessingStatusMessage {
     DbInitSuccess,
-    DbInitError,
+    DbInitError(Error),
     BenchesDone(u64),
-    Benchmarks(Vec<Benchmark>),
+    /// The DB writer stopped because of an infrastructure failure, after flushing what was
+    /// collected so far
+    Aborted(Error),
+    Benchmarks(Vec<Benchmark>, Vec<Configuration>),
+}
+
+/// All jobs of a run: every benchmark under every configuration it passed the pre-screening
+/// under, followed by the repeated runs of the benchmarks sampled by the nondeterminism
+/// detection
+pub fn jobs(
+    config: &RunConfig,
+    benchmarks: &[Benchmark],
+    configurations: &[Configuration],
+) -> Vec<Job> {
+    let mut jobs: Vec<Job> = iproduct!(benchmarks, configurations)
+        .filter(|(benchmark, configuration)| !benchmark.screened_out.contains(&configuration.id))
+        .map(|(benchmark, configuration)| Job {
+            benchmark: benchmark.clone(),
+            configuration: configuration.clone(),
+            repetition: 0,
+            worker_prefix: None,
+        })
+        .collect();
+
+    if let Some(stability) = config.coverage().and_then(|c| c.stability.as_ref()) {
+        let sampled = stability::sample_benchmarks(benchmarks, stability);
+        for repetition in 1..stability.runs {
+            for (benchmark, configuration) in iproduct!(benchmarks, configurations) {
+                if sampled.contains(&benchmark.id)
+                    && !benchmark.screened_out.contains(&configuration.id)
+                {
+                    jobs.push(Job {
+                        benchmark: benchmark.clone(),
+                        configuration: configuration.clone(),
+                        repetition,
+                        worker_prefix: None,
+                    });
+                }
+            }
+        }
+    }
+    jobs
 }
 
 pub struct Runner {
@@ -33,20 +129,52 @@ pub struct Runner {
     processing_status_queue: channel::Receiver<ProcessingStatusMessage>,
     processing_worker: worker::Worker,
 
-    enqueued: Box<HashSet<u64>>,
+    enqueued: HashSet<(u64, u64, u32)>,
 }
 
 impl Runner {
-    pub fn new() -> Self {
-        let no_workers = ARGS.job_size;
+    pub fn new(config: Arc<RunConfig>) -> RunnerResult<Self> {
+        Self::with_callbacks(config, Callbacks::default())
+    }
+
+    /// Fails if the workers can't be set up, before any of them is started
+    pub fn with_callbacks(config: Arc<RunConfig>, callbacks: Callbacks) -> RunnerResult<Self> {
+        let no_workers = config.job_size;
 
         assert!(no_workers > 0);
 
+        let cpus = affinity::pinned_cpus(&config)?;
+        let index = Arc::new(CoverageIndex::default());
+        let disk = Arc::new(disk::DiskGuard::new(&config));
+        // The functions of uninstrumented binaries are known up front
+        let traced_objects = config
+            .coverage()
+            .filter(|c| c.backend == CoverageBackend::Ptrace)
+            .map(|c| {
+                c.traced_objects
+                    .iter()
+                    .map(|path| symbols::TracedObject::load(path, &index))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Arc::new)
+                    .map_err(|e| {
+                        Error::Config(format!(
+                            "Could not read the functions of the traced objects: {}",
+                            e
+                        ))
+                    })
+            })
+            .transpose()?;
         let (p_status_send, p_status_receiver) = channel::unbounded();
-        let (p_sender, p_receiver) = channel::bounded(10 * ARGS.job_size);
+        let (p_sender, p_receiver) = channel::bounded(10 * no_workers);
         let processing_queue = p_sender;
-        let processing_worker =
-            worker::Worker::new_processing(p_status_send.clone(), p_receiver.clone());
+        let processing_worker = worker::Worker::new_processing(
+            config.clone(),
+            index.clone(),
+            disk.clone(),
+            callbacks,
+            p_status_send.clone(),
+            p_receiver.clone(),
+        );
 
         let (r_sender, r_receiver) = channel::unbounded();
         let runner_receiver = r_receiver;
@@ -56,55 +184,87 @@ impl Runner {
         for id in 0..no_workers {
             runner_workers.push(worker::Worker::new_cmd(
                 id,
+                config.clone(),
+                index.clone(),
+                disk.clone(),
+                worker::WorkerSetup {
+                    cpu: cpus.as_ref().map(|cpus| cpus[id]),
+                    tracer: traced_objects.clone().map(ptrace::FunctionTracer::new),
+                },
                 runner_receiver.clone(),
                 processing_queue.clone(),
             ));
         }
 
-        Self {
+        Ok(Self {
             runner_workers,
             runner_queue,
 
             processing_worker,
             processing_status_queue: p_status_receiver,
 
-            enqueued: Box::from(HashSet::new()),
-        }
+            enqueued: HashSet::new(),
+        })
     }
 
-    pub fn wait_on_db_ready(&mut self) {
-        match self.processing_status_queue.recv().unwrap() {
-            ProcessingStatusMessage::DbInitSuccess => {}
-            ProcessingStatusMessage::DbInitError => {
+    /// Waits until the DB writer created or opened the report DB
+    pub fn wait_on_db_ready(&mut self) -> RunnerResult<()> {
+        match self
+            .processing_status_queue
+            .recv()
+            .map_err(|_| Error::Disconnected)?
+        {
+            ProcessingStatusMessage::DbInitSuccess => Ok(()),
+            ProcessingStatusMessage::DbInitError(e) => {
                 warn!("Could not init DB in DB Writer. Exiting early...");
-                exit(1);
+                Err(e)
             }
             _ => unreachable!("This message was not expected!"),
         }
     }
 
-    pub fn wait_for_all_benchmarks(&mut self) -> Vec<Benchmark> {
-        match self.processing_status_queue.recv().unwrap() {
-            ProcessingStatusMessage::Benchmarks(res) => res,
+    /// All benchmarks and configurations of the run, or the error that aborted the run
+    /// before they were known (e.g. a failed pre-screening)
+    pub fn wait_for_all_benchmarks(
+        &mut self,
+    ) -> RunnerResult<(Vec<Benchmark>, Vec<Configuration>)> {
+        match self
+            .processing_status_queue
+            .recv()
+            .map_err(|_| Error::Disconnected)?
+        {
+            ProcessingStatusMessage::Benchmarks(benchmarks, configurations) => {
+                Ok((benchmarks, configurations))
+            }
+            ProcessingStatusMessage::Aborted(e) => Err(e),
             _ => unreachable!("This message was not expected!"),
         }
     }
 
-    pub fn wait_for_next_bench_done(&mut self) -> u64 {
-        match self.processing_status_queue.recv().unwrap() {
-            ProcessingStatusMessage::BenchesDone(res) => res,
+    /// Number of jobs finished since the last call, or the error that aborted the run
+    pub fn wait_for_next_bench_done(&mut self) -> RunnerResult<u64> {
+        match self
+            .processing_status_queue
+            .recv()
+            .map_err(|_| Error::Disconnected)?
+        {
+            ProcessingStatusMessage::BenchesDone(res) => Ok(res),
+            ProcessingStatusMessage::Aborted(e) => Err(e),
             _ => unreachable!("This message was not expected!"),
         }
     }
 
-    pub fn enqueue(&mut self, benchmark: Benchmark) {
+    pub fn enqueue(&mut self, job: Job) -> RunnerResult<()> {
         // Safety guard
-        if !self.enqueued.contains(&benchmark.id) {
-            self.enqueued.insert(benchmark.id);
+        if self
+            .enqueued
+            .insert((job.benchmark.id, job.configuration.id, job.repetition))
+        {
             self.runner_queue
-                .send(RunnerQueueMessage::Start(benchmark))
-                .unwrap();
+                .send(RunnerQueueMessage::Start(Box::new(job)))
+                .map_err(|_| Error::Disconnected)?;
         }
+        Ok(())
     }
 
     pub fn enqueue_worker_stop(&mut self) {
@@ -0,0 +1,384 @@
+//! Function coverage of uninstrumented binaries. Every function entry of the traced objects
+//! gets a one-shot int3 breakpoint, which is removed again once it was hit, so a run only
+//! stops once per function it enters. x86-64 Linux only.
+
+use super::gcov::{hit, GcovRes};
+use super::index::{FunctionId, InstantiationId};
+use super::run::read_to_end;
+use super::symbols::TracedObject;
+use crate::config::TimeoutPolicy;
+
+use crossbeam::channel::{self, RecvTimeoutError};
+use log::{debug, warn};
+use object::{Object, ObjectSymbol};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const INT3: u8 = 0xCC;
+const WORD: u64 = std::mem::size_of::<libc::c_long>() as u64;
+/// Auxiliary vector entry holding the load address of the dynamic loader
+const AT_BASE: u64 = 7;
+
+struct Breakpoint {
+    original: u8,
+    /// Functions entered at this address, aliased symbols share their entry
+    targets: Vec<(FunctionId, InstantiationId)>,
+    hit: bool,
+}
+
+/// Traces the runs of a single worker
+pub(super) struct FunctionTracer {
+    objects: Arc<Vec<TracedObject>>,
+    /// Breakpoints of the current run by their runtime address
+    breakpoints: HashMap<u64, Breakpoint>,
+    /// Objects not yet mapped into the process of the current run
+    pending: Vec<usize>,
+    /// `_dl_debug_state` of the dynamic loader and the byte the breakpoint replaced there. The
+    /// loader calls it whenever it mapped objects, so shared libraries get their breakpoints
+    /// before any of their code runs.
+    loader_hook: Option<(u64, u8)>,
+}
+
+impl FunctionTracer {
+    pub fn new(objects: Arc<Vec<TracedObject>>) -> Self {
+        FunctionTracer {
+            objects,
+            breakpoints: HashMap::new(),
+            pending: vec![],
+            loader_hook: None,
+        }
+    }
+
+    /// Functions entered by the last run
+    pub fn result(&self) -> GcovRes {
+        let mut result = GcovRes::default();
+        for breakpoint in self.breakpoints.values().filter(|b| b.hit) {
+            for (function, instantiation) in &breakpoint.targets {
+                hit(&mut result.functions, *function);
+                hit(&mut result.instantiations, *instantiation);
+            }
+        }
+        result
+    }
+
+    /// Runs the command under ptrace, like `Command::output`. Once the timeout is exceeded
+    /// the run is stopped like an untraced one, which is returned as well.
+    pub fn run(
+        &mut self,
+        cmd: &mut Command,
+        timeout: Option<&TimeoutPolicy>,
+    ) -> io::Result<(Output, bool)> {
+        self.breakpoints.clear();
+        self.pending = (0..self.objects.len()).collect();
+        self.loader_hook = None;
+
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        // Its own process group, so only its tasks are waited for
+        let mut child = cmd
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let pid = child.id() as libc::pid_t;
+        let stdout = read_to_end(child.stdout.take());
+        let stderr = read_to_end(child.stderr.take());
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (done, finished) = channel::bounded::<()>(1);
+        let watchdog = timeout.cloned().map(|timeout| {
+            let timed_out = timed_out.clone();
+            thread::spawn(move || {
+                let limit = Duration::from_millis(timeout.limit_ms);
+                if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(limit) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    unsafe { libc::kill(pid, timeout.signal.number()) };
+                    let grace = Duration::from_millis(timeout.grace_ms);
+                    if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(grace) {
+                        unsafe { libc::kill(pid, libc::SIGKILL) };
+                    }
+                }
+            })
+        });
+
+        let status = self.trace(pid).inspect_err(|_| {
+            // Don't leave a stopped process behind
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+            while let Ok((task, status)) = wait(-pid) {
+                if task == pid && !libc::WIFSTOPPED(status) {
+                    break;
+                }
+            }
+        });
+        let _ = done.send(());
+        if let Some(watchdog) = watchdog {
+            let _ = watchdog.join();
+        }
+        let output = Output {
+            status: ExitStatus::from_raw(status?),
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        };
+        Ok((output, timed_out.load(Ordering::SeqCst)))
+    }
+
+    /// Handles the stops of all tasks of the process until it exits, returns its wait status
+    fn trace(&mut self, pid: libc::pid_t) -> io::Result<libc::c_int> {
+        // Stopped right after the exec, nothing of the binary ran yet
+        let (_, status) = wait(pid)?;
+        if !libc::WIFSTOPPED(status) {
+            return Ok(status);
+        }
+        // Threads and child processes are traced as well, an int3 would kill them otherwise
+        let options = libc::PTRACE_O_TRACECLONE
+            | libc::PTRACE_O_TRACEFORK
+            | libc::PTRACE_O_TRACEVFORK
+            | libc::PTRACE_O_TRACEEXEC
+            | libc::PTRACE_O_EXITKILL;
+        check(unsafe { libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, options) })?;
+        self.insert_mapped(pid)?;
+        if !self.pending.is_empty() {
+            self.insert_loader_hook(pid)?;
+        }
+        resume(pid, 0);
+
+        loop {
+            let (task, status) = wait(-pid)?;
+            if !libc::WIFSTOPPED(status) {
+                if task == pid {
+                    if !self.pending.is_empty() {
+                        debug!("{} traced objects were never mapped", self.pending.len());
+                    }
+                    return Ok(status);
+                }
+                continue;
+            }
+            let signal = libc::WSTOPSIG(status);
+            let is_event = status >> 16 != 0;
+            let forward = match signal {
+                // Events, and the stop of a new task right after it was attached
+                libc::SIGTRAP if is_event => 0,
+                libc::SIGTRAP => {
+                    if self.on_trap(task)? {
+                        0
+                    } else {
+                        signal
+                    }
+                }
+                libc::SIGSTOP if !is_event => 0,
+                _ => signal,
+            };
+            resume(task, forward);
+        }
+    }
+
+    /// Handles an int3 of the task, returns false if it was none of the breakpoints
+    fn on_trap(&mut self, task: libc::pid_t) -> io::Result<bool> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::ptrace(libc::PTRACE_GETREGS, task, 0, &mut regs) })?;
+        let address = regs.rip - 1;
+
+        if let Some((hook, original)) = self.loader_hook.filter(|(hook, _)| *hook == address) {
+            // Step over the hook before the objects it announced get their breakpoints
+            write_bytes(task, &[(hook, original)])?;
+            regs.rip = address;
+            check(unsafe { libc::ptrace(libc::PTRACE_SETREGS, task, 0, &regs) })?;
+            check(unsafe { libc::ptrace(libc::PTRACE_SINGLESTEP, task, 0, 0) })?;
+            wait(task)?;
+            self.loader_hook = None;
+            self.insert_mapped(task)?;
+            if !self.pending.is_empty() {
+                self.loader_hook = Some((hook, write_bytes(task, &[(hook, INT3)])?[0]));
+            }
+            return Ok(true);
+        }
+
+        let breakpoint = match self.breakpoints.get_mut(&address) {
+            Some(breakpoint) => breakpoint,
+            None => return Ok(false),
+        };
+        // Other tasks may hit it before it is removed, and child processes have a copy of
+        // their own, so it is removed from the memory of every task stopping at it
+        breakpoint.hit = true;
+        write_bytes(task, &[(address, breakpoint.original)])?;
+        regs.rip = address;
+        check(unsafe { libc::ptrace(libc::PTRACE_SETREGS, task, 0, &regs) })?;
+        Ok(true)
+    }
+
+    /// Places the breakpoints of all pending objects that are mapped by now
+    fn insert_mapped(&mut self, pid: libc::pid_t) -> io::Result<()> {
+        let mappings = mappings(pid)?;
+        let mut patches = vec![];
+        let objects = self.objects.clone();
+        self.pending.retain(|&i| {
+            let object = &objects[i];
+            let start = match mappings.get(object.path.as_path()) {
+                Some(start) => *start,
+                None => return true,
+            };
+            let bias = if object.position_independent {
+                start.wrapping_sub(object.base_address & !0xfff)
+            } else {
+                0
+            };
+            for function in &object.functions {
+                let address = function.address.wrapping_add(bias);
+                let breakpoint = self.breakpoints.entry(address).or_insert(Breakpoint {
+                    original: INT3,
+                    targets: vec![],
+                    hit: false,
+                });
+                if breakpoint.targets.is_empty() {
+                    patches.push((address, INT3));
+                }
+                breakpoint
+                    .targets
+                    .push((function.function, function.instantiation));
+            }
+            false
+        });
+
+        patches.sort_unstable();
+        let originals = write_bytes(pid, &patches)?;
+        for ((address, _), original) in patches.iter().zip(originals) {
+            self.breakpoints.get_mut(address).unwrap().original = original;
+        }
+        Ok(())
+    }
+
+    fn insert_loader_hook(&mut self, pid: libc::pid_t) -> io::Result<()> {
+        let base = match auxv(pid)?.get(&AT_BASE) {
+            Some(&base) if base != 0 => base,
+            // Statically linked, nothing is loaded later on
+            _ => {
+                warn!(
+                    "{} traced objects are not part of the binary",
+                    self.pending.len()
+                );
+                return Ok(());
+            }
+        };
+        let loader = mappings(pid)?
+            .into_iter()
+            .find(|(_, start)| *start == base)
+            .map(|(path, _)| path)
+            .ok_or_else(|| io::Error::other("Could not find the dynamic loader"))?;
+        let data = fs::read(&loader)?;
+        let file = object::File::parse(&*data).map_err(io::Error::other)?;
+        let hook = file
+            .dynamic_symbols()
+            .find(|s| s.name() == Ok("_dl_debug_state"))
+            .map(|s| base + s.address())
+            .ok_or_else(|| io::Error::other("Dynamic loader lacks _dl_debug_state"))?;
+        self.loader_hook = Some((hook, write_bytes(pid, &[(hook, INT3)])?[0]));
+        Ok(())
+    }
+}
+
+fn check(result: libc::c_long) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits for a stop or the exit of a task, `-pid` waits for any task of the process group
+fn wait(pid: libc::pid_t) -> io::Result<(libc::pid_t, libc::c_int)> {
+    let mut status = 0;
+    loop {
+        let task = unsafe { libc::waitpid(pid, &mut status, libc::__WALL) };
+        if task >= 0 {
+            return Ok((task, status));
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Continues a stopped task, it may have been killed in the meantime
+fn resume(task: libc::pid_t, signal: libc::c_int) {
+    unsafe { libc::ptrace(libc::PTRACE_CONT, task, 0, signal as libc::c_long) };
+}
+
+/// Writes single bytes into the memory of a task, `patches` sorted by address. Returns the
+/// bytes they replaced.
+fn write_bytes(task: libc::pid_t, patches: &[(u64, u8)]) -> io::Result<Vec<u8>> {
+    let mut originals = Vec::with_capacity(patches.len());
+    let mut i = 0;
+    while i < patches.len() {
+        // All patches within the same word are applied at once
+        let word_address = patches[i].0 & !(WORD - 1);
+        let word = unsafe {
+            *libc::__errno_location() = 0;
+            libc::ptrace(libc::PTRACE_PEEKDATA, task, word_address, 0)
+        };
+        if word == -1 && unsafe { *libc::__errno_location() } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut bytes = word.to_ne_bytes();
+        while i < patches.len() && patches[i].0 < word_address + WORD {
+            let offset = (patches[i].0 - word_address) as usize;
+            originals.push(bytes[offset]);
+            bytes[offset] = patches[i].1;
+            i += 1;
+        }
+        let word = libc::c_long::from_ne_bytes(bytes);
+        check(unsafe { libc::ptrace(libc::PTRACE_POKEDATA, task, word_address, word) })?;
+    }
+    Ok(originals)
+}
+
+/// Start address of every file mapped into the process, at the mapping of its first page
+fn mappings(pid: libc::pid_t) -> io::Result<HashMap<std::path::PathBuf, u64>> {
+    let maps = fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    let mut result = HashMap::new();
+    for line in maps.lines() {
+        let fields: Vec<&str> = line.splitn(6, ' ').collect();
+        let (range, offset, path) = match fields.as_slice() {
+            [range, _, offset, _, _, path] => (range, offset, path.trim()),
+            _ => continue,
+        };
+        if !path.starts_with('/') || u64::from_str_radix(offset, 16) != Ok(0) {
+            continue;
+        }
+        let start = range
+            .split('-')
+            .next()
+            .and_then(|s| u64::from_str_radix(s, 16).ok());
+        if let Some(start) = start {
+            result.entry(Path::new(path).to_path_buf()).or_insert(start);
+        }
+    }
+    Ok(result)
+}
+
+fn auxv(pid: libc::pid_t) -> io::Result<HashMap<u64, u64>> {
+    let data = fs::read(format!("/proc/{}/auxv", pid))?;
+    Ok(data
+        .chunks_exact(16)
+        .map(|entry| {
+            let key = u64::from_ne_bytes(entry[..8].try_into().unwrap());
+            let value = u64::from_ne_bytes(entry[8..].try_into().unwrap());
+            (key, value)
+        })
+        .collect())
+}
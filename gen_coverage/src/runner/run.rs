@@ -1,69 +1,215 @@
-use crate::args::{Commands, ARGS, EXEC_PLACEHOLDER};
-use crate::types::{Benchmark, BenchmarkRun};
+use super::gcov;
+use super::ptrace::FunctionTracer;
+use crate::config::{RunConfig, TimeoutPolicy};
+use crate::error::Error;
+use crate::types::{BenchmarkRun, Job};
 
-use log::{error, info};
+use log::{error, info, warn};
+use std::io::{self, Read};
 use std::os::unix::process::ExitStatusExt;
-use std::path::PathBuf;
-use std::process::Command;
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-pub(super) fn process(benchmark: &Benchmark) -> Option<BenchmarkRun> {
+/// How often a run with a timeout is checked for having exited
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Runs the job, repeating it according to the retry policy. Returns all attempts, the last
+/// one is the final result. The executable is passed `input`, which only differs from the
+/// benchmark path for compressed benchmarks. Runs are traced by `tracer` for the ptrace
+/// backend.
+pub(super) fn process(
+    job: &Job,
+    config: &RunConfig,
+    input: &Path,
+    mut tracer: Option<&mut FunctionTracer>,
+) -> Vec<BenchmarkRun> {
+    let mut attempts: Vec<BenchmarkRun> = vec![];
+    loop {
+        let attempt = attempts.len() as u32 + 1;
+        let mut run = process_attempt(job, config, input, attempt, tracer.as_deref_mut());
+        // A run stopped at the timeout would most likely hit it again
+        let retry = !run.timed_out
+            && config
+                .retry
+                .should_retry(attempt, run.exit_code, run.signal);
+        run.flaky = attempts
+            .iter()
+            .any(|a| a.exit_code != run.exit_code || a.signal != run.signal);
+        attempts.push(run);
+        if !retry {
+            break;
+        }
+
+        warn!(
+            "Retrying benchmark run (attempt {}/{}, File: {:?}, Configuration: {})",
+            attempt + 1,
+            config.retry.retries + 1,
+            &job.benchmark.path,
+            &job.configuration.name
+        );
+        // Make sure no partial coverage data of the failed attempt remains
+        if config.coverage().is_some_and(|c| c.individual_prefixes) {
+            gcov::reset_prefix(job);
+        }
+    }
+    attempts
+}
+
+fn process_attempt(
+    job: &Job,
+    config: &RunConfig,
+    input: &Path,
+    attempt: u32,
+    tracer: Option<&mut FunctionTracer>,
+) -> BenchmarkRun {
+    let benchmark = &job.benchmark;
+    let exec_template = config.exec_template();
     // Assumes that the full path is always passed
-    let exec = PathBuf::from(&EXEC_PLACEHOLDER[0]);
+    let exec = PathBuf::from(&exec_template[0]);
     let cmd = &mut Command::new(&exec);
-    match ARGS.command {
-        Commands::Coverage {
-            individual_prefixes,
-            ..
-        } if individual_prefixes => {
-            if let Some(prefix) = &benchmark.prefix {
-                cmd.env("GCOV_PREFIX", prefix.display().to_string());
-            }
+    if config.coverage().is_some_and(|c| c.individual_prefixes) {
+        if let Some(prefix) = job.prefix() {
+            cmd.env("GCOV_PREFIX", prefix.display().to_string());
         }
-        _ => {}
     }
-    // Replace {} in our template args with the file
-    let args = EXEC_PLACEHOLDER[1..].iter().map(|c| {
-        if c == "{}" {
-            benchmark.path.display().to_string()
-        } else {
-            c.clone()
-        }
-    });
-    cmd.args(args.clone());
+    // Configuration specific args directly follow the executable, afterwards replace {}
+    // in our template args with the file
+    let args: Vec<String> = job
+        .configuration
+        .args
+        .iter()
+        .cloned()
+        .chain(exec_template[1..].iter().map(|c| {
+            if c == "{}" {
+                input.display().to_string()
+            } else {
+                c.clone()
+            }
+        }))
+        .collect();
+    cmd.args(&args);
 
     let start = Instant::now();
-    let output = cmd
-        .output()
-        .expect("Could not capture output of execution...");
+    let output = match (tracer, &config.timeout) {
+        (Some(tracer), timeout) => tracer.run(cmd, timeout.as_ref()),
+        (None, None) => cmd.output().map(|output| (output, false)),
+        (None, Some(timeout)) => output_with_timeout(cmd, timeout),
+    };
+    let (output, timed_out) = match output {
+        Ok(output) => output,
+        Err(source) => {
+            let e = Error::Spawn {
+                benchmark: benchmark.path.clone(),
+                source,
+            };
+            error!("{} (Configuration: {})", e, &job.configuration.name);
+            return BenchmarkRun::failed(job, attempt, &e);
+        }
+    };
     let duration = start.elapsed();
 
-    let stderr = String::from_utf8(output.stderr).unwrap();
-    let exit_code = output
-        .status
-        .code()
-        .unwrap_or(output.status.signal().unwrap_or(100000));
-    if !output.status.success() {
+    // Solvers may print arbitrary bytes, keep what is decodable
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let signal = output.status.signal();
+    let exit_code = output.status.code().unwrap_or(signal.unwrap_or(100000));
+    if timed_out {
+        warn!(
+            "Benchmark run timed out after {}ms ({:?})! (File: {:?}, Configuration: {})",
+            duration.as_millis(),
+            output.status,
+            &benchmark.path,
+            &job.configuration.name
+        );
+    } else if !output.status.success() {
         error!(
-            "Execution failed with error ({:?})!\n Benchmark File: {:?} \n ERROR: {:?}",
-            output.status, &benchmark.path, &stderr
+            "Execution failed with error ({:?})!\n Benchmark File: {:?} (Configuration: {}) \n ERROR: {:?}",
+            output.status, &benchmark.path, &job.configuration.name, &stderr
         );
         error!("Args: {:?}", args);
     } else {
         info!(
-            "Benchmark run succeded! [{}] (File: {:?})",
-            output.status, &benchmark.path
+            "Benchmark run succeded! [{}] (File: {:?}, Configuration: {})",
+            output.status, &benchmark.path, &job.configuration.name
         );
     }
 
-    return Some(BenchmarkRun {
+    BenchmarkRun {
         bench_id: benchmark.id,
+        config_id: job.configuration.id,
         exit_code,
-        time_ms: duration
-            .as_millis()
-            .try_into()
-            .expect("Duration too long for 64 bits"),
-        stdout: Some(String::from_utf8(output.stdout).expect("Error decoding execution stdout")),
+        signal,
+        time_ms: duration.as_millis().try_into().unwrap_or(u64::MAX),
+        stdout: Some(String::from_utf8_lossy(&output.stdout).into_owned()),
         stderr: Some(stderr),
-    });
+        attempt,
+        flaky: false,
+        error: None,
+        timed_out,
+        failed_coverage: false,
+        cpu: None,
+    }
+}
+
+/// Like `Command::output`, but stops the run once it exceeds the timeout. Returns whether
+/// that happened.
+fn output_with_timeout(cmd: &mut Command, timeout: &TimeoutPolicy) -> io::Result<(Output, bool)> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+
+    let mut timed_out = false;
+    let status = match wait_for(&mut child, timeout.limit_ms)? {
+        Some(status) => status,
+        None => {
+            timed_out = true;
+            // Only this thread reaps the child, so its pid can't have been reused yet.
+            // Give the run the chance to exit cleanly, so coverage data gets written
+            unsafe { libc::kill(child.id() as libc::pid_t, timeout.signal.number()) };
+            match wait_for(&mut child, timeout.grace_ms)? {
+                Some(status) => status,
+                None => {
+                    child.kill()?;
+                    child.wait()?
+                }
+            }
+        }
+    };
+    let output = Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    };
+    Ok((output, timed_out))
+}
+
+/// Polls the child until it exits or `ms` have passed, returns `None` on the timeout
+fn wait_for(child: &mut Child, ms: u64) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + Duration::from_millis(ms);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(WAIT_POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// Reads the pipe in the background, a run filling it would block otherwise
+pub(super) fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
 }
@@ -0,0 +1,102 @@
+//! Pre-screening of all benchmarks with a faster build of the executable (e.g. a production
+//! build), so the slow coverage runs are only spent on benchmarks that finish in time
+
+use super::affinity;
+use super::run;
+use crate::archive::Unpacker;
+use crate::config::RunConfig;
+use crate::error::{Error, RunnerResult};
+use crate::trace;
+use crate::types::{Benchmark, BenchmarkRun, Configuration, Job};
+
+use crossbeam::channel;
+use itertools::iproduct;
+use log::{error, info};
+use std::thread;
+
+/// Runs every benchmark under every configuration with the screening executable, using as
+/// many threads as the run has workers. Returns the final attempt of every run.
+pub(super) fn screen(
+    config: &RunConfig,
+    screening: &RunConfig,
+    benchmarks: &[Benchmark],
+    configurations: &[Configuration],
+) -> RunnerResult<Vec<BenchmarkRun>> {
+    let (job_sender, job_receiver) = channel::unbounded();
+    for (benchmark, configuration) in iproduct!(benchmarks, configurations) {
+        job_sender
+            .send(Job {
+                benchmark: benchmark.clone(),
+                configuration: configuration.clone(),
+                repetition: 0,
+                worker_prefix: None,
+            })
+            .unwrap();
+    }
+    drop(job_sender);
+    let total = benchmarks.len() * configurations.len();
+    info!("[Screening] Pre-screening {} benchmark runs", total);
+
+    // Same cores as the workers of the coverage run, which are idle until it starts
+    let cpus = affinity::pinned_cpus(config)?;
+    let (result_sender, result_receiver) = channel::unbounded();
+    thread::scope(|scope| {
+        for id in 0..config.job_size {
+            let jobs = job_receiver.clone();
+            let results = result_sender.clone();
+            let cpu = cpus.as_ref().map(|cpus| cpus[id]);
+            scope.spawn(move || {
+                trace::thread_name(&format!("Screening {}", id));
+                if let Some(Err(e)) = cpu.map(affinity::pin) {
+                    error!("[Screening {}] Could not pin to CPU: {}", id, e);
+                }
+                let mut unpacker =
+                    Unpacker::new(config.scratch_dir().join(format!("screening-{}", id)));
+                for job in jobs {
+                    let _span = trace::span("screening run").arg("bench_id", job.benchmark.id);
+                    let run = match unpacker.materialize(&job.benchmark) {
+                        Ok(input) => {
+                            let mut attempts = run::process(&job, screening, &input, None);
+                            unpacker.release(&job.benchmark, &input);
+                            attempts.pop().unwrap()
+                        }
+                        Err(source) => {
+                            let e = Error::Unpack {
+                                benchmark: job.benchmark.path.clone(),
+                                source,
+                            };
+                            error!("[Screening {}] {}", id, e);
+                            BenchmarkRun::failed(&job, 1, &e)
+                        }
+                    };
+                    if results.send(run).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut runs = Vec::with_capacity(total);
+        for run in result_receiver {
+            runs.push(run);
+            if runs.len() % 100 == 0 {
+                info!(
+                    "[Screening] Screened {}/{} benchmark runs",
+                    runs.len(),
+                    total
+                );
+            }
+        }
+        let passed = runs
+            .iter()
+            .filter(|r| r.exit_code == 0 && !r.timed_out)
+            .count();
+        info!(
+            "[Screening] {} of {} benchmark runs passed and will be measured",
+            passed,
+            runs.len()
+        );
+        Ok(runs)
+    })
+}
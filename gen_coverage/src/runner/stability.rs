@@ -0,0 +1,117 @@
+use super::gcov::HitVec;
+use super::index::FunctionId;
+use super::GcovRes;
+use crate::config::StabilityConfig;
+use crate::types::{Benchmark, Job};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::collections::{HashMap, HashSet};
+
+/// Ids of the benchmarks that are re-run to detect nondeterministic coverage, the sample is
+/// fixed by the seed so a run can be reproduced
+pub fn sample_benchmarks(benchmarks: &[Benchmark], stability: &StabilityConfig) -> HashSet<u64> {
+    let mut rng = StdRng::seed_from_u64(stability.seed);
+    benchmarks
+        .choose_multiple(&mut rng, stability.samples)
+        .map(|b| b.id)
+        .collect()
+}
+
+/// A function that was used in some, but not all, runs of the same benchmark
+#[derive(Debug, Clone)]
+pub struct NondeterministicFunction {
+    pub function: FunctionId,
+    pub bench_id: u64,
+    pub config_id: u64,
+    pub runs: u32,
+    pub used_runs: u32,
+}
+
+/// Share of the sampled benchmarks using a function that use it in every run
+#[derive(Debug, Clone)]
+pub struct FunctionStability {
+    pub function: FunctionId,
+    pub config_id: u64,
+    /// Sampled benchmarks that used the function in at least one of their runs
+    pub sampled_benchmarks: u32,
+    /// Sampled benchmarks that used the function in every one of their runs
+    pub stable_benchmarks: u32,
+}
+
+impl FunctionStability {
+    pub fn score(&self) -> f64 {
+        self.stable_benchmarks as f64 / self.sampled_benchmarks as f64
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StabilityResults {
+    pub nondeterministic: Vec<NondeterministicFunction>,
+    pub stability: Vec<FunctionStability>,
+}
+
+/// Collects the used functions of every run of the sampled benchmarks
+#[derive(Default)]
+pub(super) struct StabilityTracker {
+    runs: HashMap<(u64, u64), Vec<HitVec>>,
+}
+
+impl StabilityTracker {
+    /// Records the used functions of a successful run of a sampled benchmark
+    pub(super) fn add(&mut self, job: &Job, gcov_result: &GcovRes) {
+        self.runs
+            .entry((job.configuration.id, job.benchmark.id))
+            .or_default()
+            .push(gcov_result.functions.clone());
+    }
+
+    pub(super) fn results(self) -> StabilityResults {
+        let mut nondeterministic = vec![];
+        let mut stability: HashMap<(FunctionId, u64), FunctionStability> = HashMap::new();
+
+        for ((config_id, bench_id), runs) in self.runs {
+            // A single successful run can't disagree with anything
+            if runs.len() < 2 {
+                continue;
+            }
+            let mut used_runs: HashMap<FunctionId, u32> = HashMap::new();
+            for run in runs.iter() {
+                for function in run.iter_ones() {
+                    *used_runs.entry(function as FunctionId).or_insert(0) += 1;
+                }
+            }
+
+            for (function, used) in used_runs {
+                let stable = used as usize == runs.len();
+                if !stable {
+                    nondeterministic.push(NondeterministicFunction {
+                        function,
+                        bench_id,
+                        config_id,
+                        runs: runs.len() as u32,
+                        used_runs: used,
+                    });
+                }
+
+                let entry =
+                    stability
+                        .entry((function, config_id))
+                        .or_insert_with(|| FunctionStability {
+                            function,
+                            config_id,
+                            sampled_benchmarks: 0,
+                            stable_benchmarks: 0,
+                        });
+                entry.sampled_benchmarks += 1;
+                entry.stable_benchmarks += stable as u32;
+            }
+        }
+
+        StabilityResults {
+            nondeterministic,
+            stability: stability.into_values().collect(),
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Functions of uninstrumented binaries, read from their ELF symbol table and mapped to their
+//! source via the DWARF line info. Every function is interned into the coverage index up
+//! front, so the ptrace backend only has to set bits for the ones it saw being entered.
+
+use super::index::{CoverageIndex, FunctionId, IndexKey, InstantiationId};
+use crate::types::ResultT;
+
+use addr2line::gimli;
+use addr2line::Loader;
+use log::{info, warn};
+use object::{Object, ObjectKind, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Entry of a function with a symbol of its own, inlined copies can't be observed
+#[derive(Debug, Clone)]
+pub(super) struct TracedFunction {
+    /// Address of the entry as linked, i.e. without the load bias
+    pub address: u64,
+    pub function: FunctionId,
+    pub instantiation: InstantiationId,
+}
+
+/// A binary or shared library whose functions are traced
+#[derive(Debug)]
+pub(super) struct TracedObject {
+    /// Canonical path, as it shows up in the mappings of a process
+    pub path: PathBuf,
+    /// Position independent objects are loaded at a bias that is only known at runtime
+    pub position_independent: bool,
+    /// Lowest address of a loaded segment, it is mapped at the start of the object
+    pub base_address: u64,
+    pub functions: Vec<TracedFunction>,
+}
+
+impl TracedObject {
+    /// Reads the functions of the object, ones without line info are skipped
+    pub fn load(path: &Path, index: &CoverageIndex) -> ResultT<Self> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("Could not find traced object {}: {}", path.display(), e))?;
+        let data = fs::read(&path)?;
+        let file = object::File::parse(&*data)?;
+        let lines = Loader::new(&path)
+            .map_err(|e| format!("Could not read DWARF of {}: {}", path.display(), e))?;
+
+        // Stripped binaries still have their dynamic symbols
+        let mut symbols: Vec<_> = file.symbols().collect();
+        if symbols.is_empty() {
+            symbols = file.dynamic_symbols().collect();
+        }
+
+        let mut seen = HashSet::new();
+        let mut functions = vec![];
+        let mut without_lines = 0;
+        let mut index = index.write();
+        for symbol in symbols {
+            if symbol.kind() != SymbolKind::Text
+                || !symbol.is_definition()
+                || symbol.size() == 0
+                || symbol.address() == 0
+            {
+                continue;
+            }
+            let name = match symbol.name() {
+                Ok(name) if seen.insert(name) => name,
+                _ => continue,
+            };
+            let start = symbol.address();
+            let location = match lines.find_location(start)? {
+                Some(location) => location,
+                None => {
+                    without_lines += 1;
+                    continue;
+                }
+            };
+            let (Some(source), Some(start_line)) = (location.file, location.line) else {
+                without_lines += 1;
+                continue;
+            };
+            // The body ends at the last line of the source file of the entry, other files
+            // are inlined code
+            let mut end_line = start_line;
+            for (_, _, range_location) in lines.find_location_range(start, start + symbol.size())? {
+                if range_location.file == Some(source) {
+                    end_line = end_line.max(range_location.line.unwrap_or(0));
+                }
+            }
+            let demangled_name = addr2line::demangle(name, gimli::DW_LANG_C_plus_plus)
+                .unwrap_or_else(|| name.to_string());
+
+            let source = index.intern(&IndexKey::Source(source));
+            let function = index.intern(&IndexKey::Function {
+                source,
+                start: (start_line, location.column.unwrap_or(0)),
+                end: (end_line, 0),
+                name: &demangled_name,
+            });
+            let instantiation = index.intern(&IndexKey::Instantiation {
+                function,
+                name,
+                demangled_name: &demangled_name,
+                blocks: 0,
+            });
+            functions.push(TracedFunction {
+                address: start,
+                function,
+                instantiation,
+            });
+        }
+
+        if without_lines > 0 {
+            warn!(
+                "{} functions of {} have no line info and are not traced",
+                without_lines,
+                path.display()
+            );
+        }
+        info!(
+            "Tracing {} functions of {}",
+            functions.len(),
+            path.display()
+        );
+        Ok(TracedObject {
+            position_independent: file.kind() == ObjectKind::Dynamic,
+            base_address: file.segments().map(|s| s.address()).min().unwrap_or(0),
+            path,
+            functions,
+        })
+    }
+}
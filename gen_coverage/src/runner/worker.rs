@@ -1,32 +1,45 @@
+use super::affinity;
+use super::disk::{self, DiskGuard};
 use super::gcov;
+use super::ptrace::FunctionTracer;
 use super::run;
+use super::screening;
+use super::Callbacks;
 use super::ProcessingQueueMessage;
 use super::ProcessingStatusMessage;
 use super::RunnerQueueMessage;
-use crate::args::Commands;
+use crate::archive::Unpacker;
+use crate::config::RunConfig;
 use crate::db::DbWriter;
-use crate::runner::gcov::merge_gcov;
+use crate::error::{Error, RunnerResult, ERROR_EXIT_CODE};
 use crate::runner::gcov::res_to_bitvec;
 use crate::runner::gcov::GcovBitvec;
-use crate::runner::gcov::MergeKind;
-use crate::runner::GcovRes;
-use crate::ARGS;
+use crate::runner::gcov::GcovCounts;
+use crate::runner::index::CoverageIndex;
+use crate::runner::stability::{self, StabilityTracker};
+use crate::trace;
+use crate::types::BenchmarkRun;
 
 use crossbeam::channel;
 use log::debug;
 use log::LevelFilter;
 use log::{error, info, warn};
-use std::borrow::BorrowMut;
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::create_dir_all;
 use std::fs::remove_dir_all;
-use std::mem;
-use std::path::Path;
-use std::process::exit;
+use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 
+/// Optional parts of a benchmark running worker, set up by the runner
+pub(super) struct WorkerSetup {
+    /// CPU the worker and its runs are pinned to
+    pub cpu: Option<usize>,
+    /// Traces the runs for the ptrace backend
+    pub tracer: Option<FunctionTracer>,
+}
+
 // Worker struct (represents a worker thread)
 pub(super) struct Worker {
     _id: usize,
@@ -36,22 +49,99 @@ pub(super) struct Worker {
 impl Worker {
     pub(super) fn new_cmd(
         id: usize,
+        config: Arc<RunConfig>,
+        index: Arc<CoverageIndex>,
+        disk: Arc<DiskGuard>,
+        setup: WorkerSetup,
         receiver: channel::Receiver<RunnerQueueMessage>,
         processing_queue: channel::Sender<ProcessingQueueMessage>,
     ) -> Worker {
         let thread = thread::spawn(move || {
+            trace::thread_name(&format!("Worker {}", id));
+            // Inherited by the benchmark runs, only recorded if pinning worked
+            let WorkerSetup { cpu, mut tracer } = setup;
+            let cpu = cpu.filter(|&cpu| match affinity::pin(cpu) {
+                Ok(_) => {
+                    info!("[Worker {}] Pinned to CPU {}", id, cpu);
+                    true
+                }
+                Err(e) => {
+                    error!("[Worker {}] Could not pin to CPU {}: {}", id, cpu, e);
+                    false
+                }
+            });
+            let mut unpacker = Unpacker::new(config.scratch_dir().join(id.to_string()));
+            // Reused by all runs of this worker, the gcno symlinks are only created once
+            let prefix = config.worker_prefix(id).map(|prefix| {
+                create_dir_all(&prefix)
+                    .and_then(|_| prefix.canonicalize())
+                    .unwrap_or_else(|e| {
+                        error!("[Worker {}] Could not create GCOV prefix: {}", id, e);
+                        prefix
+                    })
+            });
             loop {
-                let job = receiver.recv();
+                // Throttled workers must not hold back a job while waiting
+                let resumed = {
+                    let _span = trace::span("wait for disk space");
+                    disk.wait(id)
+                };
+                if !resumed {
+                    warn!("[Worker {}] Run stopped while waiting for disk space.", id);
+                    break;
+                }
+                let job = {
+                    let _span = trace::span("wait for job");
+                    receiver.recv()
+                };
                 match job {
-                    Ok(RunnerQueueMessage::Start(benchmark)) => {
-                        info!("[Worker {}] Received job (bench_id: {})", id, benchmark.id);
+                    Ok(RunnerQueueMessage::Start(job)) => {
+                        let mut job = *job;
+                        job.worker_prefix = prefix.clone();
+                        let benchmark = &job.benchmark;
+                        info!(
+                            "[Worker {}] Received job (bench_id: {}, config_id: {})",
+                            id, benchmark.id, job.configuration.id
+                        );
                         let start = if log::max_level() >= LevelFilter::Debug {
                             Some(Instant::now())
                         } else {
                             None
                         };
-                        let run_result = run::process(&benchmark).unwrap();
-                        let res_exit = run_result.exit_code;
+                        let materialized = {
+                            let _span = trace::span("unpack").arg("bench_id", benchmark.id);
+                            unpacker.materialize(benchmark)
+                        };
+                        let input = match materialized {
+                            Ok(input) => input,
+                            Err(source) => {
+                                let e = Error::Unpack {
+                                    benchmark: benchmark.path.clone(),
+                                    source,
+                                };
+                                error!("[Worker {}] {}", id, e);
+                                let mut run = BenchmarkRun::failed(&job, 1, &e);
+                                run.cpu = cpu;
+                                let attempts = vec![run];
+                                if processing_queue.send((job, attempts, None)).is_err() {
+                                    warn!("Worker could not send result to DB writer");
+                                    break;
+                                }
+                                continue;
+                            }
+                        };
+                        let mut attempts = {
+                            let _span = trace::span("benchmark execution")
+                                .arg("bench_id", benchmark.id)
+                                .arg("config_id", job.configuration.id);
+                            run::process(&job, &config, &input, tracer.as_mut())
+                        };
+                        for attempt in &mut attempts {
+                            attempt.cpu = cpu;
+                        }
+                        unpacker.release(benchmark, &input);
+                        let res_exit = attempts.last().unwrap().exit_code;
+                        let spawned = attempts.last().unwrap().error.is_none();
                         if log::max_level() >= LevelFilter::Debug {
                             debug!(
                                 "[Worker {}] Executed benchmark run in {}ms (bench_id: {})",
@@ -63,69 +153,93 @@ impl Worker {
 
                         let bench_id = benchmark.id;
                         // Coverage reports are not a thing if the process didn't terminate gracefully
-                        // or in case we are simply running a evaluation
-                        let is_evaluation = match ARGS.command {
-                            Commands::Evaluate { .. } => true,
-                            _ => false,
-                        };
-                        if res_exit == 0 && !is_evaluation {
-                            let start = if log::max_level() >= LevelFilter::Debug {
-                                Some(Instant::now())
-                            } else {
-                                None
-                            };
-                            let gcov_result = gcov::process(&benchmark);
-
-                            if log::max_level() >= LevelFilter::Debug {
-                                debug!(
-                                    "[Worker {}] Processed & ran gcov in {}ms (bench_id: {})",
-                                    id,
-                                    start.unwrap().elapsed().as_millis(),
-                                    benchmark.id
-                                );
-                            }
+                        // (unless explicitly requested) or in case we are simply running a evaluation
+                        let is_evaluation = !config.is_coverage();
+                        let collect_failed = config.coverage().is_some_and(|c| c.collect_failed);
+                        let gcov_result =
+                            if (res_exit == 0 || (collect_failed && spawned)) && !is_evaluation {
+                                let start = if log::max_level() >= LevelFilter::Debug {
+                                    Some(Instant::now())
+                                } else {
+                                    None
+                                };
+                                let _span = trace::span("gcov").arg("bench_id", bench_id);
+                                let gcov_result = match &tracer {
+                                    Some(tracer) => Ok(tracer.result()),
+                                    None => {
+                                        if res_exit == 0 && job.repetition == 0 {
+                                            gcov::accumulate_union(&job, &config, id)
+                                                .unwrap_or_else(|e| {
+                                                    error!(
+                                                        "[Worker {}] Block union incomplete: {}",
+                                                        id, e
+                                                    )
+                                                });
+                                        }
+                                        gcov::process(&job, &config, &index)
+                                    }
+                                };
 
-                            // Remove prefix directory
-                            match &benchmark.prefix {
-                                None => (),
-                                Some(v) => remove_dir_all(v).unwrap_or_else(|e| {
-                                    debug!("Could not delete prefix dir: {:?}", e)
-                                }),
-                            };
-
-                            match processing_queue.send((
-                                benchmark.id,
-                                run_result,
-                                Some(gcov_result),
-                            )) {
-                                Ok(_) => {
+                                if log::max_level() >= LevelFilter::Debug {
                                     debug!(
-                                        "[Worker {}] Queued GCOV result (bench_id: {})",
-                                        id, bench_id
+                                        "[Worker {}] Processed & ran gcov in {}ms (bench_id: {})",
+                                        id,
+                                        start.unwrap().elapsed().as_millis(),
+                                        benchmark.id
                                     );
                                 }
-                                Err(_) => {
-                                    warn!("Worker could not send gcov result to DB writer");
-                                    break;
-                                }
-                            }
-                        } else {
-                            match processing_queue.send((benchmark.id, run_result, None)) {
-                                Ok(_) => {
-                                    debug!(
-                                        "[Worker {}] Queued result (bench_id: {})",
-                                        id, bench_id
+
+                                match gcov_result {
+                                    Ok(gcov_result) if res_exit == 0 => Some(gcov_result),
+                                    // Crashed or killed runs often did not write any gcda data
+                                    Ok(gcov_result) if gcov_result.is_empty() => None,
+                                    Ok(gcov_result) => {
+                                        attempts.last_mut().unwrap().failed_coverage = true;
+                                        Some(gcov_result)
+                                    }
+                                    Err(e) if res_exit != 0 => {
+                                        debug!(
+                                        "[Worker {}] No coverage of failed run: {} (bench_id: {})",
+                                        id, e, bench_id
                                     );
+                                        None
+                                    }
+                                    Err(e) => {
+                                        // Without coverage data the run must not count as success
+                                        error!("[Worker {}] {}", id, e);
+                                        let run_result = attempts.last_mut().unwrap();
+                                        run_result.exit_code = ERROR_EXIT_CODE;
+                                        run_result.error = Some(e.to_string());
+                                        None
+                                    }
                                 }
-                                Err(_) => {
-                                    warn!("Worker could not send gcov result to DB writer");
-                                    break;
-                                }
+                            } else {
+                                debug!(
+                                    "[Worker {}] Benchmark Run Exit Code was {}... Skipping gcov",
+                                    id, res_exit
+                                );
+                                None
+                            };
+
+                        // Remove the gcda data of the run
+                        if !is_evaluation {
+                            gcov::reset_prefix(&job);
+                        }
+
+                        let sent = {
+                            // The queue to the DB writer is bounded, a long wait means it is
+                            // the bottleneck
+                            let _span = trace::span("wait for DB writer");
+                            processing_queue.send((job, attempts, gcov_result))
+                        };
+                        match sent {
+                            Ok(_) => {
+                                debug!("[Worker {}] Queued result (bench_id: {})", id, bench_id);
+                            }
+                            Err(_) => {
+                                warn!("Worker could not send result to DB writer");
+                                break;
                             }
-                            debug!(
-                                "[Worker {}] Benchmark Run Exit Code was {}... Skipping gcov",
-                                id, res_exit
-                            );
                         }
                     }
                     Ok(RunnerQueueMessage::Stop) => {
@@ -138,6 +252,10 @@ impl Worker {
                     }
                 }
             }
+            if let Some(prefix) = prefix {
+                remove_dir_all(prefix)
+                    .unwrap_or_else(|e| debug!("Could not delete prefix dir: {:?}", e));
+            }
             warn!("[Worker {}] Terminated.", id);
         });
 
@@ -148,173 +266,94 @@ impl Worker {
     }
 
     pub(super) fn new_processing(
+        config: Arc<RunConfig>,
+        index: Arc<CoverageIndex>,
+        disk: Arc<DiskGuard>,
+        callbacks: Callbacks,
         status_sender: channel::Sender<ProcessingStatusMessage>,
         receiver: channel::Receiver<ProcessingQueueMessage>,
     ) -> Worker {
         let thread = thread::spawn(move || {
+            trace::thread_name("DB Writer");
             // Db Setup
-            let out_dir = ARGS.result_db.parent().unwrap();
-            let out_dir = {
-                // Just to make sure we can canonicalize it at all
-                if out_dir.is_relative() {
-                    Path::new("./").join(out_dir).canonicalize().unwrap()
-                } else {
-                    out_dir.canonicalize().unwrap()
-                }
-            };
-            create_dir_all(out_dir).unwrap();
-
-            let is_coverage = match ARGS.command {
-                Commands::Coverage { .. } => true,
-                _ => false,
-            };
-
-            let db = DbWriter::new();
-            match db {
-                Ok(_) => status_sender
-                    .send(ProcessingStatusMessage::DbInitSuccess)
-                    .unwrap(),
-                Err(_) => {
+            let db = config
+                .result_db
+                .parent()
+                .map_or(Ok(()), |out_dir| {
+                    if out_dir.as_os_str().is_empty() {
+                        Ok(())
+                    } else {
+                        create_dir_all(out_dir)
+                    }
+                })
+                .map_err(Error::from)
+                .and_then(|_| DbWriter::new(config.clone()));
+            let db = match db {
+                Ok(db) => {
                     status_sender
-                        .send(ProcessingStatusMessage::DbInitError)
+                        .send(ProcessingStatusMessage::DbInitSuccess)
                         .unwrap();
+                    db
+                }
+                Err(e) => {
+                    error!("{}", e);
                     error!("Error during DB initialization... terminating DB writer early");
-                    exit(1);
+                    status_sender
+                        .send(ProcessingStatusMessage::DbInitError(e))
+                        .unwrap();
+                    return;
                 }
             };
-            let mut db = db.unwrap();
-            let bench_count: u64 = {
-                let benchmarks = db
-                    .get_all_benchmarks()
-                    .expect("Could not retrieve benchmarks");
-                let count = benchmarks.len();
-                status_sender
-                    .send(ProcessingStatusMessage::Benchmarks(benchmarks))
-                    .unwrap();
-                count as u64
-            };
-
-            // Bitvector storing the indicator matrix
-            let mut gcov_bitvec: GcovBitvec = HashMap::new();
 
-            // Batch process 100 results at once to decrease load on DB
-            let max_bench_aggregate: u64 = min(100, bench_count);
-            let mut result_buf: Option<GcovRes> = None;
-            let mut bench_counter: u64 = 0;
-            let mut rem_counter: u64 = bench_count;
-
-            loop {
-                if rem_counter == 0 {
-                    info!("[DB Writer] Processed all benchmarks, breaking out of message loop");
-                    break;
-                }
+            let mut writer = ResultWriter {
+                config: config.clone(),
+                index,
+                db,
+                disk,
+                callbacks,
+                gcov_bitvecs: HashMap::new(),
+                result_buf: HashMap::new(),
+                failed_buf: HashMap::new(),
+                stability_tracker: StabilityTracker::default(),
+            };
+            let result = writer.process_results(&status_sender, &receiver);
+            // No further jobs are needed, release the workers waiting for disk space
+            writer.disk.stop();
 
-                let job = receiver.recv();
-                match job {
-                    Ok((bench_id, run_result, gcov_result)) => {
-                        let start = if log::max_level() >= LevelFilter::Debug {
-                            Some(Instant::now())
-                        } else {
-                            None
-                        };
-                        info!("[DB Writer] Received a result (bench_id: {})", bench_id);
-                        debug!(
-                            "[DB Writer] Writing run result to DB (bench_id: {})",
-                            bench_id
-                        );
-                        db.add_run_result(run_result).unwrap();
-                        if let Some(gcov_result) = gcov_result {
-                            debug!(
-                                "[DB Writer] Enqueing GCOV result for later processing (bench_id: {})",
-                                bench_id
-                            );
-                            res_to_bitvec(
-                                &mut gcov_bitvec,
-                                bench_count.try_into().unwrap(),
-                                bench_id.try_into().unwrap(),
-                                &gcov_result,
-                            );
-                            match result_buf.borrow_mut() {
-                                None => {
-                                    result_buf = Some(gcov_result);
-                                }
-                                Some(r) => {
-                                    merge_gcov(r, gcov_result, MergeKind::SUM);
-                                }
-                            }
-                        }
-                        bench_counter += 1;
-                        rem_counter -= 1;
-                        if log::max_level() >= LevelFilter::Debug {
-                            debug!(
-                                "[DB Writer] Processed result in {}ms (bench_id: {})",
-                                start.unwrap().elapsed().as_millis(),
-                                bench_id
-                            );
-                        }
+            info!("[DB Writer] Cleaning up.");
+            match result {
+                Ok(bench_counter) => match writer.flush() {
+                    Ok(_) => status_sender
+                        .send(ProcessingStatusMessage::BenchesDone(bench_counter))
+                        .expect("Could not update bench status"),
+                    Err(e) => {
+                        error!("[DB Writer] Could not write results: {}", e);
+                        status_sender
+                            .send(ProcessingStatusMessage::Aborted(e))
+                            .expect("Could not update bench status");
                     }
-                    Err(_) => {
-                        warn!("[DB Writer] Disconnected; shutting down.");
-                        break;
+                },
+                Err(e) => {
+                    // Keep everything collected so far before giving up
+                    error!("[DB Writer] Aborting run: {}", e);
+                    if let Err(mark_error) = writer.db.mark_incomplete(&e) {
+                        error!(
+                            "[DB Writer] Could not mark the run as incomplete: {}",
+                            mark_error
+                        );
                     }
-                }
-
-                if bench_counter >= max_bench_aggregate {
-                    // Only wake main thread every 20 benchmarks
-                    info!("[DB Writer] Writing merged GCOV results to DB");
-                    let start = if log::max_level() >= LevelFilter::Debug {
-                        Some(Instant::now())
-                    } else {
-                        None
-                    };
-                    match result_buf {
-                        Some(r) => db
-                            .add_gcov_measurement(r)
-                            .expect("Could not add gcov measurement"),
-                        None => {
-                            if is_coverage {
-                                error!("No results to write out")
-                            }
-                        }
-                    };
-
-                    if log::max_level() >= LevelFilter::Debug {
-                        debug!(
-                            "[DB Writer] Inserted merged GCOV result in {}ms",
-                            start.unwrap().elapsed().as_millis()
+                    if let Err(flush_error) = writer.flush() {
+                        error!(
+                            "[DB Writer] Could not write partial results: {}",
+                            flush_error
                         );
                     }
-
                     status_sender
-                        .send(ProcessingStatusMessage::BenchesDone(bench_counter))
+                        .send(ProcessingStatusMessage::Aborted(e))
                         .expect("Could not update bench status");
-
-                    result_buf = None;
-                    bench_counter = 0;
                 }
             }
 
-            info!("[DB Writer] Cleaning up.");
-
-            // Only write to disk when DB is stored in memory
-            if is_coverage {
-                if let Some(r) = result_buf {
-                    db.add_gcov_measurement(r)
-                        .expect("Could not add gcov measurement");
-                };
-                db.add_gcov_bitvecs(gcov_bitvec)
-                    .expect("Could not insert gcov bitvecs");
-                status_sender
-                    .send(ProcessingStatusMessage::BenchesDone(bench_counter))
-                    .expect("Could not update bench status");
-                db.write_to_disk()
-                    .expect("Issue while writing result db to disk");
-            } else {
-                status_sender
-                    .send(ProcessingStatusMessage::BenchesDone(bench_counter))
-                    .expect("Could not update bench status");
-            }
-
             info!("[DB Writer] Terminated.");
         });
 
@@ -326,11 +365,263 @@ impl Worker {
 
     pub(super) fn join(&mut self) {
         // Join thread and replace with None
-        if let Some(_) = self._thread {
-            mem::replace(&mut self._thread, None)
+        if self._thread.is_some() {
+            self._thread
+                .take()
                 .unwrap()
                 .join()
                 .expect("Error during worker process join in runner...");
         }
     }
 }
+
+/// State of the DB writer, kept outside of the message loop so collected results can be
+/// flushed even if the run is aborted
+struct ResultWriter {
+    config: Arc<RunConfig>,
+    index: Arc<CoverageIndex>,
+    db: DbWriter,
+    disk: Arc<DiskGuard>,
+    callbacks: Callbacks,
+    /// Bitvectors storing the indicator matrix, one per configuration
+    gcov_bitvecs: HashMap<u64, GcovBitvec>,
+    /// Usage counts of the gcov results not yet written to the DB, per configuration
+    result_buf: HashMap<u64, GcovCounts>,
+    /// Same for the coverage collected from failed runs
+    failed_buf: HashMap<u64, GcovCounts>,
+    /// Used functions of all runs of the benchmarks sampled for nondeterminism detection
+    stability_tracker: StabilityTracker,
+}
+
+impl ResultWriter {
+    /// Receives and stores the results of all jobs, returns the number of results not yet
+    /// reported to the main thread
+    fn process_results(
+        &mut self,
+        status_sender: &channel::Sender<ProcessingStatusMessage>,
+        receiver: &channel::Receiver<ProcessingQueueMessage>,
+    ) -> RunnerResult<u64> {
+        let config = self.config.clone();
+        let is_coverage = config.is_coverage();
+        let stability = config.coverage().and_then(|c| c.stability.clone());
+        if let Some(screening) = config.screening_config() {
+            let _span = trace::span("screening");
+            let runs = screening::screen(
+                &config,
+                &screening,
+                &self.db.get_all_benchmarks()?,
+                &self.db.get_all_configurations()?,
+            )?;
+            self.db.add_screening_results(&runs)?;
+        }
+        let (bench_count, job_count, sampled): (u64, u64, HashSet<u64>) = {
+            let benchmarks = self.db.get_all_benchmarks()?;
+            let configurations = self.db.get_all_configurations()?;
+            let count = benchmarks.len();
+            let job_count = super::jobs(&config, &benchmarks, &configurations).len();
+            let sampled = match &stability {
+                Some(stability) => stability::sample_benchmarks(&benchmarks, stability),
+                None => HashSet::new(),
+            };
+            status_sender
+                .send(ProcessingStatusMessage::Benchmarks(
+                    benchmarks,
+                    configurations,
+                ))
+                .map_err(|_| Error::Disconnected)?;
+            (count as u64, job_count as u64, sampled)
+        };
+
+        // Batch process 100 results at once to decrease load on DB
+        let max_bench_aggregate: u64 = min(100, job_count);
+        let mut bench_counter: u64 = 0;
+        let mut rem_counter: u64 = job_count;
+
+        loop {
+            if rem_counter == 0 {
+                info!("[DB Writer] Processed all benchmarks, breaking out of message loop");
+                break;
+            }
+
+            let (job, mut attempts, gcov_result) = {
+                let _span = trace::span("wait for result");
+                loop {
+                    self.check_disk_space()?;
+                    match receiver.recv_timeout(disk::CHECK_INTERVAL) {
+                        Ok(result) => break result,
+                        Err(channel::RecvTimeoutError::Timeout) => continue,
+                        Err(channel::RecvTimeoutError::Disconnected) => {
+                            return Err(Error::Disconnected)
+                        }
+                    }
+                }
+            };
+            let bench_id = job.benchmark.id;
+            let start = if log::max_level() >= LevelFilter::Debug {
+                Some(Instant::now())
+            } else {
+                None
+            };
+            info!("[DB Writer] Received a result (bench_id: {})", bench_id);
+            debug!(
+                "[DB Writer] Writing run result to DB (bench_id: {})",
+                bench_id
+            );
+            let run_result = attempts.pop().unwrap();
+            let failed_coverage = run_result.failed_coverage;
+            if run_result.flaky {
+                warn!(
+                    "[DB Writer] Attempts of benchmark run disagree, marking it as flaky (bench_id: {}, config_id: {})",
+                    bench_id, run_result.config_id
+                );
+            }
+            if let Some(on_run) = self.callbacks.on_run.as_mut() {
+                on_run(&job, &run_result);
+            }
+            if sampled.contains(&bench_id) && !failed_coverage {
+                if let Some(gcov_result) = &gcov_result {
+                    self.stability_tracker.add(&job, gcov_result);
+                }
+            }
+            if job.repetition > 0 {
+                // Repeated runs only serve the nondeterminism detection
+                debug!(
+                    "[DB Writer] Recorded repeated run {} (bench_id: {})",
+                    job.repetition, bench_id
+                );
+            } else {
+                self.db.add_run_attempts(&attempts, &run_result)?;
+                self.db.add_run_result(run_result)?;
+            }
+            if let Some(gcov_result) = gcov_result.filter(|_| job.repetition == 0) {
+                if let Some(on_gcov) = self.callbacks.on_gcov.as_mut() {
+                    on_gcov(&job, &self.index, &gcov_result);
+                }
+                debug!(
+                    "[DB Writer] Enqueing GCOV result for later processing (bench_id: {})",
+                    bench_id
+                );
+                let _span = trace::span("bitvec update").arg("bench_id", bench_id);
+                // Failed runs only count towards the failed usage counts, the bitvecs hold the
+                // benchmarks that reliably use a function
+                if !failed_coverage {
+                    res_to_bitvec(
+                        self.gcov_bitvecs.entry(job.configuration.id).or_default(),
+                        bench_count.try_into().unwrap(),
+                        bench_id.try_into().unwrap(),
+                        &gcov_result,
+                    );
+                }
+                let buf = if failed_coverage {
+                    &mut self.failed_buf
+                } else {
+                    &mut self.result_buf
+                };
+                buf.entry(job.configuration.id)
+                    .or_default()
+                    .add(&self.index.read(), &gcov_result);
+            }
+            bench_counter += 1;
+            rem_counter -= 1;
+            if log::max_level() >= LevelFilter::Debug {
+                debug!(
+                    "[DB Writer] Processed result in {}ms (bench_id: {})",
+                    start.unwrap().elapsed().as_millis(),
+                    bench_id
+                );
+            }
+
+            if bench_counter >= max_bench_aggregate {
+                // Only wake main thread every 20 benchmarks
+                info!("[DB Writer] Writing merged GCOV results to DB");
+                let _span = trace::span("DB batch insert").arg("results", bench_counter);
+                let start = if log::max_level() >= LevelFilter::Debug {
+                    Some(Instant::now())
+                } else {
+                    None
+                };
+                if self.result_buf.is_empty() && is_coverage {
+                    error!("No results to write out")
+                }
+                self.write_measurements()?;
+
+                if log::max_level() >= LevelFilter::Debug {
+                    debug!(
+                        "[DB Writer] Inserted merged GCOV result in {}ms",
+                        start.unwrap().elapsed().as_millis()
+                    );
+                }
+
+                status_sender
+                    .send(ProcessingStatusMessage::BenchesDone(bench_counter))
+                    .map_err(|_| Error::Disconnected)?;
+
+                bench_counter = 0;
+            }
+        }
+
+        Ok(bench_counter)
+    }
+
+    /// Stops the run if a filesystem is about to run full, while enough space is left to
+    /// write the results collected so far
+    fn check_disk_space(&mut self) -> RunnerResult<()> {
+        if !self.disk.check_due() {
+            return Ok(());
+        }
+        self.disk.set_pending_bytes(self.db.memory_size()?);
+        match self.disk.exhausted() {
+            Some(free) => Err(free.into_error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the buffered usage counts to the DB
+    fn write_measurements(&mut self) -> RunnerResult<()> {
+        for (config_id, r) in std::mem::take(&mut self.result_buf) {
+            self.db
+                .add_gcov_measurement(&self.index, config_id, r, false)?;
+        }
+        for (config_id, r) in std::mem::take(&mut self.failed_buf) {
+            self.db
+                .add_gcov_measurement(&self.index, config_id, r, true)?;
+        }
+        Ok(())
+    }
+
+    /// Writes all buffered results, and the in memory DB of a coverage run to disk
+    fn flush(&mut self) -> RunnerResult<()> {
+        // Only write to disk when DB is stored in memory
+        if !self.config.is_coverage() {
+            return Ok(());
+        }
+        {
+            let _span = trace::span("DB batch insert");
+            self.write_measurements()?;
+        }
+        {
+            let _span = trace::span("DB bitvec insert");
+            self.db
+                .add_gcov_bitvecs(&self.index, std::mem::take(&mut self.gcov_bitvecs))?;
+        }
+        let union = {
+            let _span = trace::span("block union");
+            gcov::block_union(&self.config, &self.index).unwrap_or_else(|e| {
+                error!("[DB Writer] Could not compute the block union: {}", e);
+                None
+            })
+        };
+        self.db.add_function_totals(&self.index, union)?;
+        if self
+            .config
+            .coverage()
+            .is_some_and(|c| c.stability.is_some())
+        {
+            let tracker = std::mem::take(&mut self.stability_tracker);
+            self.db
+                .add_stability_results(&self.index, tracker.results())?;
+        }
+        let _span = trace::span("write to disk");
+        self.db.write_to_disk()
+    }
+}
@@ -0,0 +1,149 @@
+//! Timeline of a run in the Chrome trace-event format, which can be loaded into
+//! `chrome://tracing` or Perfetto to see where the pipeline stalls. Every thread records
+//! complete (`"ph": "X"`) events of its spans, tracing is a no-op until [`init`] is called.
+
+use serde_json::{json, Map, Value};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: Cell<u64> = const { Cell::new(0) };
+}
+
+struct Tracer {
+    start: Instant,
+    pid: u32,
+    /// `None` once the trace was finished
+    out: Mutex<Option<BufWriter<File>>>,
+    written: AtomicU64,
+}
+
+impl Tracer {
+    fn write(&self, event: Value) {
+        let mut out = self.out.lock().unwrap();
+        let Some(out) = out.as_mut() else {
+            return;
+        };
+        let separator = if self.written.fetch_add(1, Ordering::Relaxed) == 0 {
+            ""
+        } else {
+            ",\n"
+        };
+        // A lost event is not worth aborting the run for
+        let _ = write!(out, "{}{}", separator, event);
+    }
+
+    fn micros(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.start).as_micros() as u64
+    }
+}
+
+fn tid() -> u64 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::Relaxed));
+        }
+        tid.get()
+    })
+}
+
+/// Starts recording all spans into the trace file at `path`. Only the first call has an
+/// effect, a run has a single trace.
+pub fn init(path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(b"[\n")?;
+    let _ = TRACER.set(Tracer {
+        start: Instant::now(),
+        pid: std::process::id(),
+        out: Mutex::new(Some(out)),
+        written: AtomicU64::new(0),
+    });
+    Ok(())
+}
+
+/// Whether spans are recorded
+pub fn enabled() -> bool {
+    TRACER.get().is_some()
+}
+
+/// Closes the trace file, spans ending afterwards are dropped. A trace that was never
+/// finished (e.g. of an aborted run) lacks the closing bracket, which trace viewers accept.
+pub fn finish() -> io::Result<()> {
+    let Some(tracer) = TRACER.get() else {
+        return Ok(());
+    };
+    match tracer.out.lock().unwrap().take() {
+        Some(mut out) => {
+            out.write_all(b"\n]\n")?;
+            out.flush()
+        }
+        None => Ok(()),
+    }
+}
+
+/// Names the timeline of the calling thread
+pub fn thread_name(name: &str) {
+    if let Some(tracer) = TRACER.get() {
+        tracer.write(json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": tracer.pid,
+            "tid": tid(),
+            "args": { "name": name },
+        }));
+    }
+}
+
+/// Starts a span on the calling thread, it ends when the returned guard is dropped
+pub fn span(name: &'static str) -> Span {
+    Span {
+        name,
+        start: enabled().then(Instant::now),
+        args: Map::new(),
+    }
+}
+
+/// A running span, recorded as one complete event once dropped
+#[must_use = "the span ends when dropped"]
+pub struct Span {
+    name: &'static str,
+    /// `None` if tracing is disabled
+    start: Option<Instant>,
+    args: Map<String, Value>,
+}
+
+impl Span {
+    /// Attaches an argument shown with the event in the trace viewer
+    pub fn arg(mut self, key: &str, value: impl Into<Value>) -> Self {
+        if self.start.is_some() {
+            self.args.insert(key.to_string(), value.into());
+        }
+        self
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let (Some(tracer), Some(start)) = (TRACER.get(), self.start) else {
+            return;
+        };
+        let end = Instant::now();
+        tracer.write(json!({
+            "name": self.name,
+            "ph": "X",
+            "pid": tracer.pid,
+            "tid": tid(),
+            "ts": tracer.micros(start),
+            "dur": end.saturating_duration_since(start).as_micros() as u64,
+            "args": std::mem::take(&mut self.args),
+        }));
+    }
+}
@@ -1,4 +1,9 @@
+use crate::error::{Error, ERROR_EXIT_CODE};
+
+use serde::Serialize;
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
 pub type ResultT<T> = Result<T, Box<dyn std::error::Error>>;
 
@@ -49,31 +54,140 @@ pub struct LineBenchUsage {
 #[derive(Debug, Clone)]
 pub struct Benchmark {
     pub id: u64,
+    /// Logical path, for compressed benchmarks the path they would have if decompressed
     pub path: PathBuf,
     pub prefix: Option<PathBuf>,
+    /// Compressed file or tar archive the benchmark is stored in
+    pub archive: Option<PathBuf>,
+    /// Path of the benchmark inside of a tar archive
+    pub member: Option<String>,
+    /// Ids of the configurations under which the benchmark did not pass the pre-screening,
+    /// it is not run under them
+    pub screened_out: Vec<u64>,
+}
+
+/// A single unit of work: one benchmark run under one configuration
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub benchmark: Benchmark,
+    pub configuration: Configuration,
+    /// 0 for the regular run, repeated runs of the nondeterminism detection count up from 1
+    pub repetition: u32,
+    /// GCOV prefix of the worker running the job, set by the worker if prefixes are kept per
+    /// worker
+    pub worker_prefix: Option<PathBuf>,
+}
+
+impl Job {
+    /// GCOV prefix of this job, every configuration (and repetition) gets its own
+    /// subdirectory of the benchmark prefix as they may run at once
+    pub fn prefix(&self) -> Option<PathBuf> {
+        if self.worker_prefix.is_some() {
+            return self.worker_prefix.clone();
+        }
+        self.benchmark.prefix.as_ref().map(|p| {
+            if self.repetition == 0 {
+                p.join(self.configuration.id.to_string())
+            } else {
+                p.join(format!("{}-{}", self.configuration.id, self.repetition))
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Configuration {
+    pub id: u64,
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            id: 1,
+            name: "default".to_string(),
+            args: vec![],
+        }
+    }
+}
+
+impl FromStr for Configuration {
+    type Err = String;
+
+    /// Parses a configuration from "NAME=ARGS", the id is assigned once all configurations
+    /// of a run are known
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME=ARGS, got '{}'", s))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Configuration name must not be empty".to_string());
+        }
+        let args = shellwords::split(args).map_err(|e| {
+            format!(
+                "Could not parse arguments of configuration '{}': {}",
+                name, e
+            )
+        })?;
+
+        Ok(Configuration {
+            id: 0,
+            name: name.to_string(),
+            args,
+        })
+    }
+}
+
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<&str> = self.args.iter().map(String::as_str).collect();
+        write!(f, "{}={}", self.name, shellwords::join(&args))
+    }
 }
 
 pub struct BenchmarkRun {
     pub bench_id: u64,
+    pub config_id: u64,
     pub time_ms: u64,
     pub exit_code: i32,
+    /// Signal that terminated the run, `exit_code` is the signal number in that case
+    pub signal: Option<i32>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    /// Number of this attempt, starting at 1
+    pub attempt: u32,
+    /// Set on the final attempt if the outcomes of earlier attempts disagree with it
+    pub flaky: bool,
+    /// Reason of a failure not caused by the benchmark itself, `exit_code` is
+    /// `ERROR_EXIT_CODE` in that case
+    pub error: Option<String>,
+    /// The runner stopped the run as it exceeded the timeout
+    pub timed_out: bool,
+    /// Coverage of this run was collected even though it did not succeed
+    pub failed_coverage: bool,
+    /// CPU the run was pinned to
+    pub cpu: Option<usize>,
 }
 
-#[derive(Debug, Clone)]
-pub struct GcovFuncResult {
-    pub name: String,
-    pub start: FilePosition,
-    pub end: FilePosition,
-    pub usage: u32,
+impl BenchmarkRun {
+    /// Error outcome of a job that could not be run or measured
+    pub fn failed(job: &Job, attempt: u32, error: &Error) -> Self {
+        BenchmarkRun {
+            bench_id: job.benchmark.id,
+            config_id: job.configuration.id,
+            time_ms: 0,
+            exit_code: ERROR_EXIT_CODE,
+            signal: None,
+            stdout: Some(String::new()),
+            stderr: Some(String::new()),
+            attempt,
+            flaky: false,
+            error: Some(error.to_string()),
+            timed_out: false,
+            failed_coverage: false,
+            cpu: None,
+        }
+    }
 }
-
-#[derive(Debug, Clone)]
-pub struct GcovLineResult {
-    pub line_no: u32,
-    pub usage: u32,
-}
-
-#[derive(Debug, Clone)]
-pub struct GcovBranchResult {}
@@ -71,14 +71,10 @@ evaluate_solution_file(std::string &filename) {
 }
 
 std::vector<bool> get_evaluation_data(std::string &db_file,
-                                      std::string &table_name) {
+                                      std::string &table_name, int config_id) {
   std::vector<bool> eval_result;
-  sqlite3 *db;
-  int rc = sqlite3_open(db_file.c_str(), &db);
-  if (rc) {
-    std::cerr << "Can't open database: " << sqlite3_errmsg(db) << std::endl;
-    exit(1);
-  }
+  sqlite3 *db = open_report_db(db_file);
+  int rc;
 
   sqlite3_stmt *stmt;
   std::string query =
@@ -86,7 +82,8 @@ std::vector<bool> get_evaluation_data(std::string &db_file,
       " from result_benchmarks as r"
       " join \"" +
       table_name +
-      "\" as e ON e.bench_id = r.bench_id"
+      "\" as e ON e.bench_id = r.bench_id AND e.config_id = r.config_id"
+      " where r.config_id = ?"
       " order by r.bench_id;";
   std::cout << query << std::endl;
   rc = sqlite3_prepare_v2(db, query.c_str(), -1, &stmt, NULL);
@@ -95,6 +92,7 @@ std::vector<bool> get_evaluation_data(std::string &db_file,
     sqlite3_close(db);
     exit(1);
   }
+  sqlite3_bind_int(stmt, 1, config_id);
 
   eval_result.push_back(false);
   for (int i = 1; (rc = sqlite3_step(stmt)) == SQLITE_ROW; i++) {
@@ -113,9 +111,10 @@ int main(int argc, char *argv[]) {
   std::string db_file = "./reports/report.sqlite";
   std::optional<std::string> eval_table = {};
   bool exec_cvc5 = false;
+  int config_id = 1;
 
   int opt;
-  while ((opt = getopt(argc, argv, "d:e:c")) != -1) {
+  while ((opt = getopt(argc, argv, "d:e:ck:")) != -1) {
     switch (opt) {
     case 'd':
       db_file = optarg;
@@ -126,12 +125,16 @@ int main(int argc, char *argv[]) {
     case 'c':
       exec_cvc5 = true;
       break;
+    case 'k':
+      config_id = std::stoi(optarg);
+      break;
     case 'h':
     case '?':
     default:
       std::cout << "Help/Usage Example\n"
                 << argv[0]
-                << " -d <DB_PATH> -e <EVAL_TABLE_NAME> [-c] <SOL-FILE> "
+                << " -d <DB_PATH> -e <EVAL_TABLE_NAME> [-c] [-k <CONFIG_ID>] "
+                   "<SOL-FILE> "
                    "[<ADD-SOL-FILES>...]"
                 << std::endl;
       exit(0);
@@ -145,10 +148,10 @@ int main(int argc, char *argv[]) {
   std::vector<std::vector<bool>> func_usages;
   std::optional<std::vector<bool>> eval_data = {};
   get_function_stats_from_db(db_file, bench_ids, func_ids, func_lens,
-                             func_usages, {});
+                             func_usages, {}, config_id);
   if (eval_table.has_value()) {
     std::cout << " |>> Extracting evaluation data from DB" << std::endl;
-    eval_data = get_evaluation_data(db_file, eval_table.value());
+    eval_data = get_evaluation_data(db_file, eval_table.value(), config_id);
   }
 
   for (int i = optind; i < argc; i++) {
@@ -191,7 +194,8 @@ int main(int argc, char *argv[]) {
     std::cout << std::endl
               << "Overview of working benchmarks per theory:" << std::endl;
 
-    std::vector<std::string> bench_names = get_bench_stats_from_db(db_file);
+    std::vector<std::string> bench_names =
+        get_bench_stats_from_db(db_file, config_id);
     std::map<std::string, std::tuple<int, int>> rel_theory_working;
     for (int j = 0; j < bench_ids.size(); j++) {
       int bench_id = bench_ids[j];
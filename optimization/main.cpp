@@ -20,9 +20,11 @@ int main(int argc, char *argv[]) {
   std::string license_file = "./optimization/gurobi.lic";
   // Scales the input size down if set
   std::optional<float> scaler = {};
+  // Configuration (see configurations table) whose runs are optimized over
+  int config_id = 1;
 
   int opt;
-  while ((opt = getopt(argc, argv, "l:d:s:")) != -1) {
+  while ((opt = getopt(argc, argv, "l:d:s:k:")) != -1) {
     switch (opt) {
     case 'l':
       license_file = optarg;
@@ -33,13 +35,16 @@ int main(int argc, char *argv[]) {
     case 's':
       scaler = std::stof(optarg);
       break;
+    case 'k':
+      config_id = std::stoi(optarg);
+      break;
     case 'h':
     case '?':
     default:
       std::cout << "Help/Usage Example" << std::endl
                 << argv[0]
                 << " -s <SAMPLE_FACTOR> -d <DB_PATH> -l <GUROBI_LICENSE_FILE> "
-                   "<P-VALUE> "
+                   "-k <CONFIG_ID> <P-VALUE> "
                    "[<ADD-P-VALUES>...]"
                 << std::endl;
       exit(0);
@@ -60,7 +65,7 @@ int main(int argc, char *argv[]) {
   std::vector<int> func_lens;
   std::vector<std::vector<bool>> func_usages;
   get_function_stats_from_db(db_file, bench_ids, func_ids, func_lens,
-                             func_usages, scaler);
+                             func_usages, scaler, config_id);
 
   // long pages = sysconf(_SC_AVPHYS_PAGES);
   // long page_size = sysconf(_SC_PAGE_SIZE);
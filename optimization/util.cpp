@@ -18,16 +18,47 @@
 #include <unistd.h>
 #include <vector>
 
-void store_used_functions_to_db(std::string db_file,
-                                std::vector<bool> &func_state,
-                                std::vector<int> &func_ids, float p) {
+sqlite3 *open_report_db(const std::string &db_file) {
   sqlite3 *db;
-  int rc = sqlite3_open(db_file.c_str(), &db);
-  if (rc) {
+  if (sqlite3_open(db_file.c_str(), &db)) {
     std::cerr << "Can't open database: " << sqlite3_errmsg(db) << std::endl;
     exit(1);
   }
 
+  // Reports written before versioning count as version 0
+  int version = 0;
+  sqlite3_stmt *stmt;
+  if (sqlite3_prepare_v2(
+          db, "SELECT value FROM config WHERE key = 'schema_version'", -1,
+          &stmt, NULL) == SQLITE_OK &&
+      sqlite3_step(stmt) == SQLITE_ROW) {
+    version = sqlite3_column_int(stmt, 0);
+  }
+  sqlite3_finalize(stmt);
+
+  if (version < SCHEMA_VERSION) {
+    std::cerr << "Report DB " << db_file << " has schema version " << version
+              << ", upgrade it to version " << SCHEMA_VERSION
+              << " via 'gen_coverage " << db_file << " migrate'" << std::endl;
+    sqlite3_close(db);
+    exit(1);
+  }
+  if (version > SCHEMA_VERSION) {
+    std::cerr << "Report DB " << db_file << " has schema version " << version
+              << ", newer than the supported version " << SCHEMA_VERSION
+              << ", update the optimizer" << std::endl;
+    sqlite3_close(db);
+    exit(1);
+  }
+  return db;
+}
+
+void store_used_functions_to_db(std::string db_file,
+                                std::vector<bool> &func_state,
+                                std::vector<int> &func_ids, float p) {
+  sqlite3 *db = open_report_db(db_file);
+  int rc;
+
   // Create the table name using 'p', replacing any '.' with '_'
   std::ostringstream table_name_stream;
   table_name_stream << "optimization_result_p" << std::fixed
@@ -144,25 +175,24 @@ void store_used_functions_to_db(std::string db_file,
   sqlite3_close(db);
 }
 
-std::vector<std::string> get_bench_stats_from_db(std::string db_file) {
+std::vector<std::string> get_bench_stats_from_db(std::string db_file,
+                                                 int config_id) {
   std::vector<std::string> bench_names;
-  sqlite3 *db;
-  int rc = sqlite3_open(db_file.c_str(), &db);
-  if (rc) {
-    std::cerr << "Can't open database: " << sqlite3_errmsg(db) << std::endl;
-    exit(1);
-  }
+  sqlite3 *db = open_report_db(db_file);
+  int rc;
 
   sqlite3_stmt *stmt;
   const char *query =
       "SELECT b.id, b.path FROM benchmarks AS b JOIN result_benchmarks AS r ON "
-      "r.bench_id = b.id WHERE r.exit_code = 0 ORDER BY b.id";
+      "r.bench_id = b.id WHERE r.exit_code = 0 AND r.config_id = ? "
+      "ORDER BY b.id";
   rc = sqlite3_prepare_v2(db, query, -1, &stmt, NULL);
   if (rc != SQLITE_OK) {
     std::cerr << "Failed to execute query: " << sqlite3_errmsg(db) << std::endl;
     sqlite3_close(db);
     exit(1);
   }
+  sqlite3_bind_int(stmt, 1, config_id);
 
   bench_names.push_back("");
   for (int i = 1; (rc = sqlite3_step(stmt)) == SQLITE_ROW; i++) {
@@ -184,32 +214,32 @@ void get_function_stats_from_db(std::string db_file,
                                 std::vector<int> &func_ids,
                                 std::vector<int> &func_lens,
                                 std::vector<std::vector<bool>> &func_usages,
-                                std::optional<double> scaler) {
-  sqlite3 *db;
-  int rc = sqlite3_open(db_file.c_str(), &db);
-  if (rc) {
-    std::cerr << "Can't open database: " << sqlite3_errmsg(db) << std::endl;
-    exit(1);
-  }
+                                std::optional<double> scaler, int config_id) {
+  sqlite3 *db = open_report_db(db_file);
+  int rc;
 
   sqlite3_stmt *stmt;
   const char *query = "SELECT bench_id FROM result_benchmarks WHERE exit_code "
-                      "= 0 ORDER BY bench_id";
+                      "= 0 AND config_id = ? ORDER BY bench_id";
   rc = sqlite3_prepare_v2(db, query, -1, &stmt, NULL);
   if (rc != SQLITE_OK) {
     std::cerr << "Failed to execute query: " << sqlite3_errmsg(db) << std::endl;
     sqlite3_close(db);
     exit(1);
   }
+  sqlite3_bind_int(stmt, 1, config_id);
 
   while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
     bench_ids.push_back(sqlite3_column_int(stmt, 0));
   }
   sqlite3_finalize(stmt);
 
-  query = "SELECT id, benchmark_usage_count, start_line, end_line, data "
-          " FROM functions"
+  // Reports with line tracking know how many lines belong to each function,
+  // for the others (0 lines) only its start and end line are known
+  query = "SELECT id, benchmark_usage_count, start_line, end_line, data, "
+          "lines FROM functions"
           " JOIN function_bitvecs AS fb ON id = fb.function_id "
+          " WHERE fb.config_id = ?"
           " ORDER BY id";
   rc = sqlite3_prepare_v2(db, query, -1, &stmt, NULL);
   if (rc != SQLITE_OK) {
@@ -217,12 +247,14 @@ void get_function_stats_from_db(std::string db_file,
     sqlite3_close(db);
     exit(1);
   }
+  sqlite3_bind_int(stmt, 1, config_id);
 
   while ((rc = sqlite3_step(stmt)) == SQLITE_ROW) {
     int func_id = sqlite3_column_int(stmt, 0);
     // int usage_count = sqlite3_column_int(stmt, 1);
     int start_line = sqlite3_column_int(stmt, 2);
     int end_line = sqlite3_column_int(stmt, 3);
+    int lines = sqlite3_column_int(stmt, 5);
 
     // Read the BLOB data
     const void *usage_blob = sqlite3_column_blob(stmt, 4);
@@ -264,7 +296,8 @@ void get_function_stats_from_db(std::string db_file,
     }
 
     func_ids.push_back(func_id);
-    func_lens.push_back(end_line - start_line + 1);
+    // Lines are only counted if they were tracked during the data aggregation
+    func_lens.push_back(lines > 0 ? lines : end_line - start_line + 1);
     func_usages.push_back(func_usage);
   }
   sqlite3_finalize(stmt);
@@ -1,5 +1,13 @@
 #include "gurobi_c++.h"
 #include <optional>
+#include <sqlite3.h>
+#include <string>
+
+// Schema version of the report DBs read, has to match the one of gen_coverage
+constexpr int SCHEMA_VERSION = 1;
+
+// Opens the report DB, exiting if it has a different schema version
+sqlite3 *open_report_db(const std::string &db_file);
 
 void store_used_functions_to_db(std::string db_file,
                                 std::vector<bool> &func_state,
@@ -8,8 +16,9 @@ void store_used_functions_to_db(std::string db_file,
 void get_function_stats_from_db(std::string db_file, std::vector<int> &benches,
                                 std::vector<int> &uids, std::vector<int> &len_c,
                                 std::vector<std::vector<bool>> &B,
-                                std::optional<double> scaler);
+                                std::optional<double> scaler, int config_id);
 
-std::vector<std::string> get_bench_stats_from_db(std::string db_file);
+std::vector<std::string> get_bench_stats_from_db(std::string db_file,
+                                                 int config_id);
 
 GRBEnv *get_env_from_license(const std::string &file_path);